@@ -1,6 +1,11 @@
-use std::{collections::HashMap, ops::RangeFrom};
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    ops::RangeFrom,
+};
 
 use crate::{
+    normalize::{free_variables, fresh, substitute},
     parser::{Branch, CodedLiteral, Constructor, Variable},
     Expr, MetaExpr, Program,
 };
@@ -12,7 +17,8 @@ pub fn replace_coded_literals<T: Coder>(
     coder: &mut T,
 ) -> Program<Expr> {
     match program {
-        Program::Let(var, expr, rest) => Program::Let(
+        Program::Let(doc, var, expr, rest) => Program::Let(
+            doc,
             var,
             to_expr(expr, coder),
             Box::new(replace_coded_literals(*rest, coder)),
@@ -21,7 +27,11 @@ pub fn replace_coded_literals<T: Coder>(
     }
 }
 
-fn to_expr<T: Coder>(expr: MetaExpr, coder: &mut T) -> Expr {
+/// The name of the variable that replaces the parts of a program that could not be parsed, see `parse_recovering`.
+/// It can not be written in the source, so the evaluators get stuck on it like on any other unbound variable
+pub(crate) const SYNTAX_ERROR: &str = "(syntax error)";
+
+pub(crate) fn to_expr<T: Coder>(expr: MetaExpr, coder: &mut T) -> Expr {
     match expr {
         MetaExpr::Apply(e1, e2) => {
             let e1 = to_expr(*e1, coder);
@@ -32,6 +42,8 @@ fn to_expr<T: Coder>(expr: MetaExpr, coder: &mut T) -> Expr {
             let e = to_expr(*e, coder);
             Expr::Lambda(x, Box::new(e))
         }
+        MetaExpr::Lambdas(xs, e) => to_expr(nested_lambdas(xs, *e), coder),
+        MetaExpr::Let(x, e1, e2) => let_to_expr(x, *e1, *e2, coder),
         MetaExpr::Case(e, branches) => Expr::Case(
             Box::new(to_expr(*e, coder)),
             branches
@@ -44,10 +56,116 @@ fn to_expr<T: Coder>(expr: MetaExpr, coder: &mut T) -> Expr {
         MetaExpr::Const(c, es) => {
             Expr::Const(c, es.into_iter().map(|e| to_expr(e, coder)).collect())
         }
+        MetaExpr::Nat(n) => Expr::nat(n),
         MetaExpr::Coded(literal) => coder.code_literal(*literal),
+        MetaExpr::Located(span, e) => Expr::Located(span, Box::new(to_expr(*e, coder))),
+        MetaExpr::Error => Expr::Var(Variable(SYNTAX_ERROR.to_string())),
     }
 }
 
+/// `let x = e1 in e2` is `(\x. e2) e1`, where the binders of `e2` do not capture the free variables of `e1`
+fn let_to_expr<T: Coder>(x: Variable, e1: MetaExpr, e2: MetaExpr, coder: &mut T) -> Expr {
+    // In the same order as `local_definition`, so that the coded symbols are numbered the same
+    let e2 = to_expr(e2, coder);
+    let e1 = to_expr(e1, coder);
+    let e2 = avoid_capture(&x, &free_variables(&e1), e2);
+    Expr::Apply(Box::new(Expr::Lambda(x, Box::new(e2))), Box::new(e1))
+}
+
+/// `\x1 ... xn. e` is `\x1. ... \xn. e`
+fn nested_lambdas(xs: Vec<Variable>, e: MetaExpr) -> MetaExpr {
+    xs.into_iter()
+        .rev()
+        .fold(e, |e, x| MetaExpr::Lambda(x, Box::new(e)))
+}
+
+/// `let x = e1 in e2` is `(\x. e2) e1`
+fn local_definition(x: Variable, e1: MetaExpr, e2: MetaExpr) -> MetaExpr {
+    MetaExpr::Apply(Box::new(MetaExpr::Lambda(x, Box::new(e2))), Box::new(e1))
+}
+
+/// Rename the binders in `e` that would capture the `free` variables of the definition of `x`.
+/// `eval` substitutes without renaming, which is only correct for closed programs, but the definition of a local
+/// definition may use variables that are bound outside of it (or not at all), for instance in
+/// `f A() where { f = \y. Pair(y, z); z = C() }` the `z` of `f` is not the `z` of the where clause
+fn avoid_capture(x: &Variable, free: &HashSet<Variable>, mut e: Expr) -> Expr {
+    if free.is_empty() {
+        return e;
+    }
+
+    match &mut e {
+        Expr::Apply(e1, e2) => Expr::Apply(
+            Box::new(avoid_capture(x, free, e1.take())),
+            Box::new(avoid_capture(x, free, e2.take())),
+        ),
+        Expr::Lambda(y, body) => {
+            let (ys, body) = avoid_capture_under(x, free, vec![y.clone()], body.take());
+            Expr::Lambda(ys.into_iter().next().unwrap(), Box::new(body))
+        }
+        Expr::Rec(y, body) => {
+            let (ys, body) = avoid_capture_under(x, free, vec![y.clone()], body.take());
+            Expr::Rec(ys.into_iter().next().unwrap(), Box::new(body))
+        }
+        Expr::Case(scrutinee, branches) => Expr::Case(
+            Box::new(avoid_capture(x, free, scrutinee.take())),
+            mem::take(branches)
+                .into_iter()
+                .map(|Branch(c, ys, body)| {
+                    let (ys, body) = avoid_capture_under(x, free, ys, body);
+                    Branch(c, ys, body)
+                })
+                .collect(),
+        ),
+        Expr::Const(c, es) => Expr::Const(
+            c.clone(),
+            mem::take(es)
+                .into_iter()
+                .map(|e| avoid_capture(x, free, e))
+                .collect(),
+        ),
+        Expr::Located(span, inner) => {
+            Expr::Located(span.clone(), Box::new(avoid_capture(x, free, inner.take())))
+        }
+        Expr::Var(_) | Expr::Nat(_) | Expr::Global(..) => e,
+    }
+}
+
+/// `avoid_capture` for `e` under the binders `ys`, they are only renamed if `x` is used in `e`
+fn avoid_capture_under(
+    x: &Variable,
+    free: &HashSet<Variable>,
+    ys: Vec<Variable>,
+    e: Expr,
+) -> (Vec<Variable>, Expr) {
+    if ys.contains(x) {
+        return (ys, e);
+    }
+    if ys.iter().all(|y| !free.contains(y)) {
+        return (ys, avoid_capture(x, free, e));
+    }
+    let mut avoid = free_variables(&e);
+    if !avoid.contains(x) {
+        return (ys, e);
+    }
+
+    avoid.extend(free.iter().cloned());
+    avoid.extend(ys.iter().cloned());
+    let mut renaming = Vec::new();
+    let ys = ys
+        .into_iter()
+        .map(|y| {
+            if !free.contains(&y) {
+                return y;
+            }
+            let z = fresh(&y, &avoid);
+            avoid.insert(z.clone());
+            renaming.push((y, Expr::Var(z.clone())));
+            z
+        })
+        .collect();
+    (ys, avoid_capture(x, free, substitute(&renaming, e)))
+}
+
 pub trait Coder {
     fn code_constructor(&mut self, c: Constructor) -> Expr;
     fn code_variable(&mut self, c: Variable) -> Expr;
@@ -61,6 +179,22 @@ pub trait Coder {
     // TODO: add decoding methods (that would make it possible to pretty print using the coding literals)
 }
 
+/// The constructors of the trees that `StandardCoder` codes a literal as, with their number of fields
+pub(crate) const CODED_CONSTRUCTORS: [(&str, usize); 11] = [
+    ("Apply", 2),
+    ("Lambda", 2),
+    ("Case", 2),
+    ("Branch", 3),
+    ("Rec", 2),
+    ("Var", 1),
+    ("Const", 2),
+    ("Cons", 2),
+    ("Nil", 0),
+    ("Zero", 0),
+    ("Suc", 1),
+];
+
+#[derive(Clone)]
 pub struct StandardCoder {
     counter: RangeFrom<usize>,
     previous_symbols: HashMap<String, usize>,
@@ -124,6 +258,12 @@ impl Coder for StandardCoder {
                     self.code_literal(CodedLiteral::Expr(*e)),
                 ],
             ),
+            MetaExpr::Lambdas(xs, e) => {
+                self.code_literal(CodedLiteral::Expr(nested_lambdas(xs, *e)))
+            }
+            MetaExpr::Let(x, e1, e2) => {
+                self.code_literal(CodedLiteral::Expr(local_definition(x, *e1, *e2)))
+            }
             MetaExpr::Case(e, branches) => {
                 let branches: Vec<_> = branches
                     .into_iter()
@@ -169,16 +309,27 @@ impl Coder for StandardCoder {
                     vec![self.code_constructor(c), self.code_list(es.into_iter())],
                 )
             }
+            MetaExpr::Nat(n) => {
+                // The parser rejects the numbers in coded literals that are too large to be coded
+                let n = usize::try_from(n).expect("the number is too large to code");
+                (0..n).fold(
+                    self.code_literal(CodedLiteral::Expr(MetaExpr::Const("Zero".into(), vec![]))),
+                    |e, _| {
+                        let suc = self.code_constructor("Suc".into());
+                        Expr::Const("Const".into(), vec![suc, self.code_list([e].into_iter())])
+                    },
+                )
+            }
             MetaExpr::Coded(_) => panic!("Nested coded literals are not supported"),
+            // The locations inside of a coded literal are not kept, the whole literal is located instead
+            MetaExpr::Located(_, e) => self.code_literal(CodedLiteral::Expr(*e)),
+            // Not a coded variable, so that the evaluation of the coded literal gets stuck
+            MetaExpr::Error => Expr::Var(Variable(SYNTAX_ERROR.to_string())),
         }
     }
 
     fn code_natural(&self, n: usize) -> Expr {
-        if n == 0 {
-            Expr::Const("Zero".into(), vec![])
-        } else {
-            Expr::Const("Suc".into(), vec![self.code_natural(n - 1)])
-        }
+        Expr::nat(n)
     }
 
     fn code_list<I>(&mut self, mut es: I) -> Expr
@@ -0,0 +1,227 @@
+/// Data declarations, `data Nat = Zero() | Suc(Nat);`, and the checks of the constructors of a program against them.
+/// Chi is untyped, so the declarations are optional and only used for diagnostics. Once a program declares a data type,
+/// every constructor it uses must be declared (the constructors of numbers, lists and booleans are always known) with
+/// the declared number of fields, and every case expression must have exactly one branch for each constructor of its data type.
+/// The types of the fields are not checked, they only document the declaration
+use chumsky::{error::Rich, span::SimpleSpan};
+
+use crate::{lexer::Token, parser::Constructor};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct DataType {
+    pub name: String,
+    /// The constructors, with their number of fields
+    pub constructors: Vec<(Constructor, usize)>,
+}
+
+impl DataType {
+    fn new(name: &str, constructors: &[(&str, usize)]) -> Self {
+        Self {
+            name: name.to_string(),
+            constructors: constructors
+                .iter()
+                .map(|(c, arity)| (Constructor::from(*c), *arity))
+                .collect(),
+        }
+    }
+
+    fn contains(&self, c: &Constructor) -> bool {
+        self.constructors.iter().any(|(d, _)| d == c)
+    }
+}
+
+/// The data types that are known, the built-in ones and the declared ones.
+/// A catch-all pattern is expanded to the constructors of the data type that are not matched by the other branches,
+/// if the data type is not known it is expanded to the other constructors of the program instead
+#[derive(Debug, Clone)]
+pub(crate) struct Constructors {
+    builtin: Vec<DataType>,
+    declared: Vec<DataType>,
+}
+
+impl Default for Constructors {
+    fn default() -> Self {
+        Self {
+            builtin: vec![
+                DataType::new("Nat", &[("Zero", 0), ("Suc", 1)]),
+                DataType::new("List", &[("Nil", 0), ("Cons", 2)]),
+                DataType::new("Bool", &[("False", 0), ("True", 0)]),
+            ],
+            declared: Vec::new(),
+        }
+    }
+}
+
+impl Constructors {
+    /// The data type of `c`, if it is known.
+    /// A built-in data type is replaced by a declared one that uses any of its constructors
+    pub fn data_type(&self, c: &Constructor) -> Option<&DataType> {
+        self.declared
+            .iter()
+            .find(|data_type| data_type.contains(c))
+            .or_else(|| {
+                self.builtin.iter().find(|data_type| {
+                    data_type.contains(c)
+                        && !data_type
+                            .constructors
+                            .iter()
+                            .any(|(d, _)| self.declared.iter().any(|t| t.contains(d)))
+                })
+            })
+    }
+
+    /// Add a declared data type, the errors are about names that are already declared
+    pub fn declare<'a>(
+        &mut self,
+        data_type: DataType,
+        span: SimpleSpan,
+    ) -> Vec<Rich<'a, Token<'a>>> {
+        let mut errors = Vec::new();
+        if self.declared.iter().any(|t| t.name == data_type.name) {
+            errors.push(Rich::custom(
+                span,
+                format!("The data type {} is declared twice", data_type.name),
+            ));
+        }
+        for (i, (c, _)) in data_type.constructors.iter().enumerate() {
+            let earlier = self
+                .declared
+                .iter()
+                .find(|t| t.contains(c))
+                .map(|t| t.name.as_str())
+                .or_else(|| {
+                    data_type.constructors[..i]
+                        .iter()
+                        .any(|(d, _)| d == c)
+                        .then_some(data_type.name.as_str())
+                });
+            if let Some(name) = earlier {
+                errors.push(Rich::custom(
+                    span,
+                    format!("{c} is already a constructor of {name}"),
+                ));
+            }
+        }
+        self.declared.push(data_type);
+        errors
+    }
+
+    /// Add a data type that is declared by an imported file, unless it is already declared by another import
+    pub fn import<'a>(
+        &mut self,
+        data_type: &DataType,
+        span: SimpleSpan,
+    ) -> Vec<Rich<'a, Token<'a>>> {
+        if self.declared.contains(data_type) {
+            return Vec::new();
+        }
+        self.declare(data_type.clone(), span)
+    }
+
+    /// The declared data types, in order
+    pub fn declarations(&self) -> &[DataType] {
+        &self.declared
+    }
+
+    /// The number of declared data types, the checks are only done if there is at least one
+    pub fn declared(&self) -> usize {
+        self.declared.len()
+    }
+
+    /// Forget the data types that were declared after the first `n`
+    pub fn truncate(&mut self, n: usize) {
+        self.declared.truncate(n);
+    }
+}
+
+/// A use of constructors that is checked against the declarations
+#[derive(Debug, Clone)]
+pub(crate) enum Use {
+    /// A constructor with a number of arguments, in an expression or a pattern
+    Const(Constructor, usize, SimpleSpan),
+    /// The constructors of the branches of a case expression, with the location of their patterns.
+    /// For nested patterns, the constructors in a position of the patterns that is matched by a plain `case`
+    Case {
+        branches: Vec<(Constructor, SimpleSpan)>,
+        span: SimpleSpan,
+        nested: bool,
+    },
+}
+
+fn fields(n: usize) -> String {
+    match n {
+        1 => "1 field".to_string(),
+        _ => format!("{n} fields"),
+    }
+}
+
+/// The errors of the uses of constructors, there are none if no data types are declared
+pub(crate) fn check<'a>(constructors: &Constructors, uses: &[Use]) -> Vec<Rich<'a, Token<'a>>> {
+    if constructors.declared() == 0 {
+        return Vec::new();
+    }
+
+    let mut errors = Vec::new();
+    for use_ in uses {
+        match use_ {
+            Use::Const(c, n, span) => match constructors.data_type(c) {
+                None => errors.push(Rich::custom(
+                    *span,
+                    format!("{c} is not a constructor of a declared data type"),
+                )),
+                Some(data_type) => {
+                    let (_, arity) = data_type.constructors.iter().find(|(d, _)| d == c).unwrap();
+                    if arity != n {
+                        errors.push(Rich::custom(
+                            *span,
+                            format!("{c} has {}, but is used with {n}", fields(*arity)),
+                        ));
+                    }
+                }
+            },
+            Use::Case {
+                branches,
+                span,
+                nested,
+            } => {
+                // Unknown constructors are reported by their `Use::Const`
+                let Some(data_type) = branches.iter().find_map(|(c, _)| constructors.data_type(c))
+                else {
+                    continue;
+                };
+
+                for (i, (c, span)) in branches.iter().enumerate() {
+                    if branches[..i].iter().any(|(d, _)| d == c) {
+                        errors.push(Rich::custom(*span, format!("{c} already has a branch")));
+                    } else if let Some(other) = constructors
+                        .data_type(c)
+                        .filter(|other| other.name != data_type.name)
+                    {
+                        errors.push(Rich::custom(
+                            *span,
+                            format!(
+                                "{c} is a constructor of {}, not of {}",
+                                other.name, data_type.name
+                            ),
+                        ));
+                    }
+                }
+
+                let missing: Vec<String> = data_type
+                    .constructors
+                    .iter()
+                    .filter(|(c, _)| !branches.iter().any(|(d, _)| d == c))
+                    .map(|(c, _)| c.to_string())
+                    .collect();
+                if !missing.is_empty() {
+                    let position = if *nested { " in a nested pattern" } else { "" };
+                    errors.push(Rich::custom(
+                        *span,
+                        format!("There is no branch for {}{position}", missing.join(", ")),
+                    ));
+                }
+            }
+        }
+    }
+    errors
+}
@@ -0,0 +1,137 @@
+use crate::{parse, parse_recovering, parse_with_warnings};
+
+/// The located error messages of a program
+fn errors(source: &str) -> Vec<(&str, String)> {
+    parse(source)
+        .unwrap_err()
+        .into_iter()
+        .map(|error| (&source[error.span().into_range()], error.to_string()))
+        .collect()
+}
+
+const TREE: &str = r"
+    data Tree = Leaf() | Node(Tree, Nat, Tree);
+";
+
+#[test]
+fn declarations() {
+    parse(&format!(
+        r"{TREE}
+        let rec size t = case t of {{
+            Leaf() -> 0;
+            Node(l, n, r) -> Suc(size l)
+        }};
+        size Node(Leaf(), 3, Leaf())"
+    ))
+    .unwrap();
+    parse("data Nat = Zero() | Suc(Nat); data List = Nil() | Cons(a, List,); Suc(Zero())").unwrap();
+
+    // Without declarations nothing is checked, and `data` is still a variable name
+    parse("let data = Sucs(Zero(), Zero()); case data of { Zero() -> A() }").unwrap();
+    assert!(parse("data Nat =; x").is_err());
+}
+
+#[test]
+fn unknown_constructors_and_arities() {
+    assert_eq!(
+        errors(&format!("{TREE} Node(Leaf(), Sucs(Zero()))")),
+        vec![
+            (
+                "Sucs(Zero())",
+                "Sucs is not a constructor of a declared data type".to_string()
+            ),
+            (
+                "Node(Leaf(), Sucs(Zero()))",
+                "Node has 3 fields, but is used with 2".to_string()
+            ),
+        ]
+    );
+
+    // The constructors of numbers, lists and booleans are always known, also before the declarations
+    parse(&format!("let x = [0, True()]; {TREE} x")).unwrap();
+    assert_eq!(
+        errors(&format!("let x = Suc(); {TREE} x")),
+        vec![("Suc()", "Suc has 1 field, but is used with 0".to_string())]
+    );
+}
+
+#[test]
+fn case_branches() {
+    assert_eq!(
+        errors(&format!(
+            "{TREE} case t of {{ Leaf() -> 0; Leaf() -> 1; Zero() -> 2 }}"
+        )),
+        vec![
+            ("Leaf()", "Leaf already has a branch".to_string()),
+            (
+                "Zero()",
+                "Zero is a constructor of Nat, not of Tree".to_string()
+            ),
+            (
+                "case t of { Leaf() -> 0; Leaf() -> 1; Zero() -> 2 }",
+                "There is no branch for Node".to_string()
+            ),
+        ]
+    );
+    assert_eq!(
+        errors(&format!(
+            "{TREE} case t of {{ Node(l, r) -> 0; Leaf() -> 1 }}"
+        )),
+        vec![(
+            "Node(l, r)",
+            "Node has 3 fields, but is used with 2".to_string()
+        )]
+    );
+}
+
+#[test]
+fn nested_patterns() {
+    let source = "data Unit = U(); case n of { Suc(Zero()) -> 0; Zero() -> 1 }";
+    assert_eq!(
+        errors(source),
+        vec![(
+            "case n of { Suc(Zero()) -> 0; Zero() -> 1 }",
+            "There is no branch for Suc in a nested pattern".to_string()
+        )]
+    );
+
+    // A catch-all covers the constructors of a data type that is declared before it
+    let source = format!("{TREE} case t of {{ Node(Leaf(), n, Leaf()) -> n; _ -> 0 }}");
+    let (program, warnings) = parse_with_warnings(&source);
+    program.unwrap();
+    assert_eq!(warnings, vec![]);
+}
+
+#[test]
+fn duplicate_declarations() {
+    assert_eq!(
+        errors("data A = A() | B(); data B = C() | B(); data A = D(); A()"),
+        vec![
+            (
+                "data B = C() | B();",
+                "B is already a constructor of A".to_string()
+            ),
+            (
+                "data A = D();",
+                "The data type A is declared twice".to_string()
+            ),
+        ]
+    );
+
+    // A declaration replaces the built-in data type of its constructors
+    assert_eq!(
+        errors("data Number = Zero() | One(); Suc(Zero())"),
+        vec![(
+            "Suc(Zero())",
+            "Suc is not a constructor of a declared data type".to_string()
+        )]
+    );
+}
+
+#[test]
+fn recovered_declarations() {
+    let source = format!("data T = A(; {TREE} Leaf()");
+    let (program, errors) = parse_recovering(&source);
+    assert!(program.is_some());
+    assert_eq!(errors.len(), 1);
+}
@@ -0,0 +1,109 @@
+/// A locally nameless representation of expressions, where the bound variables are replaced by de Bruijn indices
+/// and the free variables keep their names. Two expressions are alpha-equivalent (equal up to the renaming of bound variables)
+/// exactly when their nameless representations are equal.
+///
+/// Natural numbers are canonical as well, both the constructor trees `Suc(...Zero())` and the compact `Expr::Nat`
+/// are converted to `DeBruijn::Nat`.
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use num_bigint::BigUint;
+
+use crate::{
+    parser::{Branch, Constructor, Variable},
+    Expr,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DeBruijn {
+    Apply(Box<Self>, Box<Self>),
+    /// Binds one variable in the body
+    Lambda(Box<Self>),
+    Case(Box<Self>, Vec<DeBruijnBranch>),
+    /// Binds one variable in the body
+    Rec(Box<Self>),
+    /// The number of binders between the variable and its binder, the innermost binder is 0
+    Bound(usize),
+    Free(Variable),
+    Const(Constructor, Vec<Self>),
+    /// `Suc(Suc(...Zero()))`
+    Nat(BigUint),
+    /// A reference to a top-level definition
+    Global(usize),
+}
+
+/// A branch that binds `arity` variables in the body, the last variable is the innermost.
+/// This means that if the same name is bound twice in a branch, the last one is used, just like in the substitution
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DeBruijnBranch(pub Constructor, pub usize, pub DeBruijn);
+
+impl From<&Expr> for DeBruijn {
+    fn from(expr: &Expr) -> Self {
+        to_de_bruijn(expr, &mut Vec::new())
+    }
+}
+
+/// `bound` are the variables bound so far, the innermost last
+fn to_de_bruijn(expr: &Expr, bound: &mut Vec<Variable>) -> DeBruijn {
+    match expr {
+        Expr::Apply(e1, e2) => DeBruijn::Apply(
+            Box::new(to_de_bruijn(e1, bound)),
+            Box::new(to_de_bruijn(e2, bound)),
+        ),
+        Expr::Lambda(x, e) => {
+            bound.push(x.clone());
+            let e = to_de_bruijn(e, bound);
+            bound.pop();
+            DeBruijn::Lambda(Box::new(e))
+        }
+        Expr::Case(e, branches) => DeBruijn::Case(
+            Box::new(to_de_bruijn(e, bound)),
+            branches
+                .iter()
+                .map(|Branch(c, xs, e)| {
+                    let n = bound.len();
+                    bound.extend(xs.iter().cloned());
+                    let e = to_de_bruijn(e, bound);
+                    bound.truncate(n);
+                    DeBruijnBranch(c.clone(), xs.len(), e)
+                })
+                .collect(),
+        ),
+        Expr::Rec(x, e) => {
+            bound.push(x.clone());
+            let e = to_de_bruijn(e, bound);
+            bound.pop();
+            DeBruijn::Rec(Box::new(e))
+        }
+        Expr::Var(x) => match bound.iter().rev().position(|y| y == x) {
+            Some(index) => DeBruijn::Bound(index),
+            None => DeBruijn::Free(x.clone()),
+        },
+        Expr::Const(c, es) => {
+            let mut es: Vec<_> = es.iter().map(|e| to_de_bruijn(e, bound)).collect();
+            match (c.0.as_str(), es.as_mut_slice()) {
+                ("Zero", []) => DeBruijn::Nat(BigUint::default()),
+                ("Suc", [DeBruijn::Nat(n)]) => DeBruijn::Nat(std::mem::take(n) + 1u32),
+                _ => DeBruijn::Const(c.clone(), es),
+            }
+        }
+        Expr::Nat(n) => DeBruijn::Nat(n.clone()),
+        Expr::Global(_, index) => DeBruijn::Global(*index),
+        Expr::Located(_, e) => to_de_bruijn(e, bound),
+    }
+}
+
+/// Whether the expressions are equal up to the renaming of bound variables (and ignoring locations)
+pub fn alpha_eq(e1: &Expr, e2: &Expr) -> bool {
+    DeBruijn::from(e1) == DeBruijn::from(e2)
+}
+
+/// A hash of the expression that is the same for alpha-equivalent expressions.
+/// Note: the hash is only stable for a given build, so it should not be stored.
+pub fn alpha_hash(expr: &Expr) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    DeBruijn::from(expr).hash(&mut hasher);
+    hasher.finish()
+}
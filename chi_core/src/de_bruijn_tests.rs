@@ -0,0 +1,70 @@
+use std::collections::HashSet;
+
+use crate::{alpha_eq, alpha_hash, parser::Variable, test_utils::expr, DeBruijn, Expr};
+
+#[test]
+fn conversion() {
+    use DeBruijn::*;
+    assert_eq!(
+        DeBruijn::from(&expr(r"\x. \y. x y z")),
+        Lambda(Box::new(Lambda(Box::new(Apply(
+            Box::new(Apply(Box::new(Bound(1)), Box::new(Bound(0)))),
+            Box::new(Free(Variable::from("z")))
+        )))))
+    );
+}
+
+#[test]
+fn renaming() {
+    assert!(alpha_eq(&expr(r"\x. x"), &expr(r"\y. y")));
+    assert!(alpha_eq(
+        &expr(r"rec f = \x. f x"),
+        &expr(r"rec g = \y. g y")
+    ));
+    assert!(alpha_eq(
+        &expr(r"\z. case z of { C(a, b) -> b; D() -> z }"),
+        &expr(r"\x. case x of { C(y, z) -> z; D() -> x }")
+    ));
+}
+
+#[test]
+fn not_equivalent() {
+    // Free variables must have the same names
+    assert!(!alpha_eq(&expr(r"x"), &expr(r"y")));
+    assert!(!alpha_eq(&expr(r"\x. \y. x"), &expr(r"\x. \y. y")));
+    // A bound variable is not the same as a free one
+    assert!(!alpha_eq(&expr(r"\x. y"), &expr(r"\y. y")));
+    assert!(!alpha_eq(
+        &expr(r"case C() of { C(a, b) -> a }"),
+        &expr(r"case C() of { C(a, b) -> b }")
+    ));
+}
+
+#[test]
+fn shadowing() {
+    assert!(alpha_eq(&expr(r"\x. \x. x"), &expr(r"\y. \z. z")));
+    assert!(!alpha_eq(&expr(r"\x. \x. x"), &expr(r"\y. \z. y")));
+    // The last variable of a branch is used if a name is repeated
+    assert!(alpha_eq(
+        &expr(r"case C() of { C(x, x) -> x }"),
+        &expr(r"case C() of { C(y, z) -> z }")
+    ));
+}
+
+#[test]
+fn hash_deduplicates() {
+    let terms = [r"\x. x", r"\y. y", r"\x. \y. x", r"\a. \b. a", r"\x. \y. y"];
+    let hashes: HashSet<u64> = terms.iter().map(|t| alpha_hash(&expr(t))).collect();
+    assert_eq!(hashes.len(), 3);
+
+    let unique: HashSet<DeBruijn> = terms.iter().map(|t| DeBruijn::from(&expr(t))).collect();
+    assert_eq!(unique.len(), 3);
+}
+
+#[test]
+fn compact_naturals() {
+    let two = expr("Suc(Suc(Zero()))");
+    assert!(alpha_eq(&two, &Expr::nat(2u32)));
+    assert!(!alpha_eq(&two, &Expr::nat(3u32)));
+    assert_eq!(alpha_hash(&two), alpha_hash(&Expr::nat(2u32)));
+}
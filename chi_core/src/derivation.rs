@@ -0,0 +1,340 @@
+/// Derivation trees for the big-step relation `e ⇓ v`, as in the course notes and the Agda specification.
+/// The trees are recorded by the evaluator, see `eval_derivation`.
+use std::fmt::{self, Write};
+
+use crate::{pretty, Expr};
+
+/// The big-step rule used in a node of a derivation tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DerivationRule {
+    /// `e1 ⇓ \x. e`, `e2 ⇓ v2` and `e[x := v2] ⇓ v` gives `e1 e2 ⇓ v`
+    Apply,
+    /// `e ⇓ c(vs)` and `e'[xs := vs] ⇓ v` where `c(xs) -> e'` is the first matching branch gives `case e of {...} ⇓ v`
+    Case,
+    /// `e[x := rec x = e] ⇓ v` gives `rec x = e ⇓ v`
+    Rec,
+    /// `es ⇓ vs` gives `c(es) ⇓ c(vs)`
+    Const,
+    /// `\x. e ⇓ \x. e`
+    Lambda,
+}
+
+/// A derivation of `expr ⇓ value`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Derivation {
+    pub rule: DerivationRule,
+    pub expr: Expr,
+    pub value: Expr,
+    pub premises: Vec<Derivation>,
+}
+
+/// Bounds on how much of a derivation that is rendered, derivations of even small programs get very large
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderLimits {
+    /// Maximum number of rule instances, the premises of the remaining nodes are elided
+    pub max_nodes: Option<usize>,
+    /// Terms longer than this (in characters) are cut off
+    pub max_term_length: Option<usize>,
+}
+
+impl Default for RenderLimits {
+    fn default() -> Self {
+        Self {
+            max_nodes: Some(500),
+            max_term_length: Some(80),
+        }
+    }
+}
+
+/// Builds the derivation tree while the evaluator is running.
+/// A node is started for every term that the evaluator begins to evaluate,
+/// and it is finished once all of its premises have been derived.
+#[derive(Default)]
+pub(crate) struct Recorder {
+    /// Rule instances that are still missing premises, the innermost one last
+    pending: Vec<Pending>,
+    root: Option<Derivation>,
+}
+
+struct Pending {
+    rule: DerivationRule,
+    expr: Expr,
+    premises: Vec<Derivation>,
+    expected: usize,
+}
+
+impl Recorder {
+    /// Called when the evaluator starts evaluating `expr`
+    pub(crate) fn begin(&mut self, expr: &Expr) {
+        let (rule, expected) = match expr {
+            Expr::Apply(..) => (DerivationRule::Apply, 3),
+            Expr::Lambda(..) => (DerivationRule::Lambda, 0),
+            Expr::Case(..) => (DerivationRule::Case, 2),
+            Expr::Rec(..) => (DerivationRule::Rec, 1),
+            Expr::Const(_, es) => (DerivationRule::Const, es.len()),
+            // Variables get stuck, so there is no derivation
+            Expr::Var(_) => return,
+            Expr::Located(..) => unreachable!("locations are removed before evaluating"),
+            Expr::Nat(_) => unreachable!("natural numbers are unfolded while recording"),
+            Expr::Global(..) => unreachable!("definitions are looked up before evaluating"),
+        };
+
+        self.pending.push(Pending {
+            rule,
+            expr: expr.clone(),
+            premises: Vec::new(),
+            expected,
+        });
+
+        if expected == 0 {
+            self.finish();
+        }
+    }
+
+    /// Finish the innermost pending node, and all of the enclosing nodes that got their last premise
+    fn finish(&mut self) {
+        while let Some(Pending {
+            rule,
+            expr,
+            premises,
+            ..
+        }) = self.pending.pop()
+        {
+            let value = match (&rule, &expr) {
+                (DerivationRule::Lambda, _) => expr.clone(),
+                (DerivationRule::Const, Expr::Const(c, _)) => Expr::Const(
+                    c.clone(),
+                    premises.iter().map(|p| p.value.clone()).collect(),
+                ),
+                _ => premises
+                    .last()
+                    .expect("only lambdas and constants can be derived without premises")
+                    .value
+                    .clone(),
+            };
+
+            let derivation = Derivation {
+                rule,
+                expr,
+                value,
+                premises,
+            };
+
+            match self.pending.last_mut() {
+                Some(parent) => {
+                    parent.premises.push(derivation);
+                    if parent.premises.len() < parent.expected {
+                        return;
+                    }
+                }
+                None => {
+                    self.root = Some(derivation);
+                    return;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn into_derivation(self) -> Option<Derivation> {
+        self.root
+    }
+}
+
+impl Derivation {
+    /// The number of rule instances in the derivation
+    pub fn size(&self) -> usize {
+        1 + self.premises.iter().map(Derivation::size).sum::<usize>()
+    }
+
+    /// Render the derivation as an indented tree, conclusions above their premises
+    pub fn to_text(&self, limits: &RenderLimits) -> String {
+        let mut s = String::new();
+        let mut budget = limits.max_nodes;
+        self.text(&mut s, 0, limits, &mut budget);
+        s
+    }
+
+    fn text(
+        &self,
+        s: &mut String,
+        indent: usize,
+        limits: &RenderLimits,
+        budget: &mut Option<usize>,
+    ) {
+        writeln!(
+            s,
+            "{indent}{rule}: {expr} ⇓ {value}",
+            indent = "  ".repeat(indent),
+            rule = self.rule,
+            expr = term(&self.expr, limits),
+            value = term(&self.value, limits)
+        )
+        .unwrap();
+
+        if !self.premises.is_empty() && !take_node(budget) {
+            writeln!(s, "{}…", "  ".repeat(indent + 1)).unwrap();
+            return;
+        }
+
+        for premise in &self.premises {
+            premise.text(s, indent + 1, limits, budget);
+        }
+    }
+
+    /// Render the derivation as a LaTeX `prooftree` using the `bussproofs` package
+    pub fn to_latex(&self, limits: &RenderLimits) -> String {
+        let mut s = String::from("\\begin{prooftree}\n");
+        let mut budget = limits.max_nodes;
+        self.latex(&mut s, limits, &mut budget);
+        s.push_str("\\end{prooftree}\n");
+        s
+    }
+
+    fn latex(&self, s: &mut String, limits: &RenderLimits, budget: &mut Option<usize>) {
+        // bussproofs supports at most five premises, so any extra premises are elided
+        const MAX_PREMISES: usize = 5;
+
+        let premises = if !self.premises.is_empty() && !take_node(budget) {
+            s.push_str("\\AxiomC{$\\vdots$}\n");
+            1
+        } else if self.premises.is_empty() {
+            s.push_str("\\AxiomC{}\n");
+            1
+        } else if self.premises.len() > MAX_PREMISES {
+            for premise in &self.premises[..MAX_PREMISES - 1] {
+                premise.latex(s, limits, budget);
+            }
+            s.push_str("\\AxiomC{$\\cdots$}\n");
+            MAX_PREMISES
+        } else {
+            for premise in &self.premises {
+                premise.latex(s, limits, budget);
+            }
+            self.premises.len()
+        };
+
+        let inference = match premises {
+            1 => "UnaryInfC",
+            2 => "BinaryInfC",
+            3 => "TrinaryInfC",
+            4 => "QuaternaryInfC",
+            _ => "QuinaryInfC",
+        };
+
+        writeln!(
+            s,
+            "\\RightLabel{{\\textsc{{{rule}}}}}\n\\{inference}{{\\texttt{{{expr}}} $\\Downarrow$ \\texttt{{{value}}}}}",
+            rule = self.rule,
+            expr = latex_escape(&term(&self.expr, limits)),
+            value = latex_escape(&term(&self.value, limits)),
+        )
+        .unwrap();
+    }
+
+    /// Render the derivation as JSON, each node is an object with the fields `rule`, `expr`, `value` and `premises`.
+    /// Elided premises are replaced with `null`.
+    pub fn to_json(&self, limits: &RenderLimits) -> String {
+        let mut s = String::new();
+        let mut budget = limits.max_nodes;
+        self.json(&mut s, limits, &mut budget);
+        s
+    }
+
+    fn json(&self, s: &mut String, limits: &RenderLimits, budget: &mut Option<usize>) {
+        write!(
+            s,
+            r#"{{"rule":"{rule}","expr":{expr},"value":{value},"premises":"#,
+            rule = self.rule,
+            expr = json_string(&term(&self.expr, limits)),
+            value = json_string(&term(&self.value, limits)),
+        )
+        .unwrap();
+
+        if !self.premises.is_empty() && !take_node(budget) {
+            s.push_str("null}");
+            return;
+        }
+
+        s.push('[');
+        for (i, premise) in self.premises.iter().enumerate() {
+            if i != 0 {
+                s.push(',');
+            }
+            premise.json(s, limits, budget);
+        }
+        s.push_str("]}");
+    }
+}
+
+/// Use up one node of the budget, returns false if there is nothing left
+fn take_node(budget: &mut Option<usize>) -> bool {
+    match budget {
+        Some(0) => false,
+        Some(n) => {
+            *n -= 1;
+            true
+        }
+        None => true,
+    }
+}
+
+/// Print a term on a single line, cut off according to the limits
+fn term(expr: &Expr, limits: &RenderLimits) -> String {
+    let s = pretty::message(expr)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    match limits.max_term_length {
+        Some(max) if s.chars().count() > max => {
+            let mut s: String = s.chars().take(max).collect();
+            s.push('…');
+            s
+        }
+        _ => s,
+    }
+}
+
+fn latex_escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '{' | '}' | '_' | '#' | '$' | '%' | '&' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '…' => escaped.push_str("\\ldots{}"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => write!(&mut escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+impl fmt::Display for DerivationRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DerivationRule::Apply => write!(f, "apply"),
+            DerivationRule::Case => write!(f, "case"),
+            DerivationRule::Rec => write!(f, "rec"),
+            DerivationRule::Const => write!(f, "const"),
+            DerivationRule::Lambda => write!(f, "lambda"),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{
+    eval, eval_derivation, test_utils::program, Derivation, DerivationRule, EvalLimits, Program,
+    RenderLimits,
+};
+
+fn derive(source: &str) -> Derivation {
+    eval_derivation(program(source), &EvalLimits::default()).unwrap()
+}
+
+fn rules(derivation: &Derivation) -> Vec<DerivationRule> {
+    let mut rules = vec![derivation.rule];
+    for premise in &derivation.premises {
+        rules.extend(self::rules(premise));
+    }
+    rules
+}
+
+#[test]
+fn lambda_axiom() {
+    let derivation = derive(r"\x. x");
+    assert_eq!(derivation.rule, DerivationRule::Lambda);
+    assert!(derivation.premises.is_empty());
+    assert_eq!(derivation.expr, derivation.value);
+}
+
+#[test]
+fn application() {
+    use DerivationRule::*;
+    let derivation = derive(r"(\x. x) C()");
+    assert_eq!(rules(&derivation), vec![Apply, Lambda, Const, Const]);
+    assert_eq!(Program::Expr(derivation.value), program("C()"));
+}
+
+#[test]
+fn case_and_rec() {
+    use DerivationRule::*;
+    let derivation = derive(r"case (rec x = C(\y. y)) of { C(f) -> f }");
+    assert_eq!(rules(&derivation), vec![Case, Rec, Const, Lambda, Lambda]);
+}
+
+#[test]
+fn conclusion_is_the_value() {
+    let src = r#"
+    let add = rec add = \x. \y. case x of
+    { Zero() -> y
+    ; Suc(n) -> Suc(add n y)
+    };
+    add Suc(Suc(Zero())) Suc(Zero())
+    "#;
+    let derivation = derive(src);
+    assert_eq!(
+        derivation.value,
+        eval(program(src), &EvalLimits::default()).unwrap()
+    );
+    // Every premise should also be a valid conclusion on its own, in the program that has the definition of `add`
+    for premise in &derivation.premises {
+        let Program::Let(doc, x, add, _) = program(src) else {
+            unreachable!()
+        };
+        let premise_program =
+            Program::Let(doc, x, add, Box::new(Program::Expr(premise.expr.clone())));
+        assert_eq!(
+            premise.value,
+            eval(premise_program, &EvalLimits::default()).unwrap()
+        );
+    }
+}
+
+#[test]
+fn definitions_are_looked_up() {
+    // Substituting the definitions would create 2^40 copies of `twice0`
+    let mut src = r"let twice0 = \f. \x. f (f x);".to_string();
+    for i in 1..40 {
+        src += &format!(
+            r"let twice{i} = \f. \x. twice{} f (twice{} f x);",
+            i - 1,
+            i - 1
+        );
+    }
+    src += r"twice1 (\x. S(x)) Z()";
+    let derivation = derive(&src);
+    assert_eq!(
+        Program::Expr(derivation.value.clone()),
+        program("S(S(S(S(Z()))))")
+    );
+    // The terms refer to the definitions by name
+    let text = derivation.to_text(&RenderLimits::default());
+    assert!(text.contains("twice0 f"), "{text}");
+}
+
+#[test]
+fn stuck_has_no_derivation() {
+    assert!(eval_derivation(program("C() C()"), &EvalLimits::default()).is_err());
+}
+
+#[test]
+fn render_text() {
+    let text = derive(r"(\x. x) C()").to_text(&RenderLimits::default());
+    assert_eq!(
+        text,
+        "apply: (\\x. x) C() ⇓ C()\n  lambda: \\x. x ⇓ \\x. x\n  const: C() ⇓ C()\n  const: C() ⇓ C()\n"
+    );
+}
+
+#[test]
+fn render_latex() {
+    let latex = derive(r"(\x. x) C()").to_latex(&RenderLimits::default());
+    assert!(latex.starts_with("\\begin{prooftree}\n\\AxiomC{}\n"));
+    assert!(latex.contains(
+        "\\TrinaryInfC{\\texttt{(\\textbackslash{}x. x) C()} $\\Downarrow$ \\texttt{C()}}"
+    ));
+    assert!(latex.ends_with("\\end{prooftree}\n"));
+}
+
+#[test]
+fn render_json() {
+    let json = derive(r"C(\x. x)").to_json(&RenderLimits::default());
+    assert_eq!(
+        json,
+        r#"{"rule":"const","expr":"C(\\x. x)","value":"C(\\x. x)","premises":[{"rule":"lambda","expr":"\\x. x","value":"\\x. x","premises":[]}]}"#
+    );
+}
+
+#[test]
+fn render_limits() {
+    let derivation = derive(r"(\x. x) C(D(E()))");
+    let limits = RenderLimits {
+        max_nodes: Some(1),
+        max_term_length: Some(3),
+    };
+    assert_eq!(
+        derivation.to_text(&limits),
+        "apply: (\\x… ⇓ C(D…\n  lambda: \\x.… ⇓ \\x.…\n  const: C(D… ⇓ C(D…\n    …\n  const: C(D… ⇓ C(D…\n    …\n"
+    );
+    assert!(derivation.to_json(&limits).contains(r#""premises":null"#));
+}
@@ -0,0 +1,896 @@
+/// An alternative evaluator that uses environments and closures instead of substitution.
+/// Terms are shared between closures rather than copied on every beta-reduction, case branch and `rec` unfolding,
+/// and the result is converted back into an `Expr` once the evaluation is finished.
+///
+/// On closed programs it computes the same values as `eval`, and it counts reduction steps the same way,
+/// so it also runs out of steps at the same point. Note that the evaluation depth may differ, since
+/// values are not re-evaluated after being substituted, and that `max_size` is not checked.
+///
+/// The machine can also use the lazy strategies call-by-name and call-by-need, see `Strategy`.
+use std::{cell::RefCell, fmt, mem, rc::Rc};
+
+use num_bigint::BigUint;
+
+use crate::{
+    eval::{Budget, Definitions},
+    parser::{Branch, Constructor, Span, Variable},
+    EvalError, EvalLimits, Expr, Program,
+};
+
+/// An immutable copy of `Expr` where the subterms can be shared
+enum Term {
+    Apply(Rc<Term>, Rc<Term>),
+    Lambda(Variable, Rc<Term>),
+    Case(Rc<Term>, Rc<Vec<Branch<Rc<Term>>>>),
+    Rec(Variable, Rc<Term>),
+    Var(Variable),
+    Const(Constructor, Rc<Vec<Rc<Term>>>),
+    Located(Span, Rc<Term>),
+    /// A reference to a top-level definition, see `Expr::Global`
+    Global(Variable, usize),
+    /// A compact natural number, see `Expr::Nat`
+    Nat(BigUint),
+}
+
+/// The order in which the subterms are evaluated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// Arguments and constructor fields are evaluated before they are used, this is the semantics of `eval`
+    #[default]
+    CallByValue,
+    /// Arguments and constructor fields are evaluated every time they are used, and not at all if they are never used
+    CallByName,
+    /// Like call-by-name, but an argument or constructor field is only evaluated the first time it is used
+    CallByNeed,
+}
+
+enum Value {
+    Closure(Variable, Rc<Term>, Env),
+    Const(Constructor, Vec<Rc<Thunk>>),
+    /// A natural number that is only unfolded to `Zero()` or `Suc(n)` when it is matched by a case expression,
+    /// like `Expr::Nat` in `eval`
+    Nat(BigUint),
+}
+
+impl Value {
+    /// The constructor application `c(fields)`, using the compact representation if it is `Zero()`
+    /// or `Suc` of a compact number, like `Expr::constructor`
+    fn constructor(c: Constructor, fields: Vec<Rc<Thunk>>) -> Self {
+        let n = match (c.0.as_str(), fields.as_slice()) {
+            ("Zero", []) => Some(BigUint::default()),
+            ("Suc", [field]) => match &*field.0.borrow() {
+                ThunkState::Evaluated(value) => match &**value {
+                    Value::Nat(n) => Some(n + 1u32),
+                    _ => None,
+                },
+                ThunkState::Delayed(..) => None,
+            },
+            _ => None,
+        };
+        match n {
+            Some(n) => Value::Nat(n),
+            None => Value::Const(c, fields),
+        }
+    }
+}
+
+/// An argument or constructor field, which is only evaluated once it is needed when using a lazy strategy
+struct Thunk(RefCell<ThunkState>);
+
+enum ThunkState {
+    Delayed(Rc<Term>, Env),
+    Evaluated(Rc<Value>),
+}
+
+impl Thunk {
+    fn evaluated(value: Rc<Value>) -> Rc<Self> {
+        Rc::new(Thunk(RefCell::new(ThunkState::Evaluated(value))))
+    }
+
+    /// Delay the evaluation of `term` in `env`.
+    /// A variable that is bound to a thunk is not delayed again, otherwise call-by-name would build
+    /// longer and longer chains of variables that must be followed every time they are used.
+    fn delayed(term: Rc<Term>, env: Env) -> Rc<Self> {
+        let mut unlocated = &term;
+        while let Term::Located(_, e) = &**unlocated {
+            unlocated = e;
+        }
+
+        if let Term::Var(x) = &**unlocated {
+            if let Some(Bound::Value(thunk)) = env.lookup(x) {
+                return thunk.clone();
+            }
+        }
+
+        Rc::new(Thunk(RefCell::new(ThunkState::Delayed(term, env))))
+    }
+}
+
+/// A persistent linked list of variable bindings, the innermost binding first
+#[derive(Clone, Default)]
+struct Env(Option<Rc<Binding>>);
+
+struct Binding {
+    var: Variable,
+    bound: Bound,
+    next: Env,
+}
+
+enum Bound {
+    Value(Rc<Thunk>),
+    /// The variable of `rec x = e`, every use of it unfolds the recursion once more
+    Rec(Variable, Rc<Term>, Env),
+}
+
+impl Env {
+    fn bind(&self, var: Variable, bound: Bound) -> Self {
+        Env(Some(Rc::new(Binding {
+            var,
+            bound,
+            next: self.clone(),
+        })))
+    }
+
+    fn lookup(&self, var: &Variable) -> Option<&Bound> {
+        let mut env = self;
+        while let Some(binding) = &env.0 {
+            if binding.var == *var {
+                return Some(&binding.bound);
+            }
+            env = &binding.next;
+        }
+        None
+    }
+}
+
+/// Evaluate a program using environments and closures, see the module documentation for how it relates to `eval`
+pub fn eval_env(program: Program<Expr>, limits: &EvalLimits) -> Result<Expr, EvalError> {
+    eval_strategy(program, Strategy::CallByValue, limits)
+}
+
+/// Evaluate a program using the given strategy.
+/// With the lazy strategies, the constructor fields of the final value are also evaluated,
+/// but the bodies of lambdas are left as they are (with the unevaluated arguments substituted).
+pub fn eval_strategy(
+    program: Program<Expr>,
+    strategy: Strategy,
+    limits: &EvalLimits,
+) -> Result<Expr, EvalError> {
+    let (definitions, expr) = Definitions::new(program);
+    let definitions = definitions.iter().map(to_term).collect();
+    let term = to_term(&expr);
+    let value = Machine::new(term, definitions, strategy, Budget::new(limits)).run()?;
+    Ok(readback(&value).without_spans())
+}
+
+/// The outcome of evaluating a program using two different strategies, see `compare_strategies`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Comparison {
+    /// Both strategies computed the same value
+    SameValue(Expr),
+    /// Both strategies computed a value, but not the same one
+    DifferentValues(Expr, Expr),
+    /// Only the first strategy computed a value, the second failed or exceeded the limits
+    OnlyFirst(Expr, EvalError),
+    /// Only the second strategy computed a value, the first failed or exceeded the limits
+    OnlySecond(EvalError, Expr),
+    /// Neither strategy computed a value
+    Neither(EvalError, EvalError),
+}
+
+impl Comparison {
+    /// Whether the strategies disagree on the value or on whether there is a value at all
+    pub fn diverges(&self) -> bool {
+        !matches!(self, Comparison::SameValue(_) | Comparison::Neither(..))
+    }
+}
+
+/// Evaluate a program using two strategies, with the same limits for both
+pub fn compare_strategies(
+    program: Program<Expr>,
+    first: Strategy,
+    second: Strategy,
+    limits: &EvalLimits,
+) -> Comparison {
+    let first = eval_strategy(program.clone(), first, limits);
+    let second = eval_strategy(program, second, limits);
+
+    match (first, second) {
+        (Ok(v1), Ok(v2)) if v1 == v2 => Comparison::SameValue(v1),
+        (Ok(v1), Ok(v2)) => Comparison::DifferentValues(v1, v2),
+        (Ok(v1), Err(e2)) => Comparison::OnlyFirst(v1, e2),
+        (Err(e1), Ok(v2)) => Comparison::OnlySecond(e1, v2),
+        (Err(e1), Err(e2)) => Comparison::Neither(e1, e2),
+    }
+}
+
+/// Convert an expression to a term, with an explicit stack since expressions can be very deeply nested
+fn to_term(expr: &Expr) -> Rc<Term> {
+    /// The terms are built in post-order like in `Expr::clone_iteratively`, a term is built once its subterms are
+    enum Task<'a> {
+        Convert(&'a Expr),
+        Build(&'a Expr),
+    }
+
+    let mut tasks = vec![Task::Convert(expr)];
+    let mut terms: Vec<Rc<Term>> = Vec::new();
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Convert(e) => match e {
+                Expr::Apply(e1, e2) => {
+                    tasks.extend([Task::Build(e), Task::Convert(e2), Task::Convert(e1)]);
+                }
+                Expr::Lambda(_, body) | Expr::Rec(_, body) | Expr::Located(_, body) => {
+                    tasks.extend([Task::Build(e), Task::Convert(body)]);
+                }
+                Expr::Case(scrutinee, branches) => {
+                    tasks.push(Task::Build(e));
+                    tasks.extend(
+                        branches
+                            .iter()
+                            .rev()
+                            .map(|Branch(_, _, e)| Task::Convert(e)),
+                    );
+                    tasks.push(Task::Convert(scrutinee));
+                }
+                Expr::Const(_, es) => {
+                    tasks.push(Task::Build(e));
+                    tasks.extend(es.iter().rev().map(Task::Convert));
+                }
+                Expr::Var(x) => terms.push(Rc::new(Term::Var(x.clone()))),
+                Expr::Nat(n) => terms.push(Rc::new(Term::Nat(n.clone()))),
+                Expr::Global(x, index) => terms.push(Rc::new(Term::Global(x.clone(), *index))),
+            },
+            Task::Build(e) => {
+                let mut pop = || terms.pop().unwrap();
+                let term = match e {
+                    Expr::Apply(..) => {
+                        let e2 = pop();
+                        Term::Apply(pop(), e2)
+                    }
+                    Expr::Lambda(x, _) => Term::Lambda(x.clone(), pop()),
+                    Expr::Rec(x, _) => Term::Rec(x.clone(), pop()),
+                    Expr::Located(span, _) => Term::Located(span.clone(), pop()),
+                    Expr::Case(_, branches) => {
+                        let bodies = terms.split_off(terms.len() - branches.len());
+                        let scrutinee = terms.pop().unwrap();
+                        let branches = branches
+                            .iter()
+                            .zip(bodies)
+                            .map(|(Branch(c, xs, _), e)| Branch(c.clone(), xs.clone(), e))
+                            .collect();
+                        Term::Case(scrutinee, Rc::new(branches))
+                    }
+                    Expr::Const(c, es) => {
+                        Term::Const(c.clone(), Rc::new(terms.split_off(terms.len() - es.len())))
+                    }
+                    Expr::Var(_) | Expr::Nat(_) | Expr::Global(..) => unreachable!(),
+                };
+                terms.push(Rc::new(term));
+            }
+        }
+    }
+
+    terms.pop().unwrap()
+}
+
+/// Convert a value back into an expression, by substituting the environments of the closures
+fn readback(value: &Rc<Value>) -> Expr {
+    read_back(vec![Readback::Value(value.clone())])
+        .pop()
+        .unwrap()
+}
+
+/// Convert a term back to an expression, replacing the free variables that are bound in `env`
+fn term_to_expr(term: &Rc<Term>, env: &Env) -> Expr {
+    let tasks = vec![
+        Readback::Leave,
+        Readback::Term(term.clone()),
+        Readback::Enter(env.clone(), Vec::new()),
+    ];
+    read_back(tasks).pop().unwrap()
+}
+
+fn branches_to_expr(branches: &[Branch<Rc<Term>>], env: &Env) -> Vec<Branch<Expr>> {
+    let mut tasks = vec![Readback::Leave];
+    for Branch(_, xs, e) in branches.iter().rev() {
+        tasks.extend([
+            Readback::Unbind(xs.len()),
+            Readback::Term(e.clone()),
+            Readback::Bind(xs.clone()),
+        ]);
+    }
+    tasks.push(Readback::Enter(env.clone(), Vec::new()));
+
+    branches
+        .iter()
+        .zip(read_back(tasks))
+        .map(|(Branch(c, xs, _), e)| Branch(c.clone(), xs.clone(), e))
+        .collect()
+}
+
+/// The work of `read_back`, the expressions are built in post-order once their subexpressions are read back
+enum Readback {
+    Value(Rc<Value>),
+    /// An unevaluated thunk becomes its term
+    Thunk(Rc<Thunk>),
+    /// A term in the current scope
+    Term(Rc<Term>),
+    /// Start the scope of the term of a closure, thunk or recursion: its environment, and the variables that are
+    /// bound inside the term so far, which shadow the environment
+    Enter(Env, Vec<Variable>),
+    Leave,
+    /// The variables of a lambda, `rec` or branch are bound in its body
+    Bind(Vec<Variable>),
+    Unbind(usize),
+    Build(Build),
+}
+
+enum Build {
+    Apply,
+    Lambda(Variable),
+    Rec(Variable),
+    Case(Rc<Vec<Branch<Rc<Term>>>>),
+    Const(Constructor, usize),
+    Located(Span),
+}
+
+/// Read back values and terms with an explicit stack, since values can be very deep (a long list for instance).
+/// Returns the expressions in order
+fn read_back(mut tasks: Vec<Readback>) -> Vec<Expr> {
+    let mut scopes: Vec<(Env, Vec<Variable>)> = Vec::new();
+    let mut exprs: Vec<Expr> = Vec::new();
+    while let Some(task) = tasks.pop() {
+        match task {
+            Readback::Value(value) => match &*value {
+                Value::Closure(x, body, env) => tasks.extend([
+                    Readback::Build(Build::Lambda(x.clone())),
+                    Readback::Leave,
+                    Readback::Term(body.clone()),
+                    Readback::Enter(env.clone(), vec![x.clone()]),
+                ]),
+                Value::Const(c, fields) => {
+                    tasks.push(Readback::Build(Build::Const(c.clone(), fields.len())));
+                    tasks.extend(fields.iter().rev().cloned().map(Readback::Thunk));
+                }
+                Value::Nat(n) => exprs.push(Expr::Nat(n.clone())),
+            },
+            Readback::Thunk(thunk) => match &*thunk.0.borrow() {
+                ThunkState::Delayed(term, env) => tasks.extend([
+                    Readback::Leave,
+                    Readback::Term(term.clone()),
+                    Readback::Enter(env.clone(), Vec::new()),
+                ]),
+                ThunkState::Evaluated(value) => tasks.push(Readback::Value(value.clone())),
+            },
+            Readback::Term(term) => {
+                let (env, bound) = scopes.last().expect("terms are read back in a scope");
+                match &*term {
+                    Term::Apply(e1, e2) => tasks.extend([
+                        Readback::Build(Build::Apply),
+                        Readback::Term(e2.clone()),
+                        Readback::Term(e1.clone()),
+                    ]),
+                    Term::Lambda(x, e) => tasks.extend([
+                        Readback::Build(Build::Lambda(x.clone())),
+                        Readback::Unbind(1),
+                        Readback::Term(e.clone()),
+                        Readback::Bind(vec![x.clone()]),
+                    ]),
+                    Term::Rec(x, e) => tasks.extend([
+                        Readback::Build(Build::Rec(x.clone())),
+                        Readback::Unbind(1),
+                        Readback::Term(e.clone()),
+                        Readback::Bind(vec![x.clone()]),
+                    ]),
+                    Term::Case(e, branches) => {
+                        tasks.push(Readback::Build(Build::Case(branches.clone())));
+                        for Branch(_, xs, e) in branches.iter().rev() {
+                            tasks.extend([
+                                Readback::Unbind(xs.len()),
+                                Readback::Term(e.clone()),
+                                Readback::Bind(xs.clone()),
+                            ]);
+                        }
+                        tasks.push(Readback::Term(e.clone()));
+                    }
+                    Term::Var(x) if bound.contains(x) => exprs.push(Expr::Var(x.clone())),
+                    Term::Var(x) => match env.lookup(x) {
+                        Some(Bound::Value(thunk)) => tasks.push(Readback::Thunk(thunk.clone())),
+                        Some(Bound::Rec(x, e, env)) => tasks.extend([
+                            Readback::Build(Build::Rec(x.clone())),
+                            Readback::Leave,
+                            Readback::Term(e.clone()),
+                            Readback::Enter(env.clone(), vec![x.clone()]),
+                        ]),
+                        None => exprs.push(Expr::Var(x.clone())),
+                    },
+                    Term::Const(c, es) => {
+                        tasks.push(Readback::Build(Build::Const(c.clone(), es.len())));
+                        tasks.extend(es.iter().rev().cloned().map(Readback::Term));
+                    }
+                    Term::Located(span, e) => tasks.extend([
+                        Readback::Build(Build::Located(span.clone())),
+                        Readback::Term(e.clone()),
+                    ]),
+                    Term::Global(x, index) => exprs.push(Expr::Global(x.clone(), *index)),
+                    Term::Nat(n) => exprs.push(Expr::Nat(n.clone())),
+                }
+            }
+            Readback::Enter(env, bound) => scopes.push((env, bound)),
+            Readback::Leave => {
+                scopes.pop();
+            }
+            Readback::Bind(xs) => scopes.last_mut().unwrap().1.extend(xs),
+            Readback::Unbind(n) => {
+                let bound = &mut scopes.last_mut().unwrap().1;
+                bound.truncate(bound.len() - n);
+            }
+            Readback::Build(build) => {
+                let mut pop = || Box::new(exprs.pop().unwrap());
+                let expr = match build {
+                    Build::Apply => {
+                        let e2 = pop();
+                        Expr::Apply(pop(), e2)
+                    }
+                    Build::Lambda(x) => Expr::Lambda(x, pop()),
+                    Build::Rec(x) => Expr::Rec(x, pop()),
+                    Build::Located(span) => Expr::Located(span, pop()),
+                    Build::Case(branches) => {
+                        let bodies = exprs.split_off(exprs.len() - branches.len());
+                        let e = Box::new(exprs.pop().unwrap());
+                        let branches = branches
+                            .iter()
+                            .zip(bodies)
+                            .map(|(Branch(c, xs, _), e)| Branch(c.clone(), xs.clone(), e))
+                            .collect();
+                        Expr::Case(e, branches)
+                    }
+                    Build::Const(c, n) => Expr::Const(c, exprs.split_off(exprs.len() - n)),
+                };
+                exprs.push(expr);
+            }
+        }
+    }
+
+    exprs
+}
+
+/// Terms, values and environments can be very deep, for instance the value of a long list, so they are dropped with
+/// an explicit stack instead of recursively. The subterms that are only referenced by the node that is dropped are
+/// moved to the stack (leaving a leaf behind), and they are dropped once their own subterms have been moved
+#[derive(Default)]
+struct Garbage(Vec<Node>);
+
+enum Node {
+    Term(Term),
+    Value(Value),
+    Binding(Rc<Binding>),
+}
+
+impl Garbage {
+    fn term(&mut self, term: &mut Rc<Term>) {
+        if let Some(term) = Rc::get_mut(term) {
+            if !matches!(term, Term::Var(_) | Term::Global(..) | Term::Nat(_)) {
+                let leaf = Term::Var(Variable(String::new()));
+                self.0.push(Node::Term(mem::replace(term, leaf)));
+            }
+        }
+    }
+
+    fn value(&mut self, value: &mut Rc<Value>) {
+        if let Some(value) = Rc::get_mut(value) {
+            if !matches!(value, Value::Nat(_)) {
+                let leaf = Value::Nat(BigUint::default());
+                self.0.push(Node::Value(mem::replace(value, leaf)));
+            }
+        }
+    }
+
+    /// A thunk is emptied where it is, it has no subterms of its own
+    fn thunk(&mut self, thunk: &mut Rc<Thunk>) {
+        if let Some(thunk) = Rc::get_mut(thunk) {
+            self.thunk_state(thunk.0.get_mut());
+        }
+    }
+
+    fn thunk_state(&mut self, state: &mut ThunkState) {
+        match state {
+            ThunkState::Delayed(term, env) => {
+                self.term(term);
+                self.env(env);
+            }
+            ThunkState::Evaluated(value) => self.value(value),
+        }
+    }
+
+    fn env(&mut self, env: &mut Env) {
+        if env
+            .0
+            .as_ref()
+            .is_some_and(|binding| Rc::strong_count(binding) == 1)
+        {
+            self.0.push(Node::Binding(env.0.take().unwrap()));
+        }
+    }
+
+    fn term_children(&mut self, term: &mut Term) {
+        match term {
+            Term::Apply(e1, e2) => {
+                self.term(e1);
+                self.term(e2);
+            }
+            Term::Lambda(_, e) | Term::Rec(_, e) | Term::Located(_, e) => self.term(e),
+            Term::Case(e, branches) => {
+                self.term(e);
+                if let Some(branches) = Rc::get_mut(branches) {
+                    branches.iter_mut().for_each(|Branch(_, _, e)| self.term(e));
+                }
+            }
+            Term::Const(_, es) => {
+                if let Some(es) = Rc::get_mut(es) {
+                    es.iter_mut().for_each(|e| self.term(e));
+                }
+            }
+            Term::Var(_) | Term::Global(..) | Term::Nat(_) => {}
+        }
+    }
+
+    fn value_children(&mut self, value: &mut Value) {
+        match value {
+            Value::Closure(_, body, env) => {
+                self.term(body);
+                self.env(env);
+            }
+            Value::Const(_, fields) => fields.iter_mut().for_each(|field| self.thunk(field)),
+            Value::Nat(_) => {}
+        }
+    }
+
+    fn binding_children(&mut self, binding: &mut Binding) {
+        match &mut binding.bound {
+            Bound::Value(thunk) => self.thunk(thunk),
+            Bound::Rec(_, term, env) => {
+                self.term(term);
+                self.env(env);
+            }
+        }
+        self.env(&mut binding.next);
+    }
+
+    /// Drop the nodes, each one after its subterms have been moved to the stack
+    fn collect(mut self) {
+        while let Some(node) = self.0.pop() {
+            match node {
+                Node::Term(mut term) => self.term_children(&mut term),
+                Node::Value(mut value) => self.value_children(&mut value),
+                Node::Binding(mut binding) => {
+                    if let Some(binding) = Rc::get_mut(&mut binding) {
+                        self.binding_children(binding);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Term {
+    fn drop(&mut self) {
+        let mut garbage = Garbage::default();
+        garbage.term_children(self);
+        garbage.collect();
+    }
+}
+
+impl Drop for Value {
+    fn drop(&mut self) {
+        let mut garbage = Garbage::default();
+        garbage.value_children(self);
+        garbage.collect();
+    }
+}
+
+impl Drop for Thunk {
+    fn drop(&mut self) {
+        let mut garbage = Garbage::default();
+        garbage.thunk_state(self.0.get_mut());
+        garbage.collect();
+    }
+}
+
+impl Drop for Binding {
+    fn drop(&mut self) {
+        let mut garbage = Garbage::default();
+        garbage.binding_children(self);
+        garbage.collect();
+    }
+}
+
+/// A CEK-style machine, with the same continuation frames as the machine in `eval` and some more for the lazy strategies
+struct Machine {
+    state: Option<State>,
+    stack: Vec<Frame>,
+    /// The terms of the top-level definitions, which are closed
+    definitions: Vec<Rc<Term>>,
+    strategy: Strategy,
+    budget: Budget,
+}
+
+enum State {
+    Eval(Rc<Term>, Env),
+    Return(Rc<Value>),
+}
+
+enum Frame {
+    /// `□ e2`
+    ApplyFun(Rc<Term>, Env, Option<Span>),
+    /// `(\x. e) □`
+    ApplyArg(Variable, Rc<Term>, Env),
+    /// `case □ of { branches }`
+    Case(Rc<Vec<Branch<Rc<Term>>>>, Env, Option<Span>),
+    /// `c(v1, ..., □, e1, ...)`, the next argument is the one after the computed values
+    Const(Constructor, Vec<Rc<Thunk>>, Rc<Vec<Rc<Term>>>, Env),
+    /// Store the value of a forced thunk, only used by call-by-need
+    Update(Rc<Thunk>),
+    /// The value should be fully evaluated, which means that the fields of a constructor must be forced.
+    /// Only used by the lazy strategies, to evaluate the final value
+    Normalize,
+    /// `c(v1, ..., □, t1, ...)` where the fields `t1, ...` are thunks that are forced one at a time.
+    /// All of the fields are stored, the next one to force is the one after the normalized values
+    NormalizeConst(Constructor, Vec<Rc<Thunk>>, Vec<Rc<Thunk>>),
+}
+
+impl Machine {
+    fn new(term: Rc<Term>, definitions: Vec<Rc<Term>>, strategy: Strategy, budget: Budget) -> Self {
+        let stack = match strategy {
+            Strategy::CallByValue => Vec::new(),
+            Strategy::CallByName | Strategy::CallByNeed => vec![Frame::Normalize],
+        };
+
+        Self {
+            state: Some(State::Eval(term, Env::default())),
+            stack,
+            definitions,
+            strategy,
+            budget,
+        }
+    }
+
+    fn run(mut self) -> Result<Rc<Value>, EvalError> {
+        loop {
+            if let Some(value) = self.step()? {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn push(&mut self, frame: Frame) -> Result<(), EvalError> {
+        self.budget.check_depth(self.stack.len())?;
+        self.stack.push(frame);
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<Option<Rc<Value>>, EvalError> {
+        let Some(state) = self.state.take() else {
+            panic!("The machine has already stopped");
+        };
+
+        self.state = Some(match state {
+            State::Eval(mut term, env) => {
+                let mut span = None;
+                while let Term::Located(s, e) = &*term {
+                    span = Some(s.clone());
+                    term = e.clone();
+                }
+                self.eval(term, env, span)?
+            }
+            State::Return(value) => match self.stack.pop() {
+                None => return Ok(Some(value)),
+                Some(frame) => self.ret(frame, value)?,
+            },
+        });
+
+        Ok(None)
+    }
+
+    fn eval(&mut self, term: Rc<Term>, env: Env, span: Option<Span>) -> Result<State, EvalError> {
+        Ok(match &*term {
+            Term::Apply(e1, e2) => {
+                self.push(Frame::ApplyFun(e2.clone(), env.clone(), span))?;
+                State::Eval(e1.clone(), env)
+            }
+            Term::Lambda(x, e) => State::Return(Rc::new(Value::Closure(x.clone(), e.clone(), env))),
+            Term::Case(e, branches) => {
+                self.push(Frame::Case(branches.clone(), env.clone(), span))?;
+                State::Eval(e.clone(), env)
+            }
+            Term::Rec(x, e) => self.unfold(x, e, env)?,
+            Term::Var(x) => match env.lookup(x) {
+                Some(Bound::Value(thunk)) => self.force(thunk.clone())?,
+                Some(Bound::Rec(x, e, rec_env)) => self.unfold(x, e, rec_env.clone())?,
+                None => {
+                    return Err(EvalError::UnboundVariable {
+                        variable: x.clone(),
+                        expr: Expr::Var(x.clone()),
+                        span,
+                    })
+                }
+            },
+            Term::Const(c, es) if self.strategy != Strategy::CallByValue => {
+                let fields = es
+                    .iter()
+                    .map(|e| Thunk::delayed(e.clone(), env.clone()))
+                    .collect();
+                State::Return(Rc::new(Value::Const(c.clone(), fields)))
+            }
+            Term::Const(c, es) => match es.first() {
+                Some(e) => {
+                    self.push(Frame::Const(c.clone(), Vec::new(), es.clone(), env.clone()))?;
+                    State::Eval(e.clone(), env)
+                }
+                None => State::Return(Rc::new(Value::constructor(c.clone(), Vec::new()))),
+            },
+            Term::Nat(n) => State::Return(Rc::new(Value::Nat(n.clone()))),
+            // Looking up a definition is not a reduction, just like in `eval`
+            Term::Global(_, index) => State::Eval(self.definitions[*index].clone(), Env::default()),
+            Term::Located(..) => unreachable!("locations are removed before evaluating"),
+        })
+    }
+
+    /// Continue with the value of `thunk`, evaluating it if needed
+    fn force(&mut self, thunk: Rc<Thunk>) -> Result<State, EvalError> {
+        let (term, env) = match &*thunk.0.borrow() {
+            ThunkState::Evaluated(value) => return Ok(State::Return(value.clone())),
+            ThunkState::Delayed(term, env) => (term.clone(), env.clone()),
+        };
+
+        if self.strategy == Strategy::CallByNeed {
+            self.push(Frame::Update(thunk))?;
+        }
+        Ok(State::Eval(term, env))
+    }
+
+    /// Normalize the next field of `c(fields)`, the first `normalized.len()` fields are already done
+    fn normalize_fields(
+        &mut self,
+        c: Constructor,
+        normalized: Vec<Rc<Thunk>>,
+        fields: Vec<Rc<Thunk>>,
+    ) -> Result<State, EvalError> {
+        let Some(next) = fields.get(normalized.len()).cloned() else {
+            return Ok(State::Return(Rc::new(Value::constructor(c, normalized))));
+        };
+
+        self.push(Frame::NormalizeConst(c, normalized, fields))?;
+        self.push(Frame::Normalize)?;
+        self.force(next)
+    }
+
+    /// `rec x = e` in `env` reduces to `e` with `x` bound to the recursion itself
+    fn unfold(&mut self, x: &Variable, e: &Rc<Term>, env: Env) -> Result<State, EvalError> {
+        self.budget.step()?;
+        let rec = Bound::Rec(x.clone(), e.clone(), env.clone());
+        let body_env = env.bind(x.clone(), rec);
+        Ok(State::Eval(e.clone(), body_env))
+    }
+
+    fn ret(&mut self, frame: Frame, value: Rc<Value>) -> Result<State, EvalError> {
+        Ok(match frame {
+            Frame::ApplyFun(e2, env, span) => {
+                let Value::Closure(x, e, closure_env) = &*value else {
+                    let value = readback(&value);
+                    return Err(EvalError::NotALambda {
+                        expr: Expr::Apply(
+                            Box::new(value.clone()),
+                            Box::new(term_to_expr(&e2, &env)),
+                        ),
+                        value,
+                        span,
+                    });
+                };
+                if self.strategy == Strategy::CallByValue {
+                    self.push(Frame::ApplyArg(x.clone(), e.clone(), closure_env.clone()))?;
+                    State::Eval(e2, env)
+                } else {
+                    self.budget.step()?;
+                    let argument = Bound::Value(Thunk::delayed(e2, env));
+                    State::Eval(e.clone(), closure_env.bind(x.clone(), argument))
+                }
+            }
+            Frame::ApplyArg(x, e, env) => {
+                self.budget.step()?;
+                State::Eval(e, env.bind(x, Bound::Value(Thunk::evaluated(value))))
+            }
+            Frame::Case(branches, env, span) => {
+                // The stuck case expression, for error messages
+                let stuck = || {
+                    Expr::Case(
+                        Box::new(readback(&value)),
+                        branches_to_expr(&branches, &env),
+                    )
+                };
+
+                let (constructor_name, values) = match &*value {
+                    Value::Const(c, values) => (c.clone(), values.clone()),
+                    // The number is unfolded once, like `Expr::unfold`
+                    Value::Nat(n) if *n == BigUint::default() => ("Zero".into(), Vec::new()),
+                    Value::Nat(n) => (
+                        "Suc".into(),
+                        vec![Thunk::evaluated(Rc::new(Value::Nat(n - 1u32)))],
+                    ),
+                    Value::Closure(..) => {
+                        return Err(EvalError::NotAConstructor {
+                            value: readback(&value),
+                            expr: stuck(),
+                            span,
+                        })
+                    }
+                };
+
+                let Some(Branch(_, xs, e)) =
+                    branches.iter().find(|Branch(c, ..)| *c == constructor_name)
+                else {
+                    return Err(EvalError::MissingBranch {
+                        constructor: constructor_name,
+                        available: branches.iter().map(|Branch(c, ..)| c.clone()).collect(),
+                        expr: stuck(),
+                        span,
+                    });
+                };
+
+                // Ensure that xs and values are the same arity
+                if xs.len() != values.len() {
+                    return Err(EvalError::ArityMismatch {
+                        constructor: constructor_name,
+                        expected: xs.len(),
+                        actual: values.len(),
+                        expr: stuck(),
+                        span,
+                    });
+                }
+
+                self.budget.step()?;
+                // Later variables shadow earlier ones with the same name, just like the substitution in `eval`
+                let env = xs.iter().zip(values).fold(env, |env, (x, value)| {
+                    env.bind(x.clone(), Bound::Value(value))
+                });
+                State::Eval(e.clone(), env)
+            }
+            Frame::Const(c, mut values, es, env) => {
+                values.push(Thunk::evaluated(value));
+                match es.get(values.len()) {
+                    Some(e) => {
+                        let e = e.clone();
+                        self.push(Frame::Const(c, values, es, env.clone()))?;
+                        State::Eval(e, env)
+                    }
+                    None => State::Return(Rc::new(Value::constructor(c, values))),
+                }
+            }
+            Frame::Update(thunk) => {
+                *thunk.0.borrow_mut() = ThunkState::Evaluated(value.clone());
+                State::Return(value)
+            }
+            Frame::Normalize => match &*value {
+                Value::Closure(..) | Value::Nat(_) => State::Return(value),
+                Value::Const(c, fields) => {
+                    self.normalize_fields(c.clone(), Vec::new(), fields.clone())?
+                }
+            },
+            Frame::NormalizeConst(c, mut normalized, fields) => {
+                normalized.push(Thunk::evaluated(value));
+                self.normalize_fields(c, normalized, fields)?
+            }
+        })
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Strategy::CallByValue => write!(f, "call-by-value"),
+            Strategy::CallByName => write!(f, "call-by-name"),
+            Strategy::CallByNeed => write!(f, "call-by-need"),
+        }
+    }
+}
@@ -0,0 +1,342 @@
+use crate::{
+    compare_strategies, eval, eval_env, eval_strategy,
+    parser::{Branch, Constructor, Variable},
+    test_utils::program,
+    Comparison, EvalError, EvalLimits, Expr, Program,
+    Strategy::{self, *},
+};
+
+// The evaluation depth is not comparable between the two evaluators
+fn limits() -> EvalLimits {
+    EvalLimits {
+        max_steps: Some(2_000),
+        ..EvalLimits::unlimited()
+    }
+}
+
+fn assert_same(program: Program<Expr>) {
+    assert_eq!(
+        eval(program.clone(), &limits()),
+        eval_env(program.clone(), &limits()),
+        "{program:?}"
+    );
+}
+
+#[test]
+fn sample_programs() {
+    let programs = [
+        "C() C()",
+        "rec x = x",
+        r"case \x. x of {}",
+        r"case C() of { C(x) -> C() }",
+        r"case C(C()) of { C() -> C(); C(x) -> x }",
+        r"case C() of { D() -> D() }",
+        r"case C(D(),E()) of { C(x, x) -> x }",
+        r"case C(\x.x, Zero()) of { C(f, x) -> f x }",
+        r"((\x.x)(\x.x))(\x.x)",
+        r"(\x. \x. x) A() B()",
+        r"(\x. \y. Pair(x, \z. Pair(y, z))) A() B()",
+        r"(\f. \x. f) (\y. case y of { A() -> B() }) C()",
+        r"(rec f = \x. case x of { A() -> f B(); B() -> C(f) }) A()",
+        r"(\x. undefined) A()",
+        r#""\x. x""#,
+        r#"
+        let add = rec add = \x. \y. case x of
+        { Zero() -> y
+        ; Suc(n) -> Suc(add n y)
+        };
+        let mul = rec mul = \x. \y. case x of
+        { Zero() -> Zero()
+        ; Suc(n) -> add y (mul n y)
+        };
+        let three = Suc(Suc(Suc(Zero())));
+        Pair(mul three three, \z. add three z)
+        "#,
+    ];
+
+    for src in programs {
+        assert_same(program(src));
+    }
+}
+
+/// A small deterministic pseudo random number generator (xorshift)
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+const VARIABLES: [&str; 3] = ["x", "y", "z"];
+const CONSTRUCTORS: [&str; 3] = ["A", "B", "C"];
+
+/// Generate a random closed expression, names are picked from small pools so that shadowing and
+/// mismatching constructor names and arities are common
+fn generate(rng: &mut Rng, depth: usize, scope: &mut Vec<Variable>) -> Expr {
+    let choice = if depth == 0 {
+        rng.below(2)
+    } else {
+        rng.below(7)
+    };
+    match choice {
+        0 if !scope.is_empty() => Expr::Var(scope[rng.below(scope.len())].clone()),
+        0 | 1 => Expr::Const(CONSTRUCTORS[rng.below(3)].into(), vec![]),
+        2 => {
+            let x = Variable::from(VARIABLES[rng.below(3)]);
+            scope.push(x.clone());
+            let e = generate(rng, depth - 1, scope);
+            scope.pop();
+            Expr::Lambda(x, Box::new(e))
+        }
+        3 | 4 => Expr::Apply(
+            Box::new(generate(rng, depth - 1, scope)),
+            Box::new(generate(rng, depth - 1, scope)),
+        ),
+        5 => {
+            let arity = rng.below(3);
+            Expr::Const(
+                Constructor::from(CONSTRUCTORS[rng.below(3)]),
+                (0..arity)
+                    .map(|_| generate(rng, depth - 1, scope))
+                    .collect(),
+            )
+        }
+        _ if rng.below(4) == 0 => {
+            let x = Variable::from(VARIABLES[rng.below(3)]);
+            scope.push(x.clone());
+            let e = generate(rng, depth - 1, scope);
+            scope.pop();
+            Expr::Rec(x, Box::new(e))
+        }
+        _ => {
+            let scrutinee = generate(rng, depth - 1, scope);
+            let branches = (0..1 + rng.below(3))
+                .map(|_| {
+                    let xs: Vec<Variable> = (0..rng.below(3))
+                        .map(|_| VARIABLES[rng.below(3)].into())
+                        .collect();
+                    let n = scope.len();
+                    scope.extend(xs.iter().cloned());
+                    let e = generate(rng, depth - 1, scope);
+                    scope.truncate(n);
+                    Branch(CONSTRUCTORS[rng.below(3)].into(), xs, e)
+                })
+                .collect();
+            Expr::Case(Box::new(scrutinee), branches)
+        }
+    }
+}
+
+#[test]
+fn generated_programs() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let (mut values, mut errors) = (0, 0);
+
+    for _ in 0..3_000 {
+        let expr = generate(&mut rng, 6, &mut Vec::new());
+        let program = Program::Expr(expr);
+        let expected = eval(program.clone(), &limits());
+        assert_eq!(
+            expected,
+            eval_env(program.clone(), &limits()),
+            "{program:?}"
+        );
+
+        match expected {
+            Ok(_) => values += 1,
+            Err(_) => errors += 1,
+        }
+    }
+
+    // Make sure that the corpus is not trivial
+    assert!(values > 300, "only {values} programs terminated");
+    assert!(errors > 300, "only {errors} programs failed");
+}
+
+#[test]
+fn lazy_arguments() {
+    let src = r"(\x. C()) (rec x = x)";
+    let Comparison::OnlySecond(EvalError::LimitExceeded(_), value) =
+        compare_strategies(program(src), CallByValue, CallByName, &limits())
+    else {
+        panic!("only call-by-name should terminate");
+    };
+    assert_eq!(Program::Expr(value), program("C()"));
+    assert!(!compare_strategies(program(src), CallByName, CallByNeed, &limits()).diverges());
+}
+
+#[test]
+fn lazy_constructor_fields() {
+    let src = r"case Pair(rec x = x, (\y. y) A()) of { Pair(x, y) -> Suc(y) }";
+    assert!(eval_strategy(program(src), CallByValue, &limits()).is_err());
+    for strategy in [CallByName, CallByNeed] {
+        // The fields of the final value are evaluated too
+        assert_eq!(
+            Program::Expr(eval_strategy(program(src), strategy, &limits()).unwrap()),
+            program("Suc(A())")
+        );
+    }
+}
+
+#[test]
+fn lazy_lambda_bodies() {
+    // The argument is not evaluated, since it is under a lambda in the final value
+    let src = r"(\x. \y. x) ((\z. z) C())";
+    let Comparison::DifferentValues(strict, lazy) =
+        compare_strategies(program(src), CallByValue, CallByName, &limits())
+    else {
+        panic!("the values should differ");
+    };
+    assert_eq!(Program::Expr(strict), program(r"\y. C()"));
+    assert_eq!(Program::Expr(lazy), program(r"\y. (\z. z) C()"));
+}
+
+/// The smallest number of reduction steps that the program needs to terminate using the strategy
+fn steps_needed(program: &Program<Expr>, strategy: Strategy) -> u64 {
+    (0..)
+        .find(|&max_steps| {
+            let limits = EvalLimits {
+                max_steps: Some(max_steps),
+                ..EvalLimits::unlimited()
+            };
+            eval_strategy(program.clone(), strategy, &limits).is_ok()
+        })
+        .unwrap()
+}
+
+#[test]
+fn call_by_need_shares_arguments() {
+    let src = r"
+        let add = rec add = \x. \y. case x of
+        { Zero() -> y
+        ; Suc(n) -> Suc(add n y)
+        };
+        let two = Suc(Suc(Zero()));
+        (\x. case x of { Zero() -> x; Suc(n) -> Pair(x, x) }) (add two two)
+        ";
+    let by_value = steps_needed(&program(src), CallByValue);
+    let by_name = steps_needed(&program(src), CallByName);
+    let by_need = steps_needed(&program(src), CallByNeed);
+    assert!(by_need < by_name, "{by_need} >= {by_name}");
+    assert!(by_value <= by_need, "{by_value} > {by_need}");
+    assert!(!compare_strategies(program(src), CallByValue, CallByNeed, &limits()).diverges());
+}
+
+fn contains_lambda(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lambda(..) => true,
+        Expr::Const(_, es) => es.iter().any(contains_lambda),
+        _ => false,
+    }
+}
+
+#[test]
+fn lazy_strategies_terminate_more_often() {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let lazy_limits = EvalLimits {
+        max_steps: Some(20_000),
+        ..EvalLimits::unlimited()
+    };
+    let mut only_lazy = 0;
+
+    for _ in 0..3_000 {
+        let program = Program::Expr(generate(&mut rng, 6, &mut Vec::new()));
+        let strict = eval_strategy(program.clone(), CallByValue, &limits());
+        let by_name = eval_strategy(program.clone(), CallByName, &lazy_limits);
+        let by_need = eval_strategy(program.clone(), CallByNeed, &lazy_limits);
+
+        // Call-by-name and call-by-need only differ in how much work is done
+        assert_eq!(by_name.is_ok(), by_need.is_ok(), "{program:?}");
+
+        match (strict, by_need) {
+            (Ok(v1), Ok(v2)) if !contains_lambda(&v1) => assert_eq!(v1, v2, "{program:?}"),
+            (Ok(_), Ok(_)) => {}
+            (Ok(_), Err(error)) => panic!("{program:?} failed lazily with {error}"),
+            (Err(_), Ok(_)) => only_lazy += 1,
+            (Err(_), Err(_)) => {}
+        }
+    }
+
+    assert!(only_lazy > 10, "only {only_lazy} programs needed laziness");
+}
+
+#[test]
+fn layered_definitions() {
+    // Substituting the definitions would create 2^40 copies of `twice0`
+    let mut src = r"let twice0 = \f. \x. f (f x);".to_string();
+    for i in 1..40 {
+        src += &format!(
+            r"let twice{i} = \f. \x. twice{} f (twice{} f x);",
+            i - 1,
+            i - 1
+        );
+    }
+    for expr in [r"twice1 (\x. S(x)) Z()", "twice39"] {
+        let program = program(&format!("{src} {expr}"));
+        for strategy in [CallByValue, CallByName, CallByNeed] {
+            assert_eq!(
+                eval_strategy(program.clone(), strategy, &limits()),
+                eval(program.clone(), &limits()),
+                "{strategy:?} {expr}"
+            );
+        }
+    }
+}
+
+#[test]
+fn compact_naturals() {
+    let programs = [
+        ("200000", 200_000u32),
+        (r"(\x. Suc(Suc(x))) 100000", 100_002),
+        (r"case 100000 of { Zero() -> Zero(); Suc(n) -> n }", 99_999),
+        (r"case Suc(0) of { Zero() -> 1; Suc(n) -> Suc(Suc(n)) }", 2),
+    ];
+    for (src, n) in programs {
+        for strategy in [CallByValue, CallByName, CallByNeed] {
+            assert_eq!(
+                eval_strategy(program(src), strategy, &limits()),
+                Ok(Expr::nat(n)),
+                "{src} {strategy}"
+            );
+        }
+        assert_same(program(src));
+    }
+}
+
+// Reading back and dropping these values must not overflow the stack, with any strategy
+#[test]
+fn deep_values() {
+    let mut list = Expr::Const(Constructor("Nil".into()), Vec::new());
+    for _ in 0..100_000 {
+        let a = Expr::Const(Constructor("A".into()), Vec::new());
+        list = Expr::Const(Constructor("Cons".into()), vec![a, list]);
+    }
+    let programs = [
+        ("20000", Expr::nat(20_000u32)),
+        (
+            r"let rec count n = case n of { Zero() -> Zero(); Suc(m) -> Suc(count m) }; count 100000",
+            Expr::nat(100_000u32),
+        ),
+        (
+            r"let rec build n = case n of { Zero() -> Nil(); Suc(m) -> Cons(A(), build m) }; build 100000",
+            list,
+        ),
+    ];
+    for (src, value) in programs {
+        for strategy in [CallByValue, CallByName, CallByNeed] {
+            assert!(
+                eval_strategy(program(src), strategy, &EvalLimits::unlimited())
+                    == Ok(value.clone()),
+                "{src} {strategy}"
+            );
+        }
+        assert!(matches!(
+            compare_strategies(program(src), CallByName, CallByNeed, &EvalLimits::unlimited()),
+            Comparison::SameValue(v) if v == value
+        ));
+    }
+}
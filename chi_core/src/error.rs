@@ -1,23 +1,122 @@
 use std::fmt;
 
-// TODO: Replace the error newtype with an enum that also holds the source position of the error
-#[derive(Debug)]
-pub struct Error(pub(crate) String);
+use crate::{
+    coder::SYNTAX_ERROR,
+    eval::Limit,
+    parser::{Constructor, Span, Variable},
+    pretty, Expr,
+};
 
-impl From<&'static str> for Error {
-    fn from(value: &'static str) -> Self {
-        Error(value.to_string())
-    }
+/// The reasons for why the evaluation of an expression can fail.
+/// Each variant carries the subterm that got stuck, with its already evaluated parts replaced by their values,
+/// and the source location of that subterm if it is known.
+#[derive(Debug, PartialEq, Clone)]
+pub enum EvalError {
+    /// The left-hand side of an application evaluated to `value`, which is not a lambda
+    NotALambda {
+        value: Expr,
+        expr: Expr,
+        span: Option<Span>,
+    },
+    /// The scrutinee of a case expression evaluated to `value`, which is not a constructor application
+    NotAConstructor {
+        value: Expr,
+        expr: Expr,
+        span: Option<Span>,
+    },
+    /// A case expression has no branch for the `constructor` of the scrutinee
+    MissingBranch {
+        constructor: Constructor,
+        available: Vec<Constructor>,
+        expr: Expr,
+        span: Option<Span>,
+    },
+    /// The matching branch binds `expected` variables, but the constructor was applied to `actual` arguments
+    ArityMismatch {
+        constructor: Constructor,
+        expected: usize,
+        actual: usize,
+        expr: Expr,
+        span: Option<Span>,
+    },
+    /// The program is not closed
+    UnboundVariable {
+        variable: Variable,
+        expr: Expr,
+        span: Option<Span>,
+    },
+    LimitExceeded(Limit),
 }
 
-impl From<String> for Error {
-    fn from(value: String) -> Self {
-        Error(value)
+impl EvalError {
+    /// The subterm that could not be evaluated further
+    pub fn expr(&self) -> Option<&Expr> {
+        match self {
+            EvalError::NotALambda { expr, .. }
+            | EvalError::NotAConstructor { expr, .. }
+            | EvalError::MissingBranch { expr, .. }
+            | EvalError::ArityMismatch { expr, .. }
+            | EvalError::UnboundVariable { expr, .. } => Some(expr),
+            EvalError::LimitExceeded(_) => None,
+        }
+    }
+
+    /// Where in the source the stuck subterm comes from
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            EvalError::NotALambda { span, .. }
+            | EvalError::NotAConstructor { span, .. }
+            | EvalError::MissingBranch { span, .. }
+            | EvalError::ArityMismatch { span, .. }
+            | EvalError::UnboundVariable { span, .. } => span.as_ref(),
+            EvalError::LimitExceeded(_) => None,
+        }
     }
 }
 
-impl fmt::Display for Error {
+impl fmt::Display for EvalError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
+        match self {
+            EvalError::NotALambda { value, .. } => write!(
+                f,
+                "LHS of application must be a lambda expression, found {}",
+                pretty::message(value)
+            ),
+            EvalError::NotAConstructor { value, .. } => write!(
+                f,
+                "Expected constructor in case expression, found {}",
+                pretty::message(value)
+            ),
+            EvalError::MissingBranch {
+                constructor,
+                available,
+                ..
+            } => {
+                let available: Vec<String> = available.iter().map(|c| c.to_string()).collect();
+                write!(
+                    f,
+                    "No matching constructor name, there is no branch for {constructor} (only for {})",
+                    available.join(", ")
+                )
+            }
+            EvalError::ArityMismatch {
+                constructor,
+                expected,
+                actual,
+                ..
+            } => write!(
+                f,
+                "Constructor application in branch has wrong arity, the branch for {constructor} binds {expected} variable(s) but {actual} argument(s) were given"
+            ),
+            EvalError::UnboundVariable { variable, .. } if variable.0 == SYNTAX_ERROR => write!(
+                f,
+                "This part of the program could not be parsed, so it can not be evaluated"
+            ),
+            EvalError::UnboundVariable { variable, .. } => write!(
+                f,
+                "Not a closed expression, variable '{variable}' is not bound."
+            ),
+            EvalError::LimitExceeded(limit) => write!(f, "{limit}"),
+        }
     }
 }
@@ -1,12 +1,24 @@
 /// An interpreter for Chi.
 /// Based on "Models of Computation: Section 6, An interpreter for χ in χ", by Bengt Nordström and Nils Anders Danielsson
 /// and also the Agda specification: https://www.cse.chalmers.se/~nad/listings/chi/Chi.html
+use std::{
+    collections::HashMap,
+    fmt, mem,
+    time::{Duration, Instant},
+};
+
+use num_bigint::BigUint;
+
 use crate::{
-    parser::{Branch, Constructor, Variable},
-    Error, Program,
+    derivation::Recorder,
+    normalize::free_variables,
+    parser::{Branch, Constructor, Span, Variable},
+    Derivation, EvalError, Program, Rule,
 };
 
-#[derive(Debug, PartialEq, Clone)]
+/// Expressions can be very deeply nested (a long list is a chain of `Cons`), so cloning, comparing and dropping them
+/// uses an explicit stack instead of recursion
+#[derive(Debug)]
 pub enum Expr {
     Apply(Box<Self>, Box<Self>),
     Lambda(Variable, Box<Self>),
@@ -14,12 +26,453 @@ pub enum Expr {
     Rec(Variable, Box<Self>),
     Var(Variable),
     Const(Constructor, Vec<Self>),
+    /// The expression comes from `span` in the source, used to locate runtime errors.
+    /// Locations are ignored when comparing expressions, and they are removed from the values computed by `eval`
+    Located(Span, Box<Self>),
+    /// The natural number `Suc(Suc(...Zero()))` with `n` `Suc`s, stored as a count so that large numbers are cheap to
+    /// copy and substitute. It behaves exactly like the constructor tree, the evaluator creates it whenever it builds
+    /// `Zero()` or applies `Suc` to a compact number
+    Nat(BigUint),
+    /// A reference to the top-level `let` binding of `x` with the given index, see `Definitions`.
+    /// The evaluators look up the definition when they reach the reference. The values they return may still contain
+    /// references (in the bodies of lambdas and case branches), which are printed as the name `x`
+    Global(Variable, usize),
 }
 
 use Expr::*;
 
-// NOTE: This is of course a very low limit, I should make it possible to set this dynamically when calling eval
-const MAX_DEPTH: u32 = 250;
+/// Bounds on the amount of work `eval` is allowed to do before the expression is assumed to not terminate.
+/// A limit set to `None` is not checked at all.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalLimits {
+    /// Maximum number of reductions (beta-reductions, case reductions and `rec` unfoldings)
+    pub max_steps: Option<u64>,
+    /// Maximum nesting depth of the evaluation, i.e. the number of pending evaluation contexts
+    pub max_depth: Option<usize>,
+    /// Maximum size (number of nodes) of any intermediate term
+    pub max_size: Option<usize>,
+    /// Maximum wall-clock time.
+    /// Note: `std::time::Instant` is not available on `wasm32-unknown-unknown`, so leave this unset in the browser
+    pub timeout: Option<Duration>,
+}
+
+impl Default for EvalLimits {
+    fn default() -> Self {
+        Self {
+            max_steps: Some(1_000_000),
+            max_depth: Some(100_000),
+            max_size: None,
+            timeout: None,
+        }
+    }
+}
+
+impl EvalLimits {
+    /// No limits at all, evaluation will only stop once a value is reached (or memory runs out)
+    pub fn unlimited() -> Self {
+        Self {
+            max_steps: None,
+            max_depth: None,
+            max_size: None,
+            timeout: None,
+        }
+    }
+}
+
+/// The limit that made the evaluation stop
+#[derive(Debug, Clone, PartialEq)]
+pub enum Limit {
+    Steps(u64),
+    Depth(usize),
+    Size(usize),
+    Time(Duration),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Steps(n) => write!(f, "Exceeded the maximum number of reduction steps ({n})"),
+            Limit::Depth(n) => write!(f, "Exceeded the maximum evaluation depth ({n})"),
+            Limit::Size(n) => write!(f, "Exceeded the maximum term size ({n} nodes)"),
+            Limit::Time(t) => write!(f, "Exceeded the time limit ({} ms)", t.as_millis()),
+        }?;
+        write!(f, ", expression is assumed to not terminate")
+    }
+}
+
+/// How much work an evaluation did, see `eval_with_stats`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EvalStats {
+    /// Number of `(\x. e) v` reductions
+    pub beta_reductions: u64,
+    /// Number of `case c(vs) of {...}` reductions
+    pub case_reductions: u64,
+    /// Number of times a `rec x = e` was unfolded
+    pub rec_unfoldings: u64,
+    /// Number of single variable substitutions `e[x := v]`, a case reduction substitutes each bound variable separately
+    pub substitutions: u64,
+    /// The largest number of pending evaluation contexts at any point
+    pub max_depth: usize,
+    /// The largest size (number of nodes) of the program or of any term produced by a reduction
+    pub max_size: usize,
+}
+
+impl EvalStats {
+    /// The total number of reductions, which is what `EvalLimits::max_steps` limits
+    pub fn reductions(&self) -> u64 {
+        self.beta_reductions + self.case_reductions + self.rec_unfoldings
+    }
+
+    fn record(&mut self, rule: Rule, substitutions: usize, expr: &Expr) {
+        match rule {
+            Rule::Apply => self.beta_reductions += 1,
+            Rule::Case => self.case_reductions += 1,
+            Rule::Rec => self.rec_unfoldings += 1,
+            Rule::Lookup => unreachable!("looking up a definition is not a reduction"),
+        }
+        self.substitutions += substitutions as u64;
+        self.max_size = self.max_size.max(expr.size());
+    }
+}
+
+impl fmt::Display for EvalStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "reductions: {}", self.reductions())?;
+        writeln!(f, "  beta-reductions: {}", self.beta_reductions)?;
+        writeln!(f, "  case reductions: {}", self.case_reductions)?;
+        writeln!(f, "  rec unfoldings: {}", self.rec_unfoldings)?;
+        writeln!(f, "substitutions: {}", self.substitutions)?;
+        writeln!(f, "maximum depth: {}", self.max_depth)?;
+        write!(f, "largest term: {} nodes", self.max_size)
+    }
+}
+
+impl Expr {
+    /// The number of nodes in the expression, not counting locations
+    pub fn size(&self) -> usize {
+        let mut size: usize = 0;
+        let mut todo = vec![self];
+
+        while let Some(e) = todo.pop() {
+            let e = e.unlocated();
+            size += 1;
+            match e {
+                Apply(e1, e2) => {
+                    todo.push(e1);
+                    todo.push(e2);
+                }
+                Lambda(_, e) | Rec(_, e) => todo.push(e),
+                Case(e, branches) => {
+                    todo.push(e);
+                    todo.extend(branches.iter().map(|Branch(_, _, e)| e));
+                }
+                Var(_) | Global(..) => {}
+                Const(_, es) => todo.extend(es),
+                // The `Suc`s besides this node
+                Nat(n) => size = size.saturating_add(usize::try_from(n).unwrap_or(usize::MAX)),
+                Located(..) => unreachable!(),
+            }
+        }
+
+        size
+    }
+
+    /// The expression without its outermost locations
+    pub fn unlocated(&self) -> &Self {
+        let mut expr = self;
+        while let Located(_, e) = expr {
+            expr = e;
+        }
+        expr
+    }
+
+    /// The innermost source location of the outermost node
+    pub fn span(&self) -> Option<&Span> {
+        let mut span = None;
+        let mut expr = self;
+        while let Located(s, e) = expr {
+            span = Some(s);
+            expr = e;
+        }
+        span
+    }
+
+    /// Remove all locations from the expression
+    pub fn without_spans(mut self) -> Self {
+        let mut todo = vec![&mut self];
+
+        while let Some(e) = todo.pop() {
+            while let Located(_, inner) = e {
+                *e = mem::replace(&mut **inner, Var(Variable(String::new())));
+            }
+
+            match e {
+                Apply(e1, e2) => {
+                    todo.push(e1);
+                    todo.push(e2);
+                }
+                Lambda(_, e) | Rec(_, e) => todo.push(e),
+                Case(e, branches) => {
+                    todo.push(e);
+                    todo.extend(branches.iter_mut().map(|Branch(_, _, e)| e));
+                }
+                Var(_) | Nat(_) | Global(..) => {}
+                Const(_, es) => todo.extend(es.iter_mut()),
+                Located(..) => unreachable!(),
+            }
+        }
+
+        self
+    }
+
+    /// The natural number `n` in the compact representation
+    pub fn nat(n: impl Into<BigUint>) -> Self {
+        Nat(n.into())
+    }
+
+    /// The constructor application `c(es)`, using the compact representation if it is `Zero()` or `Suc` of a compact number
+    pub fn constructor(c: Constructor, mut es: Vec<Self>) -> Self {
+        match (c.0.as_str(), es.as_mut_slice()) {
+            ("Zero", []) => Nat(BigUint::default()),
+            ("Suc", [Nat(n)]) => Nat(mem::take(n) + 1u32),
+            _ => Const(c, es),
+        }
+    }
+
+    /// Expose the outermost constructor of a compact natural number, `0` becomes `Zero()` and `n + 1` becomes `Suc(n)`.
+    /// Any other expression is returned as it is
+    pub fn unfold(mut self) -> Self {
+        match &mut self {
+            Nat(n) if *n == BigUint::default() => Const("Zero".into(), Vec::new()),
+            Nat(n) => Const("Suc".into(), vec![Nat(mem::take(n) - 1u32)]),
+            _ => self,
+        }
+    }
+
+    /// Move the expression out, leaving a variable behind.
+    /// Since `Expr` implements `Drop`, this is how the subexpressions are taken apart
+    pub(crate) fn take(&mut self) -> Self {
+        mem::replace(self, Var(Variable(String::new())))
+    }
+
+    /// Does the expression have no subexpressions
+    fn is_leaf(&self) -> bool {
+        match self {
+            Var(_) | Nat(_) | Global(..) => true,
+            Const(_, es) => es.is_empty(),
+            Apply(..) | Lambda(..) | Case(..) | Rec(..) | Located(..) => false,
+        }
+    }
+
+    fn for_each_subexpression(&mut self, mut f: impl FnMut(&mut Expr)) {
+        match self {
+            Apply(e1, e2) => {
+                f(e1);
+                f(e2);
+            }
+            Lambda(_, e) | Rec(_, e) | Located(_, e) => f(e),
+            Case(e, branches) => {
+                f(e);
+                branches.iter_mut().for_each(|Branch(_, _, e)| f(e));
+            }
+            Const(_, es) => es.iter_mut().for_each(f),
+            Var(_) | Nat(_) | Global(..) => {}
+        }
+    }
+
+    /// Drop the subexpressions, recursing at most `depth` levels before switching to an explicit stack
+    fn drop_subexpressions(&mut self, depth: usize) {
+        if depth > 0 {
+            self.for_each_subexpression(|e| {
+                if !e.is_leaf() {
+                    e.take().drop_subexpressions(depth - 1);
+                }
+            });
+            return;
+        }
+
+        // The expressions are dropped one at a time, after their subexpressions have been moved out
+        let mut todo = vec![self.take()];
+        while let Some(mut e) = todo.pop() {
+            e.for_each_subexpression(|e| {
+                if !e.is_leaf() {
+                    todo.push(e.take());
+                }
+            });
+        }
+    }
+
+    /// Clone the expression, recursing at most `depth` levels before switching to an explicit stack
+    fn clone_within(&self, depth: usize) -> Self {
+        if depth == 0 {
+            return self.clone_iteratively();
+        }
+
+        let clone = |e: &Expr| e.clone_within(depth - 1);
+        match self {
+            Apply(e1, e2) => Apply(Box::new(clone(e1)), Box::new(clone(e2))),
+            Lambda(x, e) => Lambda(x.clone(), Box::new(clone(e))),
+            Case(e, branches) => Case(
+                Box::new(clone(e)),
+                branches
+                    .iter()
+                    .map(|Branch(c, xs, e)| Branch(c.clone(), xs.clone(), clone(e)))
+                    .collect(),
+            ),
+            Rec(x, e) => Rec(x.clone(), Box::new(clone(e))),
+            Var(x) => Var(x.clone()),
+            Const(c, es) => Const(c.clone(), es.iter().map(clone).collect()),
+            Located(span, e) => Located(span.clone(), Box::new(clone(e))),
+            Nat(n) => Nat(n.clone()),
+            Global(x, index) => Global(x.clone(), *index),
+        }
+    }
+
+    fn clone_iteratively(&self) -> Self {
+        /// The expressions are cloned in post-order, a node is built once its subexpressions have been cloned
+        enum Task<'a> {
+            Clone(&'a Expr),
+            Build(&'a Expr),
+        }
+
+        let mut tasks = vec![Task::Clone(self)];
+        let mut cloned: Vec<Expr> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Clone(e) => {
+                    let leaf = match e {
+                        Var(x) => Var(x.clone()),
+                        Nat(n) => Nat(n.clone()),
+                        Global(x, index) => Global(x.clone(), *index),
+                        _ => {
+                            tasks.push(Task::Build(e));
+                            // The first subexpression is cloned first
+                            match e {
+                                Apply(e1, e2) => {
+                                    tasks.push(Task::Clone(e2));
+                                    tasks.push(Task::Clone(e1));
+                                }
+                                Lambda(_, e) | Rec(_, e) | Located(_, e) => {
+                                    tasks.push(Task::Clone(e))
+                                }
+                                Case(e, branches) => {
+                                    tasks.extend(
+                                        branches.iter().rev().map(|Branch(_, _, e)| Task::Clone(e)),
+                                    );
+                                    tasks.push(Task::Clone(e));
+                                }
+                                Const(_, es) => tasks.extend(es.iter().rev().map(Task::Clone)),
+                                Var(_) | Nat(_) | Global(..) => unreachable!(),
+                            }
+                            continue;
+                        }
+                    };
+                    cloned.push(leaf);
+                }
+                Task::Build(e) => {
+                    let mut pop = || Box::new(cloned.pop().unwrap());
+                    let node = match e {
+                        Apply(..) => {
+                            let e2 = pop();
+                            Apply(pop(), e2)
+                        }
+                        Lambda(x, _) => Lambda(x.clone(), pop()),
+                        Rec(x, _) => Rec(x.clone(), pop()),
+                        Located(span, _) => Located(span.clone(), pop()),
+                        Case(_, branches) => {
+                            let bodies = cloned.split_off(cloned.len() - branches.len());
+                            let e = Box::new(cloned.pop().unwrap());
+                            let branches = branches
+                                .iter()
+                                .zip(bodies)
+                                .map(|(Branch(c, xs, _), e)| Branch(c.clone(), xs.clone(), e))
+                                .collect();
+                            Case(e, branches)
+                        }
+                        Const(c, es) => Const(c.clone(), cloned.split_off(cloned.len() - es.len())),
+                        Var(_) | Nat(_) | Global(..) => unreachable!(),
+                    };
+                    cloned.push(node);
+                }
+            }
+        }
+
+        cloned.pop().unwrap()
+    }
+}
+
+/// How many levels of an expression `Clone` and `Drop` handle by recursion, the deeper levels use an explicit stack
+const RECURSION_DEPTH: usize = 64;
+
+impl Drop for Expr {
+    fn drop(&mut self) {
+        self.drop_subexpressions(RECURSION_DEPTH);
+    }
+}
+
+impl Clone for Expr {
+    fn clone(&self) -> Self {
+        self.clone_within(RECURSION_DEPTH)
+    }
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        // The pairs of subexpressions that remain to be compared
+        let mut todo = vec![(self, other)];
+        while let Some((e1, e2)) = todo.pop() {
+            let equal = match (e1.unlocated(), e2.unlocated()) {
+                (Apply(a1, b1), Apply(a2, b2)) => {
+                    todo.extend([(&**a1, &**a2), (&**b1, &**b2)]);
+                    true
+                }
+                (Lambda(x1, e1), Lambda(x2, e2)) | (Rec(x1, e1), Rec(x2, e2)) => {
+                    todo.push((e1, e2));
+                    x1 == x2
+                }
+                (Case(e1, branches1), Case(e2, branches2)) => {
+                    todo.push((e1, e2));
+                    branches1.len() == branches2.len()
+                        && branches1.iter().zip(branches2).all(
+                            |(Branch(c1, xs1, e1), Branch(c2, xs2, e2))| {
+                                todo.push((e1, e2));
+                                c1 == c2 && xs1 == xs2
+                            },
+                        )
+                }
+                (Var(x1), Var(x2)) => x1 == x2,
+                (Const(c1, es1), Const(c2, es2)) => {
+                    todo.extend(es1.iter().zip(es2));
+                    c1 == c2 && es1.len() == es2.len()
+                }
+                (Nat(n1), Nat(n2)) => n1 == n2,
+                (Global(x1, i1), Global(x2, i2)) => x1 == x2 && i1 == i2,
+                (Nat(n), e @ Const(..)) | (e @ Const(..), Nat(n)) => is_natural(n, e),
+                _ => false,
+            };
+            if !equal {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Is `expr` the natural number `n`, as a constructor tree or a compact number
+fn is_natural(n: &BigUint, mut expr: &Expr) -> bool {
+    let mut n = n.clone();
+    loop {
+        match expr.unlocated() {
+            Nat(m) => return *m == n,
+            Const(c, es) if c.0 == "Zero" && es.is_empty() => return n == BigUint::default(),
+            Const(c, es) if c.0 == "Suc" && es.len() == 1 && n != BigUint::default() => {
+                n -= 1u32;
+                expr = &es[0];
+            }
+            _ => return false,
+        }
+    }
+}
 
 fn lookup(const_name: &Constructor, branches: &[Branch<Expr>]) -> Option<Branch<Expr>> {
     branches
@@ -28,131 +481,527 @@ fn lookup(const_name: &Constructor, branches: &[Branch<Expr>]) -> Option<Branch<
         .cloned()
 }
 
-pub fn substitute(var: &Variable, replacement: &Expr, expr: Expr) -> Expr {
-    match expr {
-        Apply(e1, e2) => Apply(
-            Box::new(substitute(var, replacement, *e1)),
-            Box::new(substitute(var, replacement, *e2)),
-        ),
-        Lambda(x, e) => {
-            let e = if x == *var {
-                // The name `var` is bound in this expression, so we stop substituting
-                e
-            } else {
-                Box::new(substitute(var, replacement, *e))
-            };
-
-            Lambda(x, e)
-        }
-        Case(e, branches) => Case(
-            Box::new(substitute(var, replacement, *e)),
-            branches
-                .into_iter()
-                .map(|b| substitute_branch(var, replacement, b))
-                .collect(),
-        ),
-        Rec(x, e) => {
-            let e = if x == *var {
-                // The name `var` is bound in this expression, so we stop substituting
-                e
-            } else {
-                Box::new(substitute(var, replacement, *e))
-            };
+/// Replace the free occurrences of `var` in `expr` with `replacement`.
+/// Note: this is not capture-avoiding, which is fine as long as `replacement` is closed.
+///
+/// The expression is traversed using an explicit work list rather than recursion,
+/// since it may contain very deep constructor trees (e.g. large natural numbers).
+pub fn substitute(var: &Variable, replacement: &Expr, mut expr: Expr) -> Expr {
+    let mut todo = vec![&mut expr];
 
-            Rec(x, e)
+    while let Some(e) = todo.pop() {
+        if let Var(x) = &*e {
+            if x == var {
+                *e = replacement.clone();
+            }
+            continue;
         }
-        Var(x) => {
-            if x == *var {
-                replacement.clone()
-            } else {
-                Var(x)
+
+        match e {
+            Apply(e1, e2) => {
+                todo.push(e1);
+                todo.push(e2);
+            }
+            // If the name `var` is bound in this expression, we stop substituting
+            Lambda(x, e) | Rec(x, e) => {
+                if x != var {
+                    todo.push(e);
+                }
+            }
+            Case(e, branches) => {
+                todo.push(e);
+                for Branch(_, xs, e) in branches {
+                    if !xs.contains(var) {
+                        todo.push(e);
+                    }
+                }
             }
+            Const(_, es) => todo.extend(es.iter_mut()),
+            Located(_, e) => todo.push(e),
+            // References to definitions have no free variables
+            Nat(_) | Global(..) => {}
+            Var(_) => unreachable!(),
         }
-        Const(c, es) => Const(
-            c,
-            es.into_iter()
-                .map(|e| substitute(var, replacement, e))
-                .collect(),
-        ),
     }
+
+    expr
 }
 
-fn substitute_branch(
-    var: &Variable,
-    replacement: &Expr,
-    Branch(c, xs, e): Branch<Expr>,
-) -> Branch<Expr> {
-    // Check if the branch binds to the same variable name, if not we recursivly continue with the substitution
-    if xs.contains(&var) {
-        Branch(c, xs, e)
-    } else {
-        Branch(c, xs, substitute(var, replacement, e))
+/// Select the branch of a case expression that matches the value `c(es)`, and substitute the arguments into it.
+/// `span` is the location of the case expression
+pub(crate) fn select_branch(
+    c: &Constructor,
+    es: &[Expr],
+    branches: &[Branch<Expr>],
+    span: Option<&Span>,
+) -> Result<Expr, EvalError> {
+    let stuck = || Case(Box::new(Const(c.clone(), es.to_vec())), branches.to_vec());
+
+    let Some(Branch(_, xs, e)) = lookup(c, branches) else {
+        return Err(EvalError::MissingBranch {
+            constructor: c.clone(),
+            available: branches.iter().map(|Branch(c, ..)| c.clone()).collect(),
+            expr: stuck(),
+            span: span.cloned(),
+        });
+    };
+
+    // Ensure that xs and es are the same arity
+    if xs.len() != es.len() {
+        return Err(EvalError::ArityMismatch {
+            constructor: c.clone(),
+            expected: xs.len(),
+            actual: es.len(),
+            expr: stuck(),
+            span: span.cloned(),
+        });
     }
+
+    Ok(xs
+        .iter()
+        .zip(es)
+        .rfold(e, |e, (var, replacement)| substitute(var, replacement, e)))
 }
 
-// Convert a meta program into a single Chi expression using substitution
-fn substitute_program(var: &Variable, replacement: &Expr, program: Program<Expr>) -> Program<Expr> {
-    match program {
-        Program::Let(x, rhs, rest) => Program::Let(
-            x,
-            substitute(&var, replacement, rhs),
-            Box::new(substitute_program(&var, replacement, *rest)),
-        ),
-        Program::Expr(expr) => Program::Expr(substitute(var, replacement, expr)),
+/// The right-hand sides of the top-level `let` bindings of a program, in order.
+/// Instead of substituting every binding into the later ones, which copies the definitions over and over,
+/// the variables that refer to a binding are replaced by an `Expr::Global` reference to it.
+/// The scoping is the same as for substitution: a binding sees the earlier ones, and the latest binding of a name wins
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Definitions(Vec<Expr>);
+
+impl Definitions {
+    /// Split a program into its definitions and its final expression, with the references to definitions resolved
+    pub(crate) fn new(mut program: Program<Expr>) -> (Self, Expr) {
+        let mut definitions = Vec::new();
+        // The index of the latest binding of each name
+        let mut scope = HashMap::new();
+
+        loop {
+            match program {
+                Program::Let(_, x, rhs, rest) => {
+                    definitions.push(resolve(&scope, rhs));
+                    scope.insert(x, definitions.len() - 1);
+                    program = *rest;
+                }
+                Program::Expr(expr) => return (Self(definitions), resolve(&scope, expr)),
+            }
+        }
     }
+
+    /// The right-hand side of the binding with the given index
+    pub(crate) fn get(&self, index: usize) -> &Expr {
+        &self.0[index]
+    }
+
+    /// The right-hand sides, in order
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Expr> {
+        self.0.iter()
+    }
+
+    /// The definitions without their locations
+    pub(crate) fn without_spans(self) -> Self {
+        Self(self.0.into_iter().map(Expr::without_spans).collect())
+    }
+}
+
+/// Replace the free variables of `expr` that are bound in `scope` with references to their definitions
+fn resolve(scope: &HashMap<Variable, usize>, expr: Expr) -> Expr {
+    free_variables(&expr)
+        .into_iter()
+        .filter_map(|x| scope.get(&x).map(|index| (x, *index)))
+        .fold(expr, |expr, (x, index)| {
+            substitute(&x, &Global(x.clone(), index), expr)
+        })
 }
 
-fn program_to_expr(program: Program<Expr>) -> Expr {
-    match program {
-        Program::Let(var, rhs, rest) => program_to_expr(substitute_program(&var, &rhs, *rest)),
-        Program::Expr(expr) => expr,
+/// Evaluate a program using the call-by-value big-step semantics of Chi,
+/// giving up with an `EvalError::LimitExceeded` if any of the `limits` are reached
+pub fn eval(program: Program<Expr>, limits: &EvalLimits) -> Result<Expr, EvalError> {
+    let (definitions, expr) = Definitions::new(program);
+    let budget = Budget::new(limits);
+    budget.check_size(&expr)?;
+    Ok(Machine::new(definitions, expr, budget)
+        .run()?
+        .without_spans())
+}
+
+/// Evaluate a program like `eval`, but also count the reductions and measure the depth and the term sizes.
+/// Note: this computes the size of every intermediate term, so it is slower than `eval`.
+pub fn eval_with_stats(
+    program: Program<Expr>,
+    limits: &EvalLimits,
+) -> Result<(Expr, EvalStats), EvalError> {
+    let mut evaluator = Evaluator::with_stats(program, limits);
+    evaluator.step(u64::MAX);
+    let stats = evaluator.stats().cloned().unwrap_or_default();
+
+    match evaluator.status {
+        Status::Finished(value) => Ok((value, stats)),
+        Status::Failed(error) => Err(error),
+        Status::Running => {
+            unreachable!("the evaluation is not limited by the number of transitions")
+        }
     }
 }
 
-pub fn eval(program: Program<Expr>) -> Result<Expr, Error> {
-    let expr = program_to_expr(program);
-    eval_expr(expr, 0)
+/// The progress of an `Evaluator`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    Running,
+    Finished(Expr),
+    Failed(EvalError),
+}
+
+/// An evaluation that can be advanced a few steps at a time, so that a user interface does not have to block
+/// until the program is done. It computes the same result as `eval`.
+pub struct Evaluator {
+    /// `None` if the evaluation failed before it started
+    machine: Option<Machine>,
+    status: Status,
 }
 
-fn eval_expr(expr: Expr, depth: u32) -> Result<Expr, Error> {
-    if depth >= MAX_DEPTH {
-        return Err("Exceeded max depth, expression is assumed to not terminate".into());
+impl Evaluator {
+    pub fn new(program: Program<Expr>, limits: &EvalLimits) -> Self {
+        Self::start(program, limits, false)
     }
 
-    match expr {
-        Apply(e1, e2) => {
-            let Lambda(x, e) = eval_expr(*e1, depth + 1)? else {
-                return Err("LHS of application must be a lambda expression".into());
+    /// Start an evaluation that also collects statistics, like `eval_with_stats`
+    pub fn with_stats(program: Program<Expr>, limits: &EvalLimits) -> Self {
+        Self::start(program, limits, true)
+    }
+
+    fn start(program: Program<Expr>, limits: &EvalLimits, collect_stats: bool) -> Self {
+        let (definitions, expr) = Definitions::new(program);
+        let budget = Budget::new(limits);
+        if let Err(error) = budget.check_size(&expr) {
+            return Self {
+                machine: None,
+                status: Status::Failed(error),
             };
-            eval_expr(substitute(&x, &eval_expr(*e2, depth + 1)?, *e), depth + 1)
         }
-        Lambda(..) => Ok(expr),
-        Case(e, branches) => {
-            let Const(constructor_name, es) = eval_expr(*e, depth + 1)? else {
-                return Err("Expected constructor in case expression".into());
-            };
 
-            let Some(Branch(_, xs, e)) = lookup(&constructor_name, &branches) else {
-                return Err("No matching constructor name".into());
-            };
+        let stats = collect_stats.then(|| EvalStats {
+            max_size: expr.size(),
+            ..EvalStats::default()
+        });
+        let mut machine = Machine::new(definitions, expr, budget);
+        machine.stats = stats;
+
+        Self {
+            machine: Some(machine),
+            status: Status::Running,
+        }
+    }
 
-            // Ensure that xs and es are the same arity
-            if xs.len() != es.len() {
-                return Err("Constructor application in branch has wrong arity".into());
+    /// Perform at most `n` transitions of the underlying machine, and return the new status.
+    /// A transition does a bounded amount of work, besides the substitution of a reduction.
+    pub fn step(&mut self, n: u64) -> &Status {
+        if let (Status::Running, Some(machine)) = (&self.status, &mut self.machine) {
+            for _ in 0..n {
+                match machine.step() {
+                    Ok(None) => {}
+                    Ok(Some(value)) => {
+                        self.status = Status::Finished(value.without_spans());
+                        break;
+                    }
+                    Err(error) => {
+                        self.status = Status::Failed(error);
+                        break;
+                    }
+                }
             }
+        }
+
+        &self.status
+    }
+
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    /// The statistics so far, if they are collected
+    pub fn stats(&self) -> Option<&EvalStats> {
+        self.machine.as_ref()?.stats.as_ref()
+    }
+}
 
-            let subst_expr = xs
-                .iter()
-                .zip(es)
-                .rfold(e, |e, (var, replacement)| substitute(var, &replacement, e));
+/// Evaluate a program like `eval`, but also record the derivation tree of `e ⇓ v`, with one node per rule instance.
+/// Note: the derivation stores every intermediate term, so it can be much larger than the program or its value.
+pub fn eval_derivation(
+    program: Program<Expr>,
+    limits: &EvalLimits,
+) -> Result<Derivation, EvalError> {
+    // The references to definitions in the terms of the derivation are printed by name, looking them up is not a rule
+    let (definitions, expr) = Definitions::new(program);
+    let budget = Budget::new(limits);
+    budget.check_size(&expr)?;
+    let mut machine = Machine::new(definitions, expr, budget);
+    machine.recorder = Some(Recorder::default());
+    while machine.step()?.is_none() {}
+
+    Ok(machine
+        .recorder
+        .and_then(Recorder::into_derivation)
+        .expect("a finished evaluation has a derivation"))
+}
 
-            eval_expr(subst_expr, depth + 1)
+/// Keeps track of how much of the `EvalLimits` that has been used so far
+pub(crate) struct Budget {
+    limits: EvalLimits,
+    steps: u64,
+    start: Option<Instant>,
+}
+
+impl Budget {
+    pub(crate) fn new(limits: &EvalLimits) -> Self {
+        Self {
+            limits: limits.clone(),
+            steps: 0,
+            // Only ask for the time if needed, since it panics on some platforms (wasm)
+            start: limits.timeout.map(|_| Instant::now()),
+        }
+    }
+
+    pub(crate) fn check_depth(&self, depth: usize) -> Result<(), EvalError> {
+        match self.limits.max_depth {
+            Some(max) if depth >= max => Err(EvalError::LimitExceeded(Limit::Depth(max))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a reduction step and make sure that we are still within the step and time limit
+    pub(crate) fn step(&mut self) -> Result<(), EvalError> {
+        self.steps += 1;
+        if let Some(max) = self.limits.max_steps {
+            if self.steps > max {
+                return Err(EvalError::LimitExceeded(Limit::Steps(max)));
+            }
         }
-        Rec(x, e) => eval_expr(substitute(&x, &Rec(x.clone(), e.clone()), *e), depth + 1),
-        Var(x) => Err(format!("Not a closed expression, variable '{x}' is not bound.").into()),
-        Const(c, es) => {
-            let es: Result<Vec<_>, _> = es.into_iter().map(|e| eval_expr(e, depth + 1)).collect();
-            Ok(Const(c, es?))
+
+        if let (Some(timeout), Some(start)) = (self.limits.timeout, self.start) {
+            if start.elapsed() >= timeout {
+                return Err(EvalError::LimitExceeded(Limit::Time(timeout)));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn check_size(&self, expr: &Expr) -> Result<(), EvalError> {
+        match self.limits.max_size {
+            Some(max) if expr.size() > max => Err(EvalError::LimitExceeded(Limit::Size(max))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a reduction step resulting in the term `expr`
+    pub(crate) fn reduce(&mut self, expr: Expr) -> Result<Expr, EvalError> {
+        self.step()?;
+        self.check_size(&expr)?;
+        Ok(expr)
+    }
+}
+
+/// An abstract machine for the big-step semantics.
+/// Instead of recursing on the Rust stack, the pending work is kept in an explicit stack of
+/// continuation frames, so deeply nested or long running computations can not overflow the native stack.
+struct Machine {
+    /// `None` once the machine has stopped
+    state: Option<State>,
+    stack: Vec<Frame>,
+    budget: Budget,
+    definitions: Definitions,
+    /// Only present if the derivation tree should be recorded.
+    /// Natural numbers are not made compact while recording, since every `Suc` needs its own rule instance
+    recorder: Option<Recorder>,
+    /// Only present if statistics should be collected
+    stats: Option<EvalStats>,
+}
+
+enum State {
+    /// The expression should be evaluated
+    Eval(Expr),
+    /// A value has been computed and should be passed on to the topmost frame
+    Return(Expr),
+}
+
+/// The evaluation contexts of the big-step rules, `□` marks the position of the subterm being evaluated.
+/// The frames that can get stuck also hold the location of their expression
+enum Frame {
+    /// `□ e2`
+    ApplyFun(Expr, Option<Span>),
+    /// `(\x. e) □`
+    ApplyArg(Variable, Expr),
+    /// `case □ of { branches }`
+    Case(Vec<Branch<Expr>>, Option<Span>),
+    /// `c(v1, ..., □, e1, ...)`, the remaining arguments are stored in reverse order
+    Const(Constructor, Vec<Expr>, Vec<Expr>),
+}
+
+impl Machine {
+    fn new(definitions: Definitions, expr: Expr, budget: Budget) -> Self {
+        Self {
+            state: Some(State::Eval(expr)),
+            stack: Vec::new(),
+            budget,
+            definitions,
+            recorder: None,
+            stats: None,
+        }
+    }
+
+    fn run(mut self) -> Result<Expr, EvalError> {
+        loop {
+            if let Some(value) = self.step()? {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn push(&mut self, frame: Frame) -> Result<(), EvalError> {
+        self.budget.check_depth(self.stack.len())?;
+        self.stack.push(frame);
+        if let Some(stats) = &mut self.stats {
+            stats.max_depth = stats.max_depth.max(self.stack.len());
+        }
+        Ok(())
+    }
+
+    /// Count a reduction using `rule`, that performed `substitutions` substitutions and resulted in `expr`
+    fn reduce(&mut self, rule: Rule, substitutions: usize, expr: Expr) -> Result<Expr, EvalError> {
+        if let Some(stats) = &mut self.stats {
+            stats.record(rule, substitutions, &expr);
+        }
+        self.budget.reduce(expr)
+    }
+
+    /// Perform a single transition, returns the value of the expression once the machine is finished.
+    /// The references to definitions in the value are kept, since expanding them can make it exponentially larger
+    fn step(&mut self) -> Result<Option<Expr>, EvalError> {
+        let Some(state) = self.state.take() else {
+            panic!("The machine has already stopped");
+        };
+
+        self.state = Some(match state {
+            State::Eval(mut expr) => {
+                let mut span = None;
+                let mut expr = loop {
+                    match &mut expr {
+                        Located(s, e) => {
+                            span = Some(s.clone());
+                            expr = e.take();
+                        }
+                        // Looking up a definition is not a reduction, just like the substitution of the bindings
+                        Global(_, index) => expr = self.definitions.get(*index).clone(),
+                        _ => break expr,
+                    }
+                };
+
+                if let Some(recorder) = &mut self.recorder {
+                    expr = expr.unfold();
+                    recorder.begin(&expr);
+                }
+                self.eval(expr, span)?
+            }
+            State::Return(value) => match self.stack.pop() {
+                None => return Ok(Some(value)),
+                Some(frame) => self.ret(frame, value)?,
+            },
+        });
+
+        Ok(None)
+    }
+
+    /// Start evaluating `expr`, which is located at `span`
+    fn eval(&mut self, mut expr: Expr, span: Option<Span>) -> Result<State, EvalError> {
+        Ok(match &mut expr {
+            Apply(e1, e2) => {
+                self.push(Frame::ApplyFun(e2.take(), span))?;
+                State::Eval(e1.take())
+            }
+            Lambda(..) => State::Return(expr),
+            Case(e, branches) => {
+                self.push(Frame::Case(mem::take(branches), span))?;
+                State::Eval(e.take())
+            }
+            Rec(x, e) => {
+                let unfolded = substitute(x, &Rec(x.clone(), e.clone()), e.take());
+                State::Eval(self.reduce(Rule::Rec, 1, unfolded)?)
+            }
+            Var(x) => {
+                return Err(EvalError::UnboundVariable {
+                    variable: x.clone(),
+                    expr: Var(x.clone()),
+                    span,
+                })
+            }
+            Const(c, es) => {
+                let mut es = mem::take(es);
+                es.reverse();
+                match es.pop() {
+                    Some(e) => {
+                        self.push(Frame::Const(c.clone(), Vec::new(), es))?;
+                        State::Eval(e)
+                    }
+                    None => State::Return(self.constructor(c.clone(), es)),
+                }
+            }
+            Nat(_) => State::Return(expr),
+            Located(..) | Global(..) => {
+                unreachable!("locations and references are removed before evaluating")
+            }
+        })
+    }
+
+    /// Pass the `value` on to the `frame`
+    fn ret(&mut self, frame: Frame, mut value: Expr) -> Result<State, EvalError> {
+        Ok(match frame {
+            Frame::ApplyFun(e2, span) => {
+                let Lambda(x, e) = &mut value else {
+                    return Err(EvalError::NotALambda {
+                        expr: Apply(Box::new(value.clone()), Box::new(e2)),
+                        value,
+                        span,
+                    });
+                };
+                self.push(Frame::ApplyArg(x.clone(), e.take()))?;
+                State::Eval(e2)
+            }
+            Frame::ApplyArg(x, e) => {
+                State::Eval(self.reduce(Rule::Apply, 1, substitute(&x, &value, e))?)
+            }
+            Frame::Case(branches, span) => {
+                let value = value.unfold();
+                let Const(constructor_name, es) = &value else {
+                    return Err(EvalError::NotAConstructor {
+                        expr: Case(Box::new(value.clone()), branches),
+                        value,
+                        span,
+                    });
+                };
+
+                let subst_expr = select_branch(constructor_name, es, &branches, span.as_ref())?;
+                State::Eval(self.reduce(Rule::Case, es.len(), subst_expr)?)
+            }
+            Frame::Const(c, mut values, mut rest) => {
+                values.push(value);
+                match rest.pop() {
+                    Some(e) => {
+                        self.push(Frame::Const(c, values, rest))?;
+                        State::Eval(e)
+                    }
+                    None => State::Return(self.constructor(c, values)),
+                }
+            }
+        })
+    }
+
+    /// The value `c(values)`, which is compact if possible and no derivation is recorded
+    fn constructor(&self, c: Constructor, values: Vec<Expr>) -> Expr {
+        match self.recorder {
+            None => Expr::constructor(c, values),
+            Some(_) => Const(c, values),
         }
     }
 }
@@ -1,7 +1,11 @@
+use std::time::Duration;
+
 use crate::{
-    eval, parse,
+    eval, eval_env, eval_with_stats, parse,
     parser::{Constructor, Variable},
-    replace_coded_literals, Expr, StandardCoder,
+    pretty::{self, TooLarge},
+    replace_coded_literals, Coder, EvalError, EvalLimits, Evaluator, Expr, Limit, Printer, Program,
+    StandardCoder, Status,
 };
 
 // The following programs should fail to terminate:
@@ -9,42 +13,111 @@ use crate::{
 fn application_error() {
     let expr = parse("C() C()").unwrap();
     let expr = replace_coded_literals(expr, &mut StandardCoder::default());
-    assert!(eval(expr).is_err());
+    assert!(matches!(
+        eval(expr, &EvalLimits::default()),
+        Err(EvalError::NotALambda { .. })
+    ));
 }
 
 #[test]
 fn non_terminating() {
     let expr = parse("rec x = x").unwrap();
     let expr = replace_coded_literals(expr, &mut StandardCoder::default());
-    assert!(eval(expr).is_err());
+    assert!(matches!(
+        eval(expr, &EvalLimits::default()),
+        Err(EvalError::LimitExceeded(_))
+    ));
 }
 
 #[test]
 fn case_no_constructor_error() {
     let expr = parse(r"case \x. x of {}").unwrap();
     let expr = replace_coded_literals(expr, &mut StandardCoder::default());
-    assert!(eval(expr).is_err());
+    assert!(matches!(
+        eval(expr, &EvalLimits::default()),
+        Err(EvalError::NotAConstructor { .. })
+    ));
 }
 
 #[test]
 fn case_arity_to_many_error() {
     let expr = parse(r"case C() of { C(x) -> C() }").unwrap();
     let expr = replace_coded_literals(expr, &mut StandardCoder::default());
-    assert!(eval(expr).is_err());
+    assert!(matches!(
+        eval(expr, &EvalLimits::default()),
+        Err(EvalError::ArityMismatch {
+            expected: 1,
+            actual: 0,
+            ..
+        })
+    ));
 }
 
 #[test]
 fn case_arity_to_few_error() {
     let expr = parse(r"case C(C()) of { C() -> C(); C(x) -> x }").unwrap();
     let expr = replace_coded_literals(expr, &mut StandardCoder::default());
-    assert!(eval(expr).is_err());
+    assert!(matches!(
+        eval(expr, &EvalLimits::default()),
+        Err(EvalError::ArityMismatch {
+            expected: 0,
+            actual: 1,
+            ..
+        })
+    ));
 }
 
 #[test]
 fn case_lookup_error() {
     let expr = parse(r"case C() of { D() -> D() }").unwrap();
     let expr = replace_coded_literals(expr, &mut StandardCoder::default());
-    assert!(eval(expr).is_err());
+    assert!(matches!(
+        eval(expr, &EvalLimits::default()),
+        Err(EvalError::MissingBranch { .. })
+    ));
+}
+
+#[test]
+fn unbound_variable_error() {
+    let expr = parse(r"(\x. y) C()").unwrap();
+    let expr = replace_coded_literals(expr, &mut StandardCoder::default());
+    assert_eq!(
+        eval(expr, &EvalLimits::default()),
+        Err(EvalError::UnboundVariable {
+            variable: Variable("y".into()),
+            expr: Expr::Var(Variable("y".into())),
+            span: Some(5..6),
+        })
+    );
+}
+
+#[test]
+fn error_locations() {
+    let source = r"let f = \x. case x of { A() -> B() };
+f (f A())";
+    let expr = parse(source).unwrap();
+    let expr = replace_coded_literals(expr, &mut StandardCoder::default());
+    let error = eval(expr, &EvalLimits::default()).unwrap_err();
+    // The second call gets stuck on the case expression in the definition of `f`
+    assert!(matches!(error, EvalError::MissingBranch { .. }));
+    assert_eq!(
+        &source[error.span().unwrap().clone()],
+        "case x of { A() -> B() }"
+    );
+
+    let source = "C() D()";
+    let expr = parse(source).unwrap();
+    let expr = replace_coded_literals(expr, &mut StandardCoder::default());
+    let error = eval(expr, &EvalLimits::default()).unwrap_err();
+    assert_eq!(error.span(), Some(&(0..7)));
+}
+
+#[test]
+fn values_are_not_located() {
+    let expr = parse(r"(\x. Pair(x, \y. y)) C()").unwrap();
+    let expr = replace_coded_literals(expr, &mut StandardCoder::default());
+    let value = eval(expr, &EvalLimits::default()).unwrap();
+    assert!(format!("{value:?}").find("Located").is_none());
 }
 
 // The following programs should terminate with specific results
@@ -54,7 +127,7 @@ fn case_subst_order() {
     let expr = parse(r"case C(D(),E()) of { C(x, x) -> x } ").unwrap();
     let expr = replace_coded_literals(expr, &mut StandardCoder::default());
     assert_eq!(
-        eval(expr).unwrap(),
+        eval(expr, &EvalLimits::default()).unwrap(),
         Expr::Const(Constructor("E".into()), vec![])
     );
 }
@@ -64,7 +137,7 @@ fn case_and_application() {
     let expr = parse(r"case C(\x.x, Zero()) of { C(f, x) -> f x }").unwrap();
     let expr = replace_coded_literals(expr, &mut StandardCoder::default());
     assert_eq!(
-        eval(expr).unwrap(),
+        eval(expr, &EvalLimits::default()).unwrap(),
         Expr::Const(Constructor("Zero".into()), vec![])
     );
 }
@@ -74,7 +147,7 @@ fn case_and_application2() {
     let expr = parse(r"case (\x.x) C() of { C() -> C() } ").unwrap();
     let expr = replace_coded_literals(expr, &mut StandardCoder::default());
     assert_eq!(
-        eval(expr).unwrap(),
+        eval(expr, &EvalLimits::default()).unwrap(),
         Expr::Const(Constructor("C".into()), vec![])
     );
 }
@@ -85,7 +158,7 @@ fn application() {
     let expr = replace_coded_literals(expr, &mut StandardCoder::default());
     let x = Variable("x".into());
     assert_eq!(
-        eval(expr).unwrap(),
+        eval(expr, &EvalLimits::default()).unwrap(),
         Expr::Lambda(x.clone(), Box::new(Expr::Var(x)))
     );
 }
@@ -99,7 +172,7 @@ fn application_left_assoc() {
     )
     .unwrap();
     let program = replace_coded_literals(program, &mut StandardCoder::default());
-    let expr = eval(program).unwrap();
+    let expr = eval(program, &EvalLimits::default()).unwrap();
     assert_eq!(expr, Expr::Const(Constructor("Bar".into()), vec![]))
 }
 
@@ -121,6 +194,574 @@ fn sample_program_equiv() {
     "#;
     let program = dbg!(parse(src)).unwrap();
     let program = replace_coded_literals(program, &mut StandardCoder::default());
-    let expr = dbg!(eval(program)).unwrap();
+    let expr = dbg!(eval(program, &EvalLimits::default())).unwrap();
     assert_eq!(expr, Expr::Const(Constructor("False".into()), vec![]))
 }
+
+// Evaluation limits
+
+fn nat(n: usize) -> String {
+    (0..n).fold("Zero()".to_string(), |e, _| format!("Suc({e})"))
+}
+
+const ADD: &str = r"
+    let add = rec add = \x. \y. case x of
+    { Zero() -> y
+    ; Suc(n) -> Suc(add n y)
+    };
+";
+
+#[test]
+fn add_moderately_sized_naturals() {
+    let src = format!("{ADD} add {} {}", nat(100), nat(100));
+    let program = replace_coded_literals(parse(&src).unwrap(), &mut StandardCoder::default());
+    let expr = eval(program, &EvalLimits::default()).unwrap();
+    let expected = replace_coded_literals(parse(&nat(200)).unwrap(), &mut StandardCoder::default());
+    assert_eq!(Program::Expr(expr), expected);
+}
+
+#[test]
+fn step_limit() {
+    let expr = parse("rec x = x").unwrap();
+    let expr = replace_coded_literals(expr, &mut StandardCoder::default());
+    let limits = EvalLimits {
+        max_steps: Some(10),
+        ..EvalLimits::unlimited()
+    };
+    assert!(matches!(
+        eval(expr, &limits),
+        Err(EvalError::LimitExceeded(Limit::Steps(10)))
+    ));
+}
+
+#[test]
+fn depth_limit() {
+    let src = format!("{ADD} add {} {}", nat(10), nat(10));
+    let program = replace_coded_literals(parse(&src).unwrap(), &mut StandardCoder::default());
+    let limits = EvalLimits {
+        max_depth: Some(10),
+        ..EvalLimits::unlimited()
+    };
+    assert!(matches!(
+        eval(program, &limits),
+        Err(EvalError::LimitExceeded(Limit::Depth(10)))
+    ));
+}
+
+#[test]
+fn size_limit() {
+    // Every unfolding doubles the size of the constructor tree
+    let src = r"(rec grow = \x. grow Pair(x, x)) Zero()";
+    let program = replace_coded_literals(parse(src).unwrap(), &mut StandardCoder::default());
+    let limits = EvalLimits {
+        max_size: Some(1000),
+        ..EvalLimits::unlimited()
+    };
+    assert!(matches!(
+        eval(program, &limits),
+        Err(EvalError::LimitExceeded(Limit::Size(1000)))
+    ));
+}
+
+#[test]
+fn time_limit() {
+    let expr = parse("rec x = x").unwrap();
+    let expr = replace_coded_literals(expr, &mut StandardCoder::default());
+    let limits = EvalLimits {
+        timeout: Some(Duration::ZERO),
+        ..EvalLimits::default()
+    };
+    assert!(matches!(
+        eval(expr, &limits),
+        Err(EvalError::LimitExceeded(Limit::Time(_)))
+    ));
+}
+
+// The evaluator should not use the Rust stack for deep computations
+
+#[test]
+fn deep_tail_recursion() {
+    let coder = StandardCoder::default();
+    let count_down = parse(
+        r"rec count = \n. case n of
+        { Zero() -> Zero()
+        ; Suc(m) -> count m
+        }",
+    )
+    .unwrap();
+    let Program::Expr(count_down) =
+        replace_coded_literals(count_down, &mut StandardCoder::default())
+    else {
+        unreachable!()
+    };
+    let program = Program::Expr(Expr::Apply(
+        Box::new(count_down),
+        Box::new(coder.code_natural(1000)),
+    ));
+    assert_eq!(
+        eval(program, &EvalLimits::default()).unwrap(),
+        coder.code_natural(0)
+    );
+}
+
+#[test]
+fn deep_values() {
+    // A list with a million elements, which is copied by the substitution.
+    // Evaluating, comparing, printing, copying and dropping values this deep must not overflow the native stack
+    let constructor = |c: &str, es| Expr::Const(Constructor(c.into()), es);
+    let list = (0..1_000_000).fold(constructor("Nil", vec![]), |tail, _| {
+        constructor("Cons", vec![constructor("A", vec![]), tail])
+    });
+    let x = Variable("x".into());
+    let program = Program::Expr(Expr::Apply(
+        Box::new(Expr::Lambda(
+            x.clone(),
+            Box::new(constructor(
+                "Pair",
+                vec![Expr::Var(x.clone()), Expr::Var(x)],
+            )),
+        )),
+        Box::new(list),
+    ));
+    let value = eval(program, &EvalLimits::unlimited()).unwrap();
+
+    let Expr::Const(_, lists) = &value else {
+        panic!("expected a pair");
+    };
+    assert!(lists[0] == lists[1]);
+    assert_eq!(pretty::sugared(&lists[0]).len(), 5 * 1_000_000);
+    let concrete = pretty::concrete(&lists[1].clone()).unwrap();
+    assert!(concrete.starts_with("Cons(A(),Cons(A(),"));
+    assert_eq!(concrete.len(), 10 * 1_000_000 + "Nil()".len());
+    assert!(pretty::abstr(&lists[0])
+        .unwrap()
+        .starts_with("const <u>Cons</u> (cons (const <u>A</u> nil)"));
+}
+
+#[test]
+fn deep_non_tail_recursion() {
+    let coder = StandardCoder::default();
+    let Program::Let(_, _, add, _) = replace_coded_literals(
+        parse(&format!("{ADD} add")).unwrap(),
+        &mut StandardCoder::default(),
+    ) else {
+        unreachable!()
+    };
+    let program = Program::Expr(Expr::Apply(
+        Box::new(Expr::Apply(
+            Box::new(add),
+            Box::new(coder.code_natural(1000)),
+        )),
+        Box::new(coder.code_natural(0)),
+    ));
+    assert_eq!(
+        eval(program, &EvalLimits::default()).unwrap(),
+        coder.code_natural(1000)
+    );
+}
+
+#[test]
+fn statistics() {
+    let src = format!("{ADD} add {} {}", nat(2), nat(1));
+    let program = replace_coded_literals(parse(&src).unwrap(), &mut StandardCoder::default());
+    let (value, stats) = eval_with_stats(program.clone(), &EvalLimits::default()).unwrap();
+    assert_eq!(
+        value,
+        eval(program.clone(), &EvalLimits::default()).unwrap()
+    );
+
+    // One unfolding, two beta-reductions and one case reduction per recursive call
+    assert_eq!(stats.rec_unfoldings, 3);
+    assert_eq!(stats.beta_reductions, 6);
+    assert_eq!(stats.case_reductions, 3);
+    // Only the two Suc(n) branches bind a variable
+    assert_eq!(stats.substitutions, 11);
+    assert!(stats.max_depth > 0);
+    assert!(stats.max_size >= value.size());
+
+    // The reductions are exactly what the step limit counts
+    let limits = EvalLimits {
+        max_steps: Some(stats.reductions()),
+        ..EvalLimits::default()
+    };
+    assert!(eval(program.clone(), &limits).is_ok());
+    let limits = EvalLimits {
+        max_steps: Some(stats.reductions() - 1),
+        ..EvalLimits::default()
+    };
+    assert!(eval(program, &limits).is_err());
+}
+
+#[test]
+fn resumable_evaluation() {
+    let src = format!("{ADD} add {} {}", nat(10), nat(10));
+    let program = replace_coded_literals(parse(&src).unwrap(), &mut StandardCoder::default());
+    let expected = eval(program.clone(), &EvalLimits::default()).unwrap();
+
+    let mut evaluator = Evaluator::new(program, &EvalLimits::default());
+    let mut chunks = 0;
+    while *evaluator.step(10) == Status::Running {
+        chunks += 1;
+    }
+    assert!(chunks > 1);
+    assert_eq!(*evaluator.status(), Status::Finished(expected));
+    // Stepping a finished evaluation does nothing
+    assert!(matches!(evaluator.step(10), Status::Finished(_)));
+}
+
+#[test]
+fn resumable_evaluation_failure() {
+    let program =
+        replace_coded_literals(parse("rec x = x").unwrap(), &mut StandardCoder::default());
+    let limits = EvalLimits {
+        max_steps: Some(100),
+        ..EvalLimits::default()
+    };
+    let mut evaluator = Evaluator::new(program.clone(), &limits);
+    assert_eq!(*evaluator.step(0), Status::Running);
+    while *evaluator.step(1) == Status::Running {}
+    assert_eq!(
+        *evaluator.status(),
+        Status::Failed(EvalError::LimitExceeded(Limit::Steps(100)))
+    );
+
+    let limits = EvalLimits {
+        max_size: Some(1),
+        ..EvalLimits::default()
+    };
+    let evaluator = Evaluator::new(program, &limits);
+    assert_eq!(
+        *evaluator.status(),
+        Status::Failed(EvalError::LimitExceeded(Limit::Size(1)))
+    );
+}
+
+// Natural numbers built by the evaluator are compact
+
+#[test]
+fn compact_naturals() {
+    let src = format!("{ADD} add {} {}", nat(2), nat(1));
+    let program = replace_coded_literals(parse(&src).unwrap(), &mut StandardCoder::default());
+    let value = eval(program, &EvalLimits::default()).unwrap();
+    assert!(matches!(&value, Expr::Nat(n) if *n == 3u32.into()));
+
+    // Observationally the same as the constructor tree
+    let Program::Expr(tree) =
+        replace_coded_literals(parse(&nat(3)).unwrap(), &mut StandardCoder::default())
+    else {
+        unreachable!()
+    };
+    let tree = tree.without_spans();
+    assert_eq!(value, tree);
+    assert_eq!(tree, value);
+    assert_ne!(value, Expr::nat(2u32));
+    assert_eq!(value.size(), tree.size());
+    assert_eq!(
+        pretty::concrete(&value).unwrap(),
+        pretty::concrete(&tree).unwrap()
+    );
+    assert_eq!(
+        pretty::abstr(&value).unwrap(),
+        pretty::abstr(&tree).unwrap()
+    );
+}
+
+#[test]
+fn large_compact_naturals() {
+    let n = "99999999999999999999999";
+    let program = replace_coded_literals(parse(n).unwrap(), &mut StandardCoder::default());
+    let value = eval(program, &EvalLimits::default()).unwrap();
+    // Only the sugared syntax has number literals
+    assert_eq!(pretty::sugared(&value), n);
+    assert_eq!(pretty::concrete(&value), Err(TooLarge));
+    assert_eq!(pretty::abstr(&value), Err(TooLarge));
+
+    let value = Expr::nat(100_000u32);
+    let concrete = pretty::concrete(&value).unwrap();
+    assert!(concrete.starts_with("Suc(Suc("));
+    assert_eq!(concrete.len(), 5 * 100_000 + "Zero()".len());
+    assert!(pretty::abstr(&value)
+        .unwrap()
+        .starts_with("const <u>Suc</u> (cons (const <u>Suc</u>"));
+    for printer in [Printer::Concrete, Printer::Abstract] {
+        let Err(error) = crate::run(n, printer, &EvalLimits::default()) else {
+            panic!("the value should be too large to print");
+        };
+        assert!(error.contains("too large to print"));
+    }
+}
+
+#[test]
+fn case_on_compact_naturals() {
+    let pred = replace_coded_literals(
+        parse(r"\n. case n of { Zero() -> Zero(); Suc(m) -> m }").unwrap(),
+        &mut StandardCoder::default(),
+    );
+    let Program::Expr(pred) = pred else {
+        unreachable!()
+    };
+
+    for (n, expected) in [(0u32, 0u32), (1, 0), (5, 4)] {
+        let program = Program::Expr(Expr::Apply(Box::new(pred.clone()), Box::new(Expr::nat(n))));
+        assert_eq!(
+            eval(program, &EvalLimits::default()).unwrap(),
+            Expr::nat(expected)
+        );
+    }
+}
+
+#[test]
+fn multiply_large_naturals() {
+    let src = format!(
+        r"{ADD}
+        let mul = rec mul = \x. \y. case x of
+        {{ Zero() -> Zero()
+        ; Suc(n) -> add y (mul n y)
+        }};
+        mul {} {}",
+        nat(300),
+        nat(300)
+    );
+    let program = replace_coded_literals(parse(&src).unwrap(), &mut StandardCoder::default());
+    assert_eq!(
+        eval(program, &EvalLimits::default()).unwrap(),
+        Expr::nat(90_000u32)
+    );
+}
+
+// Definitions are looked up when they are used, instead of being substituted into the program
+
+#[test]
+fn layered_definitions() {
+    // Substituting the definitions would create 2^40 copies of `twice0`
+    let mut src = r"let twice0 = \f. \x. f (f x);".to_string();
+    for i in 1..40 {
+        src += &format!(
+            r"let twice{i} = \f. \x. twice{} f (twice{} f x);",
+            i - 1,
+            i - 1
+        );
+    }
+    src += r"twice1 (\x. S(x)) Z()";
+    let program = replace_coded_literals(parse(&src).unwrap(), &mut StandardCoder::default());
+    let expected = replace_coded_literals(
+        parse("S(S(S(S(Z()))))").unwrap(),
+        &mut StandardCoder::default(),
+    );
+    assert_eq!(
+        Program::Expr(eval(program, &EvalLimits::default()).unwrap()),
+        expected
+    );
+}
+
+#[test]
+fn definition_scoping() {
+    let src = r"let x = A(); let f = \y. x; let x = B(); Pair(f C(), x)";
+    let program = replace_coded_literals(parse(src).unwrap(), &mut StandardCoder::default());
+    let expected = replace_coded_literals(
+        parse("Pair(A(), B())").unwrap(),
+        &mut StandardCoder::default(),
+    );
+    assert_eq!(
+        Program::Expr(eval(program, &EvalLimits::default()).unwrap()),
+        expected
+    );
+}
+
+#[test]
+fn values_refer_to_definitions_by_name() {
+    let src = r"let id = \x. x; \y. id y";
+    let program = replace_coded_literals(parse(src).unwrap(), &mut StandardCoder::default());
+    let value = eval(program, &EvalLimits::default()).unwrap();
+    assert_eq!(pretty::concrete(&value).unwrap(), r"\y. id y");
+
+    // Expanding the definitions in the value would create 2^40 copies of `twice0`
+    let mut src = r"let twice0 = \f. \x. f (f x);".to_string();
+    for i in 1..40 {
+        src += &format!(
+            r"let twice{i} = \f. \x. twice{} f (twice{} f x);",
+            i - 1,
+            i - 1
+        );
+    }
+    src += "twice39";
+    let program = replace_coded_literals(parse(&src).unwrap(), &mut StandardCoder::default());
+    let value = eval(program, &EvalLimits::default()).unwrap();
+    assert_eq!(
+        pretty::concrete(&value).unwrap(),
+        r"\f. \x. twice38 f (twice38 f x)"
+    );
+}
+
+#[test]
+fn literals() {
+    let run = |source| {
+        let program = replace_coded_literals(parse(source).unwrap(), &mut StandardCoder::default());
+        eval(program, &EvalLimits::default()).unwrap()
+    };
+
+    let value = run(r"let pred = \n. case n of { 0 -> 0; Suc(n) -> n }; [pred 3, pred 0]");
+    assert_eq!(value, run("Cons(Suc(Suc(Zero())), Cons(Zero(), Nil()))"));
+    assert_eq!(pretty::sugared(&value), "[2, 0]");
+    assert_eq!(
+        pretty::concrete(&value).unwrap(),
+        "Cons(Suc(Suc(Zero())),Cons(Zero(),Nil()))"
+    );
+
+    assert_eq!(run(r#""[2]""#), run(r#""Cons(Suc(Suc(Zero())), Nil())""#));
+}
+
+#[test]
+fn sugared_printer() {
+    let value = parse(r"\x. case x of { Zero() -> Cons(x, Suc(Zero())); Nil() -> Cons(x, Nil()) }")
+        .unwrap();
+    let Program::Expr(value) = replace_coded_literals(value, &mut StandardCoder::default()) else {
+        panic!("expected an expression");
+    };
+    // A `Cons` chain that does not end with `Nil()` is not a list
+    assert_eq!(
+        pretty::sugared(&value),
+        "\\x. case x of {\n  0 -> Cons(x,1);\n  [] -> [x]\n}"
+    );
+}
+
+#[test]
+fn function_definitions() {
+    let run = |source| {
+        let program = replace_coded_literals(parse(source).unwrap(), &mut StandardCoder::default());
+        eval(program, &EvalLimits::default()).unwrap()
+    };
+
+    let sugared = r"
+        let rec add x y = case x of { Zero() -> y; Suc(n) -> Suc(add n y) };
+        add 2 3
+    ";
+    assert_eq!(run(sugared), run("5"));
+    assert_eq!(run(r#""\x y z. z""#), run(r#""\x. \y. \z. z""#));
+    assert_eq!(run(r#""rec f = \x y. f""#), run(r#""rec f = \x. \y. f""#));
+}
+
+#[test]
+fn local_definition_scoping() {
+    let run = |source| {
+        let program = replace_coded_literals(parse(source).unwrap(), &mut StandardCoder::default());
+        eval(program, &EvalLimits::default())
+    };
+
+    // Local definitions shadow, and are shadowed by, lambda-, rec- and branch-bound variables
+    assert_eq!(run(r"(\x. let x = A() in x) B()"), run("A()"));
+    assert_eq!(run(r"let x = A() in (\x. x) B()"), run("B()"));
+    assert_eq!(
+        run(r"case C(A()) of { C(x) -> let x = B() in x }"),
+        run("B()")
+    );
+    assert_eq!(
+        run(r"let x = A() in case C(B()) of { C(x) -> x }"),
+        run("B()")
+    );
+    assert_eq!(
+        run(r"(rec f = \n. case n of { 0 -> x; Suc(n) -> f n } where { x = A() }) 2"),
+        run("A()")
+    );
+
+    // The right-hand side is not in the scope of its own definition
+    assert!(matches!(
+        run("let x = x in x"),
+        Err(EvalError::UnboundVariable { .. })
+    ));
+
+    // Definitions in a where clause can use the ones before them, but not the ones after them
+    assert_eq!(run(r"y where { x = 1; y = Suc(x) }"), run("2"));
+    let source = r"f A() where { f = \y. Pair(y, z); z = C() }";
+    assert!(matches!(
+        run(source),
+        Err(EvalError::UnboundVariable { variable, .. }) if variable == Variable::from("z")
+    ));
+    let program = replace_coded_literals(parse(source).unwrap(), &mut StandardCoder::default());
+    assert!(matches!(
+        eval_env(program, &EvalLimits::default()),
+        Err(EvalError::UnboundVariable { variable, .. }) if variable == Variable::from("z")
+    ));
+    assert_eq!(
+        run(r"(\z. f A() where { f = \y. Pair(y, z); z = C() }) B()"),
+        run("Pair(A(), B())")
+    );
+    assert_eq!(
+        run(r"(\z. let f = \y. z in \z. Pair(f A(), z)) B() C()"),
+        run("Pair(B(), C())")
+    );
+    assert_eq!(run(r#""let x = A() in x""#), run(r#""(\x. x) A()""#));
+}
+
+#[test]
+fn local_definition_errors() {
+    let source = "let x = A() in\nx y";
+    let program = replace_coded_literals(parse(source).unwrap(), &mut StandardCoder::default());
+    let Err(EvalError::NotALambda {
+        span: Some(span), ..
+    }) = eval(program, &EvalLimits::default())
+    else {
+        panic!("expected an error");
+    };
+    assert_eq!(&source[span], "x y");
+
+    let source = r"\z. let x = y in x";
+    let program = replace_coded_literals(
+        parse(&format!("({source}) A()")).unwrap(),
+        &mut StandardCoder::default(),
+    );
+    let Err(EvalError::UnboundVariable {
+        span: Some(span), ..
+    }) = eval(program, &EvalLimits::default())
+    else {
+        panic!("expected an error");
+    };
+    assert_eq!(span, 13..14);
+}
+
+#[test]
+fn recursive_groups() {
+    let run = |source: &str| {
+        let program = replace_coded_literals(parse(source).unwrap(), &mut StandardCoder::default());
+        eval(program, &EvalLimits::default()).unwrap()
+    };
+
+    let group = r"
+        let rec even n = case n of { 0 -> True(); Suc(n) -> odd n }
+        and odd n = case n of { 0 -> False(); Suc(n) -> even n };
+    ";
+    let encoding = r"
+        let even_odd = rec even_odd = Pair(
+            \n. case n of { 0 -> True(); Suc(n) -> case even_odd of { Pair(even, odd) -> odd n } },
+            \n. case n of { 0 -> False(); Suc(n) -> case even_odd of { Pair(even, odd) -> even n } }
+        );
+        let even = case even_odd of { Pair(even, odd) -> even };
+        let odd = case even_odd of { Pair(even, odd) -> odd };
+    ";
+
+    for n in 0..6 {
+        for f in ["even", "odd"] {
+            assert_eq!(
+                run(&format!("{group} {f} {n}")),
+                run(&format!("{encoding} {f} {n}"))
+            );
+        }
+    }
+
+    // The group shadows earlier definitions with the same names, and a name may be used before the group
+    let source = r"
+        let one = A();
+        let rec one n = case n of { 0 -> One(); Suc(n) -> two n }
+        and two n = case n of { 0 -> Two(); Suc(n) -> three n }
+        and three n = case n of { 0 -> Three(); Suc(n) -> one n };
+        [one 0, one 4, two 4, three 4]
+    ";
+    assert_eq!(run(source), run("[One(), Two(), Three(), One()]"));
+
+    // The parameters shadow the functions of the group
+    assert_eq!(run("let rec f x = g x and g f = f; f A()"), run("A()"));
+    assert_eq!(
+        run(r"let rec f x y = g y and g f = f; f A() B()"),
+        run("B()")
+    );
+}
@@ -1,4 +1,4 @@
-use logos::Logos;
+use logos::{FilterResult, Lexer, Logos};
 use std::fmt;
 
 #[derive(Logos, Clone, Debug, PartialEq)]
@@ -26,6 +26,21 @@ pub enum Token<'a> {
     #[token("let")]
     Let,
 
+    /// Ends the right-hand side of a local definition, `in` is lexed as a variable name
+    /// and only becomes this token after the right-hand side of a `let`, see `parser::tokens`
+    In,
+
+    /// Starts a where clause, `where` is lexed as a variable name and only becomes this token before a `{`
+    Where,
+
+    /// Separates the definitions of a `let rec` group, `and` is lexed as a variable name
+    /// and only becomes this token between the definitions of a group, see `parser::tokens`
+    And,
+
+    /// `import "path"` at the start of a statement, which is not lexed by logos but combined by `parser::tokens`,
+    /// since a `"` starts a coded literal everywhere else and `import` is also a variable name
+    Import(&'a str),
+
     #[token("(")]
     LParen,
 
@@ -38,6 +53,16 @@ pub enum Token<'a> {
     #[token("}")]
     RCurly,
 
+    #[token("[")]
+    LBracket,
+
+    #[token("]")]
+    RBracket,
+
+    /// A natural number literal
+    #[regex(r"[0-9]+")]
+    Number(&'a str),
+
     #[token(";")]
     Semicolon,
 
@@ -56,11 +81,48 @@ pub enum Token<'a> {
     #[token("->")]
     Arrow,
 
+    /// Separates the constructors of a `data` declaration
+    #[token("|")]
+    Bar,
+
     #[token("\"")]
     Quote,
 
     #[regex(r"--[^\n]*", logos::skip)]
     Comment,
+
+    /// `--| text`, documents the following `let` binding
+    #[regex(r"--\|[^\n]*", |lex| lex.slice()[3..].trim(), priority = 10)]
+    DocComment(&'a str),
+
+    /// A `{- ... -}` comment (which can be nested) is skipped,
+    /// this token is only produced if the comment is not terminated and then covers the rest of the source
+    #[token("{-", block_comment)]
+    UnterminatedComment,
+}
+
+/// Skip to the end of a (nested) block comment, or to the end of the source if it is not terminated
+fn block_comment<'a>(lex: &mut Lexer<'a, Token<'a>>) -> FilterResult<(), ()> {
+    let mut depth = 1;
+    let mut rest = lex.remainder();
+
+    while depth > 0 {
+        if let Some(after) = rest.strip_prefix("{-") {
+            depth += 1;
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("-}") {
+            depth -= 1;
+            rest = after;
+        } else if let Some(c) = rest.chars().next() {
+            rest = &rest[c.len_utf8()..];
+        } else {
+            lex.bump(lex.remainder().len());
+            return FilterResult::Emit(());
+        }
+    }
+
+    lex.bump(lex.remainder().len() - rest.len());
+    FilterResult::Skip
 }
 
 impl<'a> fmt::Display for Token<'a> {
@@ -75,15 +137,25 @@ impl<'a> fmt::Display for Token<'a> {
             Token::RParen => write!(f, ")"),
             Token::LCurly => write!(f, "{{"),
             Token::RCurly => write!(f, "}}"),
+            Token::LBracket => write!(f, "["),
+            Token::RBracket => write!(f, "]"),
+            Token::Number(n) => write!(f, "{n}"),
             Token::Semicolon => write!(f, ";"),
             Token::Comma => write!(f, ","),
             Token::Backslash => write!(f, "\\"),
             Token::Period => write!(f, "."),
             Token::Equals => write!(f, "="),
             Token::Arrow => write!(f, "->"),
+            Token::Bar => write!(f, "|"),
             Token::Error => write!(f, "<error>"),
             Token::Comment => write!(f, "<comment>"),
+            Token::DocComment(text) => write!(f, "--| {text}"),
+            Token::UnterminatedComment => write!(f, "unterminated block comment"),
             Token::Let => write!(f, "let"),
+            Token::In => write!(f, "in"),
+            Token::Where => write!(f, "where"),
+            Token::And => write!(f, "and"),
+            Token::Import(path) => write!(f, "import \"{path}\""),
             Token::Quote => write!(f, "\""),
         }
     }
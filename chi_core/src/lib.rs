@@ -1,79 +1,458 @@
 use ariadne::{Color, Label, Report, ReportKind, Source};
+use chumsky::error::Rich;
+use coder::SYNTAX_ERROR;
+use lexer::Token;
+use parser::parse_with_imports_recovering;
+use std::fmt::Write;
 
 mod coder;
+mod data;
+mod de_bruijn;
+mod derivation;
+mod env_eval;
 mod error;
 mod eval;
 mod lexer;
+mod module;
+mod normalize;
+mod notebook;
 mod parser;
+mod pattern;
 pub mod pretty;
+mod session;
+mod small_step;
 
+#[cfg(test)]
+mod data_tests;
+#[cfg(test)]
+mod de_bruijn_tests;
+#[cfg(test)]
+mod derivation_tests;
+#[cfg(test)]
+mod env_eval_tests;
 #[cfg(test)]
 mod eval_tests;
 #[cfg(test)]
+mod module_tests;
+#[cfg(test)]
+mod normalize_tests;
+#[cfg(test)]
+mod notebook_tests;
+#[cfg(test)]
 mod parser_tests;
 #[cfg(test)]
+mod pattern_tests;
+#[cfg(test)]
+mod session_tests;
+#[cfg(test)]
+mod small_step_tests;
+#[cfg(test)]
 mod substitution_tests;
+#[cfg(test)]
+mod test_utils;
 
 pub use coder::{replace_coded_literals, Coder, StandardCoder};
-pub use error::Error;
-pub use eval::{eval, Expr};
-pub use parser::{parse, MetaExpr, Program};
+pub use de_bruijn::{alpha_eq, alpha_hash, DeBruijn, DeBruijnBranch};
+pub use derivation::{Derivation, DerivationRule, RenderLimits};
+pub use env_eval::{compare_strategies, eval_env, eval_strategy, Comparison, Strategy};
+pub use error::EvalError;
+pub use eval::{
+    eval, eval_derivation, eval_with_stats, EvalLimits, EvalStats, Evaluator, Expr, Limit, Status,
+};
+pub use module::{Diagnostic, FileResolver, MemoryResolver, SourceResolver};
+pub use normalize::normalize;
+pub use notebook::{eval_notebook, Cell, Outcome};
+pub use parser::{
+    parse, parse_entry, parse_recovering, parse_with_imports, parse_with_warnings, Entry, MetaExpr,
+    Program, Span,
+};
+pub use pattern::{Warning, WarningKind};
+pub use session::{Response, Session};
+pub use small_step::{trace, Rule, Step, Trace};
 
 /// A high-level function that runs the parser, evaluator and also generates nice errors reports
-/// for more control, see `parse` and `eval`.
-pub fn run(source: &str, printer: Printer) -> Result<(String, impl Coder), String> {
-    // Only the most recent commit of ariadne handles empty sources correctly, so we ignore empty files
-    if source.is_empty() {
-        return Err("Empty file".into());
+/// for more control, see `parse` and `eval`, or `Run` to evaluate a few steps at a time.
+/// The statistics of the evaluation are only collected when the value is printed, not for traces, derivations and normal forms
+/// (the notebook printer includes the statistics of every binding in its output).
+pub fn run(
+    source: &str,
+    printer: Printer,
+    limits: &EvalLimits,
+) -> Result<(String, Option<EvalStats>, impl Coder), String> {
+    let mut run = Run::new(source, printer, limits)?;
+    run.step(u64::MAX);
+    run.finish()
+}
+
+/// A resumable version of `run`, the evaluation is advanced by calling `step` until it is finished.
+/// Note: only the evaluation of values is resumable, traces, derivations, normal forms and notebooks are computed by `Run::new`.
+pub struct Run {
+    source: String,
+    printer: Printer,
+    coder: StandardCoder,
+    state: RunState,
+    stats: Option<EvalStats>,
+    warnings: Vec<Warning>,
+    /// The report of the syntax errors, the parts of the program that could not be parsed are left out
+    errors: Option<String>,
+}
+
+enum RunState {
+    Evaluating(Box<Evaluator>),
+    Done(Result<String, String>),
+}
+
+impl Run {
+    /// Parse the program and start evaluating it, returns the error report if nothing could be parsed.
+    /// A program with syntax errors is still evaluated without the parts that could not be parsed,
+    /// its output is then an error with the report of the syntax errors followed by the result of the rest.
+    /// The program can not import other files, see `Run::with_resolver`
+    pub fn new(source: &str, printer: Printer, limits: &EvalLimits) -> Result<Self, String> {
+        Self::with_resolver(source, printer, limits, &MemoryResolver::default())
+    }
+
+    /// Like `Run::new`, but the files that the program imports are read by `resolver`
+    pub fn with_resolver(
+        source: &str,
+        printer: Printer,
+        limits: &EvalLimits,
+        resolver: &dyn SourceResolver,
+    ) -> Result<Self, String> {
+        // Only the most recent commit of ariadne handles empty sources correctly, so we ignore empty files
+        if source.is_empty() {
+            return Err("Empty file".into());
+        }
+
+        let (program, diagnostics, warnings) = parse_with_imports_recovering(source, resolver);
+        let errors =
+            (!diagnostics.is_empty()).then(|| diagnostics_report(source, &diagnostics, resolver));
+        let Some(program) = program else {
+            return Err(errors.unwrap_or_default());
+        };
+        let mut coder = StandardCoder::default();
+        let program = replace_coded_literals(program, &mut coder);
+
+        let state = match printer {
+            Printer::Trace => RunState::Done(print_trace(source, program, limits)),
+            Printer::Derivation => RunState::Done(match eval_derivation(program, limits) {
+                Err(eval_error) => Err(eval_error_report(source, &eval_error)),
+                Ok(derivation) => Ok(derivation.to_text(&RenderLimits::default())),
+            }),
+            Printer::Normalize => RunState::Done(match normalize(program, limits) {
+                Err(eval_error) => Err(eval_error_report(source, &eval_error)),
+                Ok(normal_form) => pretty::concrete(&normal_form).map_err(too_large_report),
+            }),
+            Printer::Notebook => RunState::Done(Ok(print_notebook(source, program, limits))),
+            Printer::Concrete | Printer::Sugared | Printer::Abstract | Printer::Debug => {
+                RunState::Evaluating(Box::new(Evaluator::with_stats(program, limits)))
+            }
+        };
+
+        let state = match (state, &errors) {
+            (RunState::Done(output), Some(errors)) => {
+                RunState::Done(with_syntax_errors(errors, output))
+            }
+            (state, _) => state,
+        };
+
+        Ok(Self {
+            source: source.to_string(),
+            printer,
+            coder,
+            state,
+            stats: None,
+            warnings,
+            errors,
+        })
+    }
+
+    /// Perform at most `n` transitions of the evaluator, returns true once the run is finished
+    pub fn step(&mut self, n: u64) -> bool {
+        let RunState::Evaluating(evaluator) = &mut self.state else {
+            return true;
+        };
+
+        let output = match evaluator.step(n) {
+            Status::Running => return false,
+            Status::Finished(value) => match self.printer {
+                Printer::Concrete => pretty::concrete(value).map_err(too_large_report),
+                Printer::Sugared => Ok(pretty::sugared(value)),
+                Printer::Abstract => pretty::abstr(value).map_err(too_large_report),
+                Printer::Debug => Ok(format!("{value:#?}")),
+                Printer::Trace | Printer::Derivation | Printer::Normalize | Printer::Notebook => {
+                    unreachable!("printed when created")
+                }
+            },
+            Status::Failed(eval_error) => Err(eval_error_report(&self.source, eval_error)),
+        };
+
+        self.stats = evaluator.stats().cloned();
+        self.state = RunState::Done(match &self.errors {
+            Some(errors) => with_syntax_errors(errors, output),
+            None => output,
+        });
+        true
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.state, RunState::Done(_))
+    }
+
+    /// The printed value or the error report, once the run is finished
+    pub fn output(&self) -> Option<&Result<String, String>> {
+        match &self.state {
+            RunState::Evaluating(_) => None,
+            RunState::Done(output) => Some(output),
+        }
+    }
+
+    /// The statistics of the evaluation so far, there are none for traces, derivations, normal forms and notebooks
+    pub fn stats(&self) -> Option<&EvalStats> {
+        match &self.state {
+            RunState::Evaluating(evaluator) => evaluator.stats(),
+            RunState::Done(_) => self.stats.as_ref(),
+        }
+    }
+
+    /// The coder used for the coded literals of the program
+    pub fn coder(&self) -> &StandardCoder {
+        &self.coder
+    }
+
+    /// The warnings about the patterns of the program
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
     }
 
-    match parse(source) {
-        Ok(program) => {
-            let mut coder = StandardCoder::default();
-            let program = replace_coded_literals(program, &mut coder);
-            match eval(program) {
-                // TODO: Add nicer evaulation errors, also using ariadne
-                Err(eval_error) => Err(format!(r#"<span class="error">{eval_error}</span>"#)),
-                Ok(value) => Ok(match printer {
-                    Printer::Concrete => (pretty::concrete(&value), coder),
-                    Printer::Abstract => (pretty::abstr(&value), coder),
-                    Printer::Debug => (format!("{value:#?}"), coder),
-                }),
+    /// The warnings rendered like the error reports, empty if there are none
+    pub fn warning_report(&self) -> String {
+        let mut output = Vec::<u8>::new();
+        for warning in &self.warnings {
+            let mut report = Report::build(ReportKind::Warning, (), warning.span.start)
+                .with_message(warning.kind.to_string())
+                .with_label(Label::new(warning.span.clone()).with_color(Color::Yellow));
+            if let WarningKind::Overlapping(earlier) = &warning.kind {
+                report = report.with_label(
+                    Label::new(earlier.clone())
+                        .with_message("this branch is used instead")
+                        .with_color(Color::Blue),
+                );
             }
+            report
+                .finish()
+                .write_for_stdout(Source::from(&self.source), &mut output)
+                .unwrap();
         }
-        Err(parse_errors) => {
-            let mut output = Vec::<u8>::new();
-            for error in parse_errors {
-                // MAJOR HACK: for some reason I get spans that end before they start
-                // (when trailing whitespace/comments and let bindings involved?)
-                // so if that is the case, we will just flip them
-                let span = if error.span().start > error.span().end {
-                    error.span().end..error.span().start
-                } else {
-                    error.span().start..error.span().end
-                };
-
-                Report::build(ReportKind::Error, (), span.start)
-                    .with_message(error.to_string())
+        String::from_utf8(output).unwrap()
+    }
+
+    /// The result in the same form as `run`.
+    /// Panics if the run is not finished.
+    pub fn finish(self) -> Result<(String, Option<EvalStats>, StandardCoder), String> {
+        let RunState::Done(output) = self.state else {
+            panic!("The run is not finished");
+        };
+        output.map(|output| (output, self.stats, self.coder))
+    }
+}
+
+/// The output of a program with syntax errors is an error, even if the rest of the program could be evaluated
+fn with_syntax_errors(errors: &str, output: Result<String, String>) -> Result<String, String> {
+    let output = output.unwrap_or_else(|error| error);
+    Err(format!(
+        "{errors}\nWithout the parts that could not be parsed, the program gives:\n{output}"
+    ))
+}
+
+fn parse_error_report(source: &str, parse_errors: Vec<Rich<'_, Token<'_>>>) -> String {
+    let mut output = Vec::<u8>::new();
+    for error in parse_errors {
+        // MAJOR HACK: for some reason I get spans that end before they start
+        // (when trailing whitespace/comments and let bindings involved?)
+        // so if that is the case, we will just flip them
+        let span = if error.span().start > error.span().end {
+            error.span().end..error.span().start
+        } else {
+            error.span().start..error.span().end
+        };
+
+        Report::build(ReportKind::Error, (), span.start)
+            .with_message(error.to_string())
+            .with_label(
+                Label::new(span)
+                    .with_message(error.reason().to_string())
+                    .with_color(Color::Red),
+            )
+            .finish()
+            .write_for_stdout(Source::from(source), &mut output)
+            .unwrap();
+    }
+    std::str::from_utf8(&output).unwrap().to_string()
+}
+
+/// Render the errors of a program and of the files it imports, which are read by `resolver` again
+fn diagnostics_report(
+    source: &str,
+    diagnostics: &[Diagnostic],
+    resolver: &dyn SourceResolver,
+) -> String {
+    let mut output = Vec::<u8>::new();
+    for Diagnostic {
+        file,
+        span,
+        message,
+        label,
+    } in diagnostics
+    {
+        match file {
+            None => Report::build(ReportKind::Error, (), span.start)
+                .with_message(message)
+                .with_label(
+                    Label::new(span.clone())
+                        .with_message(label)
+                        .with_color(Color::Red),
+                )
+                .finish()
+                .write_for_stdout(Source::from(source), &mut output)
+                .unwrap(),
+            Some(file) => {
+                let imported = resolver.resolve(file).unwrap_or_default();
+                Report::build(ReportKind::Error, file.as_str(), span.start)
+                    .with_message(message)
                     .with_label(
-                        Label::new(span)
-                            .with_message(error.reason().to_string())
+                        Label::new((file.as_str(), span.clone()))
+                            .with_message(label)
                             .with_color(Color::Red),
                     )
                     .finish()
-                    .write_for_stdout(Source::from(source), &mut output)
-                    .unwrap();
+                    .write_for_stdout((file.as_str(), Source::from(imported)), &mut output)
+                    .unwrap()
             }
-            Err(std::str::from_utf8(&output).unwrap().to_string())
         }
     }
+    String::from_utf8(output).unwrap()
+}
+
+/// Render an evaluation error, pointing at the subterm that got stuck if its location is known
+fn eval_error_report(source: &str, error: &EvalError) -> String {
+    let Some(span) = error.span() else {
+        return format!(r#"<span class="error">{error}</span>"#);
+    };
+
+    let label = match error {
+        EvalError::UnboundVariable { variable, .. } if variable.0 == SYNTAX_ERROR => {
+            "this part could not be parsed"
+        }
+        EvalError::UnboundVariable { .. } => "this variable is not bound",
+        EvalError::NotALambda { .. } => "this function is not a lambda",
+        _ => "this case expression got stuck",
+    };
+
+    let mut output = Vec::<u8>::new();
+    Report::build(ReportKind::Error, (), span.start)
+        .with_message(error.to_string())
+        .with_label(
+            Label::new(span.clone())
+                .with_message(label)
+                .with_color(Color::Red),
+        )
+        .finish()
+        .write_for_stdout(Source::from(source), &mut output)
+        .unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+fn too_large_report(error: pretty::TooLarge) -> String {
+    format!(r#"<span class="error">{error}</span>"#)
+}
+
+/// The size of the printed trace after which it is cut off, since every step prints the whole term
+const MAX_TRACE_OUTPUT: usize = 1 << 22;
+
+/// Print every step of the small-step reduction of a program, until a limit is exceeded or the output gets too long
+fn print_trace(
+    source: &str,
+    program: Program<Expr>,
+    limits: &EvalLimits,
+) -> Result<String, String> {
+    let trace = trace(program, limits);
+    let mut output = trace
+        .term()
+        .map(pretty::concrete)
+        .transpose()
+        .map_err(too_large_report)?
+        .unwrap_or_default();
+
+    for (i, step) in trace.enumerate() {
+        if output.len() > MAX_TRACE_OUTPUT {
+            return Err(format!(
+                "{output}\n<span class=\"error\">The trace is too long to print, it was stopped after {i} steps</span>"
+            ));
+        }
+
+        match step {
+            Ok(Step {
+                rule,
+                position,
+                expr,
+            }) => match pretty::concrete(&expr) {
+                Ok(expr) => write!(&mut output, "\n⟶ {rule} at {position:?}\n{expr}").unwrap(),
+                Err(error) => return Err(format!("{output}\n{}", too_large_report(error))),
+            },
+            Err(error) => return Err(format!("{output}\n{}", eval_error_report(source, &error))),
+        }
+    }
+
+    Ok(output)
+}
+
+/// Print the value or error of every binding, with its statistics
+fn print_notebook(source: &str, program: Program<Expr>, limits: &EvalLimits) -> String {
+    let cells: Vec<String> = eval_notebook(program, limits)
+        .into_iter()
+        .map(|Cell { name, outcome, .. }| {
+            let name = name.map(|x| format!("{x} = ")).unwrap_or_default();
+            match outcome {
+                Outcome::Evaluated(Ok(value), stats) => format!(
+                    r#"{name}{}<div class="stats">{stats}</div>"#,
+                    pretty::concrete(&value).unwrap_or_else(too_large_report)
+                ),
+                Outcome::Evaluated(Err(error), stats) => format!(
+                    "{name}\n{}<div class=\"stats\">{stats}</div>",
+                    eval_error_report(source, &error)
+                ),
+                Outcome::Open(free) if free.iter().any(|x| x.0 == SYNTAX_ERROR) => {
+                    format!("{name}? (not evaluated, a part of it could not be parsed)")
+                }
+                Outcome::Open(free) => {
+                    let free: Vec<String> = free.iter().map(ToString::to_string).collect();
+                    format!(
+                        "{name}? (not evaluated, it depends on the free variables {})",
+                        free.join(", ")
+                    )
+                }
+            }
+        })
+        .collect();
+
+    cells.join("<hr>")
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Printer {
     Concrete,
+    /// The concrete syntax with number and list literals
+    Sugared,
     Abstract,
     Debug,
+    /// Show every reduction step instead of only the final value
+    Trace,
+    /// Show the big-step derivation tree of the value
+    Derivation,
+    /// Show the normal form, which is also reduced under lambdas and in case branches
+    Normalize,
+    /// Show the value of every top-level binding, like the cells of a notebook
+    Notebook,
 }
 
 impl TryFrom<&str> for Printer {
@@ -82,8 +461,13 @@ impl TryFrom<&str> for Printer {
     fn try_from(value: &str) -> Result<Self, ()> {
         match value {
             "concrete" => Ok(Printer::Concrete),
+            "sugared" => Ok(Printer::Sugared),
             "abstract" => Ok(Printer::Abstract),
             "debug" => Ok(Printer::Debug),
+            "trace" => Ok(Printer::Trace),
+            "derivation" => Ok(Printer::Derivation),
+            "normalize" => Ok(Printer::Normalize),
+            "notebook" => Ok(Printer::Notebook),
             _ => Err(()),
         }
     }
@@ -0,0 +1,318 @@
+/// Imports of other files, `import "nat.chi";`. The imported files are read by a `SourceResolver` before the program
+/// is parsed, and the definitions of an imported file become `let` bindings of the program that imports it
+/// (the definitions of the files that it imports included). An imported file is parsed like a program, except that it
+/// does not need to end with an expression (if it does, the expression is ignored, so the file can also be run on its own).
+/// The path of an import is relative to the file that contains it.
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+};
+
+use chumsky::error::Rich;
+
+use crate::{
+    data::DataType,
+    lexer::Token,
+    parser::{self, tokens, Branch, Span, Variable},
+    MetaExpr,
+};
+
+/// Finds the source of the imported files
+pub trait SourceResolver {
+    /// The source of the file at `path`, or a message that explains why it can not be read
+    fn resolve(&self, path: &str) -> Result<String, String>;
+}
+
+/// Reads the imported files from the file system, the paths are relative to `root`
+/// (a path that a file imports is first made relative to the root, see `imported_path`)
+pub struct FileResolver {
+    root: PathBuf,
+}
+
+impl FileResolver {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl SourceResolver for FileResolver {
+    fn resolve(&self, path: &str) -> Result<String, String> {
+        fs::read_to_string(self.root.join(path)).map_err(|error| error.to_string())
+    }
+}
+
+/// Imported files that are kept in memory, by their paths
+#[derive(Debug, Default, Clone)]
+pub struct MemoryResolver(HashMap<String, String>);
+
+impl MemoryResolver {
+    pub fn insert(&mut self, path: impl Into<String>, source: impl Into<String>) {
+        self.0.insert(path.into(), source.into());
+    }
+}
+
+impl SourceResolver for MemoryResolver {
+    fn resolve(&self, path: &str) -> Result<String, String> {
+        self.0
+            .get(path)
+            .cloned()
+            .ok_or_else(|| "there is no such file".to_string())
+    }
+}
+
+/// The path of the file that is imported as `path` by `file` (`None` for the program itself):
+/// relative paths are relative to the directory of `file`, and `.` and `..` are removed,
+/// so that a file has the same path however it is imported
+pub(crate) fn imported_path(file: Option<&str>, path: &str) -> String {
+    let directory = match file {
+        Some(file) if !path.starts_with('/') => {
+            file.rsplit_once('/').map_or("", |(directory, _)| directory)
+        }
+        _ => "",
+    };
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in directory.split('/').chain(path.split('/')) {
+        match segment {
+            "" | "." => {}
+            ".." if segments.last().is_some_and(|last| *last != "..") => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    let absolute = directory.starts_with('/') || path.starts_with('/');
+    let path = segments.join("/");
+    if absolute {
+        format!("/{path}")
+    } else {
+        path
+    }
+}
+
+/// An error in the program or in one of the files it imports
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// The imported file that contains the error, `None` for the program itself
+    pub file: Option<String>,
+    pub span: Span,
+    pub message: String,
+    /// Describes the part of the source at `span`
+    pub label: String,
+}
+
+impl Diagnostic {
+    pub(crate) fn new(file: Option<&str>, error: &Rich<'_, Token<'_>>) -> Self {
+        // The spans of some errors end before they start, see `parse_error_report`
+        let span = if error.span().start > error.span().end {
+            error.span().end..error.span().start
+        } else {
+            error.span().start..error.span().end
+        };
+        Self {
+            file: file.map(str::to_string),
+            span,
+            message: error.to_string(),
+            label: error.reason().to_string(),
+        }
+    }
+}
+
+/// The definitions of an imported file, and the data types it declares
+#[derive(Debug, Clone)]
+pub(crate) struct Module {
+    pub definitions: Vec<(Option<String>, Variable, MetaExpr)>,
+    pub data_types: Vec<DataType>,
+}
+
+/// The imported files by path, `None` if a file could not be parsed (its errors are reported when it is loaded)
+pub(crate) type Modules = HashMap<String, Option<Module>>;
+
+impl Module {
+    /// Does the file define `x`
+    pub fn defines(&self, x: &Variable) -> bool {
+        self.definitions.iter().any(|(_, y, _)| y == x)
+    }
+
+    /// The bindings of `import "path" as alias (names)`: the definitions are named `alias.x` if there is an alias,
+    /// and the ones that are not among the `names` can not be used by the importing program.
+    /// They are still bound (with a name that can not be written), since the other definitions may use them.
+    /// The locations are removed, since they refer to the source of the imported file
+    pub fn import(
+        &self,
+        path: &str,
+        alias: Option<&str>,
+        names: Option<&[Variable]>,
+    ) -> Vec<(Option<String>, Variable, MetaExpr)> {
+        let renaming: HashMap<Variable, Variable> = self
+            .definitions
+            .iter()
+            .map(|(_, x, _)| {
+                let visible = names.is_none_or(|names| names.contains(x));
+                let name = match (visible, alias) {
+                    (true, None) => x.clone(),
+                    (true, Some(alias)) => Variable(format!("{alias}.{x}")),
+                    (false, _) => Variable(format!("{path}:{x}")),
+                };
+                (x.clone(), name)
+            })
+            .collect();
+
+        self.definitions
+            .iter()
+            .map(|(doc, x, e)| {
+                (
+                    doc.clone(),
+                    renaming[x].clone(),
+                    rename(e.clone(), &renaming, &mut Vec::new()),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Rename the free variables of `e` that are not `bound`, and remove its locations.
+/// Coded literals are not changed, since their variables are just symbols
+fn rename(
+    e: MetaExpr,
+    renaming: &HashMap<Variable, Variable>,
+    bound: &mut Vec<Variable>,
+) -> MetaExpr {
+    let under = |xs: &[Variable], e: MetaExpr, bound: &mut Vec<Variable>| {
+        bound.extend(xs.iter().cloned());
+        let e = rename(e, renaming, bound);
+        bound.truncate(bound.len() - xs.len());
+        e
+    };
+
+    match e {
+        MetaExpr::Var(x) if !bound.contains(&x) => {
+            MetaExpr::Var(renaming.get(&x).cloned().unwrap_or(x))
+        }
+        MetaExpr::Apply(e1, e2) => MetaExpr::Apply(
+            Box::new(rename(*e1, renaming, bound)),
+            Box::new(rename(*e2, renaming, bound)),
+        ),
+        MetaExpr::Lambda(x, e) => {
+            let e = under(std::slice::from_ref(&x), *e, bound);
+            MetaExpr::Lambda(x, Box::new(e))
+        }
+        MetaExpr::Lambdas(xs, e) => {
+            let e = under(&xs, *e, bound);
+            MetaExpr::Lambdas(xs, Box::new(e))
+        }
+        MetaExpr::Rec(x, e) => {
+            let e = under(std::slice::from_ref(&x), *e, bound);
+            MetaExpr::Rec(x, Box::new(e))
+        }
+        MetaExpr::Let(x, e1, e2) => {
+            let e1 = rename(*e1, renaming, bound);
+            let e2 = under(std::slice::from_ref(&x), *e2, bound);
+            MetaExpr::Let(x, Box::new(e1), Box::new(e2))
+        }
+        MetaExpr::Case(e, branches) => MetaExpr::Case(
+            Box::new(rename(*e, renaming, bound)),
+            branches
+                .into_iter()
+                .map(|Branch(c, xs, e)| {
+                    let e = under(&xs, e, bound);
+                    Branch(c, xs, e)
+                })
+                .collect(),
+        ),
+        MetaExpr::Const(c, es) => MetaExpr::Const(
+            c,
+            es.into_iter().map(|e| rename(e, renaming, bound)).collect(),
+        ),
+        MetaExpr::Located(_, e) => rename(*e, renaming, bound),
+        e @ (MetaExpr::Var(_) | MetaExpr::Nat(_) | MetaExpr::Coded(_) | MetaExpr::Error) => e,
+    }
+}
+
+/// Loads the files that a program imports, and the files they import
+pub(crate) struct Loader<'r> {
+    resolver: &'r dyn SourceResolver,
+    modules: Modules,
+    /// The files that are being loaded, each one is imported by the one before it
+    loading: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'r> Loader<'r> {
+    pub fn new(resolver: &'r dyn SourceResolver) -> Self {
+        Self {
+            resolver,
+            modules: Modules::new(),
+            loading: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Load the files imported by `source`, which is the imported file `file` or the program itself
+    pub fn load_imports(&mut self, file: Option<&str>, source: &str) {
+        let mut seen = HashSet::new();
+        let imports = tokens(source)
+            .into_iter()
+            .filter_map(|(token, span)| match token {
+                Token::Import(path) => Some((imported_path(file, path), span)),
+                _ => None,
+            });
+
+        for (path, span) in imports {
+            let path = path.as_str();
+            if self.modules.contains_key(path) || !seen.insert(path.to_string()) {
+                continue;
+            }
+
+            let error = |message| Diagnostic {
+                file: file.map(str::to_string),
+                span: span.into_range(),
+                message,
+                label: "imported here".to_string(),
+            };
+
+            if let Some(i) = self.loading.iter().position(|loading| loading == path) {
+                let cycle = self.loading[i..].join(" -> ");
+                self.diagnostics.push(error(format!(
+                    "{path} can not be imported here, since it imports itself: {cycle} -> {path}"
+                )));
+                // The import of the cycle does not need to be reported by the parser
+                self.modules.insert(path.to_string(), None);
+                continue;
+            }
+
+            let source = match self.resolver.resolve(path) {
+                Ok(source) => source,
+                Err(message) => {
+                    self.diagnostics
+                        .push(error(format!("Could not import {path}: {message}")));
+                    self.modules.insert(path.to_string(), None);
+                    continue;
+                }
+            };
+
+            self.loading.push(path.to_string());
+            self.load_imports(Some(path), &source);
+            self.loading.pop();
+
+            let module = match parser::parse_module(path, &source, &mut self.modules) {
+                Ok(module) => Some(module),
+                Err(errors) => {
+                    self.diagnostics.extend(
+                        errors
+                            .iter()
+                            .map(|error| Diagnostic::new(Some(path), error)),
+                    );
+                    None
+                }
+            };
+            self.modules.insert(path.to_string(), module);
+        }
+    }
+
+    /// The loaded files, and the errors of loading them
+    pub fn finish(self) -> (Modules, Vec<Diagnostic>) {
+        (self.modules, self.diagnostics)
+    }
+}
@@ -0,0 +1,184 @@
+use std::fs;
+
+use crate::{
+    eval, parse, parse_with_imports, replace_coded_literals, Diagnostic, EvalError, EvalLimits,
+    Expr, FileResolver, MemoryResolver, Printer, Run, StandardCoder,
+};
+
+const NAT: &str = r"
+    data Nat = Zero() | Suc(Nat);
+    let rec add x y = case x of { Zero() -> y; Suc(n) -> Suc(add n y) };
+    let rec mul x y = case x of { Zero() -> 0; Suc(n) -> add y (mul n y) };
+    -- the expression at the end is ignored when the file is imported
+    mul 2 3
+";
+
+const LIST: &str = r#"
+    import "nat.chi" (add);
+    let rec sum xs = case xs of { Nil() -> 0; Cons(x, xs) -> add x (sum xs) };
+"#;
+
+fn files() -> MemoryResolver {
+    let mut files = MemoryResolver::default();
+    files.insert("nat.chi", NAT);
+    files.insert("list.chi", LIST);
+    files
+}
+
+fn run(source: &str, files: &MemoryResolver) -> Result<Expr, EvalError> {
+    let program = parse_with_imports(source, files).0.unwrap();
+    let program = replace_coded_literals(program, &mut StandardCoder::default());
+    eval(program, &EvalLimits::default())
+}
+
+fn value(source: &str) -> Result<Expr, EvalError> {
+    run(source, &MemoryResolver::default())
+}
+
+fn diagnostics(source: &str, files: &MemoryResolver) -> Vec<Diagnostic> {
+    parse_with_imports(source, files).0.unwrap_err()
+}
+
+#[test]
+fn imports() {
+    assert_eq!(
+        run(r#"import "nat.chi"; mul 2 (add 1 2)"#, &files()),
+        value("6")
+    );
+    assert_eq!(
+        run(r#"import "nat.chi" as Nat; Nat.mul 2 3"#, &files()),
+        value("6")
+    );
+
+    // The definitions that are not selected can still be used by the selected ones
+    assert_eq!(
+        run(r#"import "nat.chi" (mul,); mul 2 3"#, &files()),
+        value("6")
+    );
+    assert!(matches!(
+        run(r#"import "nat.chi" as Nat (mul); add"#, &files()),
+        Err(EvalError::UnboundVariable { .. })
+    ));
+
+    // `import` is not a keyword, and an import is a statement
+    assert_eq!(value("let import = A(); import"), value("A()"));
+    assert_eq!(
+        value(r#"let import = \x. x; import "A()""#),
+        value(r#""A()""#)
+    );
+    assert_eq!(
+        value(r#"let import = \x y. x; import "A()" (case B() of { B() -> B(); C() -> C() })"#),
+        value(r#""A()""#)
+    );
+
+    // A qualified name is written without spaces
+    let source = r#"import "nat.chi" as Nat; Nat . mul 2 3"#;
+    let errors = diagnostics(source, &files());
+    assert_eq!(errors.len(), 1);
+    assert_eq!(&source[errors[0].span.clone()], "Nat . mul");
+}
+
+#[test]
+fn nested_imports() {
+    // Both files declare the data type of `nat.chi`, which is not an error
+    assert_eq!(
+        run(
+            r#"import "list.chi"; import "nat.chi" as Nat; Nat.add (sum [1, 2]) 3"#,
+            &files()
+        ),
+        value("6")
+    );
+
+    // The declarations of imported files are checked
+    let source = r#"import "nat.chi" (add); add Sucs() 0"#;
+    let errors = diagnostics(source, &files());
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].file, None);
+    assert_eq!(&source[errors[0].span.clone()], "Sucs()");
+
+    // The paths are relative to the importing file, and a file is loaded once however its path is written
+    let mut files = MemoryResolver::default();
+    files.insert("lib/nat.chi", NAT);
+    files.insert("lib/list.chi", LIST);
+    files.insert(
+        "test/sum.chi",
+        r#"import "../lib/list.chi"; let six = sum [1, 2, 3];"#,
+    );
+    assert_eq!(
+        run(
+            r#"import "test/sum.chi"; import "./lib/../lib/nat.chi" as Nat; Nat.add six 0"#,
+            &files
+        ),
+        value("6")
+    );
+}
+
+#[test]
+fn import_errors() {
+    let source = r#"import "nat.chi" (add, sub); import "int.chi"; add"#;
+    let errors = diagnostics(source, &files());
+    assert_eq!(errors.len(), 2);
+    assert_eq!(&source[errors[0].span.clone()], r#"import "int.chi""#);
+    assert_eq!(
+        errors[0].message,
+        "Could not import int.chi: there is no such file"
+    );
+    assert_eq!(&source[errors[1].span.clone()], "sub");
+
+    // The errors in an imported file name the file
+    let mut files = files();
+    files.insert("broken.chi", "let x = A(;");
+    let errors = diagnostics(r#"import "broken.chi"; x"#, &files);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].file.as_deref(), Some("broken.chi"));
+    assert_eq!(errors[0].span, 10..11);
+
+    // Imports are only resolved by `parse_with_imports`
+    assert!(parse(r#"import "nat.chi"; add"#).is_err());
+}
+
+#[test]
+fn import_cycles() {
+    let mut files = MemoryResolver::default();
+    files.insert("a.chi", r#"import "b.chi"; let a = A();"#);
+    files.insert("b.chi", r#"import "c.chi"; let b = B();"#);
+    files.insert("c.chi", r#"import "a.chi"; let c = C();"#);
+    files.insert("self.chi", r#"import "self.chi";"#);
+
+    let errors = diagnostics(r#"import "a.chi"; a"#, &files);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].file.as_deref(), Some("c.chi"));
+    assert_eq!(
+        errors[0].message,
+        "a.chi can not be imported here, since it imports itself: a.chi -> b.chi -> c.chi -> a.chi"
+    );
+
+    assert_eq!(diagnostics(r#"import "self.chi"; A()"#, &files).len(), 1);
+}
+
+#[test]
+fn file_resolver() {
+    let root = std::env::temp_dir().join(format!("chi_imports_{}", std::process::id()));
+    fs::create_dir_all(root.join("lib")).unwrap();
+    fs::write(root.join("lib/nat.chi"), NAT).unwrap();
+
+    let resolver = FileResolver::new(&root);
+    let report = |source| {
+        Run::with_resolver(source, Printer::Concrete, &EvalLimits::default(), &resolver)
+            .and_then(|mut run| {
+                run.step(u64::MAX);
+                run.finish()
+            })
+            .map(|(output, ..)| output)
+    };
+    assert_eq!(
+        report(r#"import "lib/nat.chi"; mul 2 2"#),
+        Ok("Suc(Suc(Suc(Suc(Zero()))))".to_string())
+    );
+
+    fs::write(root.join("lib/broken.chi"), "let x = \\. x;").unwrap();
+    let error = report(r#"import "lib/broken.chi"; x"#).unwrap_err();
+    assert!(error.contains("lib/broken.chi"), "{error}");
+
+    fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,431 @@
+/// A normalizer that, unlike `eval`, also reduces under lambdas, inside case branches and in constructor arguments.
+/// The redexes are contracted in normal order (leftmost-outermost), so the normal form is found whenever it exists.
+///
+/// Since the reductions happen under binders, the terms may be open and the substitution must rename
+/// bound variables to avoid capturing the free variables of the replacement.
+/// Applications and case expressions that are stuck on a free variable are left as they are.
+///
+/// Note: recursive functions applied to free variables typically have no normal form, since the `rec` keeps unfolding
+/// inside the branches. Like `eval`, the normalizer keeps the pending subterms in an explicit stack, so an infinite
+/// normal form stops at `max_depth`. The substitution still recurses on the native stack, so it is meant for terms
+/// that are not too deeply nested.
+use std::{collections::HashSet, mem};
+
+use crate::{
+    eval::{Budget, Definitions},
+    parser::{Branch, Constructor, Variable},
+    EvalError, EvalLimits,
+    Expr::{self, *},
+    Program,
+};
+
+/// Compute the normal form of a program, the `max_steps` limit is the fuel and bounds the number of reductions.
+/// Locations are removed before normalizing, so the errors are not located.
+/// The definitions are looked up when they are reached, the normal form does not refer to them.
+pub fn normalize(program: Program<Expr>, limits: &EvalLimits) -> Result<Expr, EvalError> {
+    let (definitions, expr) = Definitions::new(program);
+    let expr = expr.without_spans();
+    let mut normalizer = Normalizer {
+        budget: Budget::new(limits),
+        stack: Vec::new(),
+        definitions: definitions.without_spans(),
+    };
+    normalizer.budget.check_size(&expr)?;
+    normalizer.normal_form(expr)
+}
+
+struct Normalizer {
+    budget: Budget,
+    /// The subterms whose normal form is being computed, the innermost one on top.
+    /// Together with the spine of `weak_head_normal_form` its length is bounded by `max_depth`
+    stack: Vec<Frame>,
+    definitions: Definitions,
+}
+
+/// The contexts of `normal_form`, `□` marks the position of the subterm being normalized.
+/// The subterms before it are already normal, the remaining ones are stored in reverse order
+enum Frame {
+    /// `\x. □`
+    Lambda(Variable),
+    /// `c(n1, ..., □, e1, ...)`
+    Const(Constructor, Vec<Expr>, Vec<Expr>),
+    /// `□ e2`, where the expression in the hole is stuck
+    ApplyFun(Expr),
+    /// `n1 □`
+    ApplyArg(Expr),
+    /// `case □ of { branches }`, where the expression in the hole is stuck
+    Scrutinee(Vec<Branch<Expr>>),
+    /// `case n of { b1; ...; c(xs) -> □; b2; ... }`
+    Branch {
+        scrutinee: Expr,
+        normalized: Vec<Branch<Expr>>,
+        constructor: Constructor,
+        variables: Vec<Variable>,
+        rest: Vec<Branch<Expr>>,
+    },
+}
+
+/// The contexts of `weak_head_normal_form`, the head of an application or case expression
+enum Spine {
+    /// `□ e2`
+    Apply(Expr),
+    /// `case □ of { branches }`
+    Case(Vec<Branch<Expr>>),
+}
+
+impl Normalizer {
+    fn push(&mut self, frame: Frame) -> Result<(), EvalError> {
+        self.budget.check_depth(self.stack.len())?;
+        self.stack.push(frame);
+        Ok(())
+    }
+
+    fn normal_form(&mut self, mut expr: Expr) -> Result<Expr, EvalError> {
+        loop {
+            let mut whnf = self.weak_head_normal_form(expr)?;
+            let mut normal = match &mut whnf {
+                Lambda(x, e) => {
+                    self.push(Frame::Lambda(x.clone()))?;
+                    expr = e.take();
+                    continue;
+                }
+                Const(c, es) => {
+                    es.reverse();
+                    match es.pop() {
+                        Some(e) => {
+                            self.push(Frame::Const(c.clone(), Vec::new(), mem::take(es)))?;
+                            expr = e;
+                            continue;
+                        }
+                        None => whnf,
+                    }
+                }
+                // Stuck on a free variable
+                Apply(e1, e2) => {
+                    self.push(Frame::ApplyFun(e2.take()))?;
+                    expr = e1.take();
+                    continue;
+                }
+                Case(e, branches) => {
+                    self.push(Frame::Scrutinee(mem::take(branches)))?;
+                    expr = e.take();
+                    continue;
+                }
+                Var(_) | Nat(_) => whnf,
+                Rec(..) | Located(..) | Global(..) => unreachable!("not a weak head normal form"),
+            };
+
+            // Pass the normal form on to the frames until one of them has a subterm left to normalize
+            expr = loop {
+                let Some(frame) = self.stack.pop() else {
+                    return Ok(normal);
+                };
+                match frame {
+                    Frame::Lambda(x) => normal = Lambda(x, Box::new(normal)),
+                    Frame::Const(c, mut normals, mut rest) => {
+                        normals.push(normal);
+                        match rest.pop() {
+                            Some(e) => {
+                                self.push(Frame::Const(c, normals, rest))?;
+                                break e;
+                            }
+                            None => normal = Const(c, normals),
+                        }
+                    }
+                    Frame::ApplyFun(e2) => {
+                        self.push(Frame::ApplyArg(normal))?;
+                        break e2;
+                    }
+                    Frame::ApplyArg(e1) => normal = Apply(Box::new(e1), Box::new(normal)),
+                    Frame::Scrutinee(mut branches) => {
+                        branches.reverse();
+                        match self.next_branch(normal, Vec::new(), branches)? {
+                            Ok(e) => break e,
+                            Err(case) => normal = case,
+                        }
+                    }
+                    Frame::Branch {
+                        scrutinee,
+                        mut normalized,
+                        constructor,
+                        variables,
+                        rest,
+                    } => {
+                        normalized.push(Branch(constructor, variables, normal));
+                        match self.next_branch(scrutinee, normalized, rest)? {
+                            Ok(e) => break e,
+                            Err(case) => normal = case,
+                        }
+                    }
+                }
+            };
+        }
+    }
+
+    /// Continue with the next branch of a stuck case expression, or return the case expression if all its branches
+    /// are normalized. The remaining branches are in reverse order
+    fn next_branch(
+        &mut self,
+        scrutinee: Expr,
+        normalized: Vec<Branch<Expr>>,
+        mut rest: Vec<Branch<Expr>>,
+    ) -> Result<Result<Expr, Expr>, EvalError> {
+        Ok(match rest.pop() {
+            Some(Branch(constructor, variables, e)) => {
+                self.push(Frame::Branch {
+                    scrutinee,
+                    normalized,
+                    constructor,
+                    variables,
+                    rest,
+                })?;
+                Ok(e)
+            }
+            None => Err(Case(Box::new(scrutinee), normalized)),
+        })
+    }
+
+    /// Reduce the expression until its outermost node is not a redex
+    fn weak_head_normal_form(&mut self, mut expr: Expr) -> Result<Expr, EvalError> {
+        let mut spine = Vec::new();
+        loop {
+            let mut value = match &mut expr {
+                Apply(e1, e2) => {
+                    self.budget.check_depth(self.stack.len() + spine.len())?;
+                    spine.push(Spine::Apply(e2.take()));
+                    expr = e1.take();
+                    continue;
+                }
+                Case(e, branches) => {
+                    self.budget.check_depth(self.stack.len() + spine.len())?;
+                    spine.push(Spine::Case(mem::take(branches)));
+                    expr = e.take();
+                    continue;
+                }
+                Rec(x, e) => {
+                    let rec = Rec(x.clone(), e.clone());
+                    expr = self
+                        .budget
+                        .reduce(substitute(&[(x.clone(), rec)], e.take()))?;
+                    continue;
+                }
+                Located(_, e) => {
+                    expr = e.take();
+                    continue;
+                }
+                // Looking up a definition is not a reduction
+                Global(_, index) => {
+                    expr = self.definitions.get(*index).clone();
+                    continue;
+                }
+                Lambda(..) | Const(..) | Var(_) | Nat(_) => expr,
+            };
+
+            // Pass the head on to the spine until there is a redex, the expression is stuck if the head is
+            expr = loop {
+                let Some(context) = spine.pop() else {
+                    return Ok(value);
+                };
+                match context {
+                    Spine::Apply(e2) => match &mut value {
+                        Lambda(x, e) => {
+                            break self
+                                .budget
+                                .reduce(substitute(&[(x.clone(), e2)], e.take()))?
+                        }
+                        Const(..) | Nat(_) => {
+                            return Err(EvalError::NotALambda {
+                                expr: Apply(Box::new(value.clone()), Box::new(e2)),
+                                value,
+                                span: None,
+                            })
+                        }
+                        _ => value = Apply(Box::new(value), Box::new(e2)),
+                    },
+                    Spine::Case(branches) => match value.unfold() {
+                        Const(ref c, ref mut es) => {
+                            let es = mem::take(es);
+                            let stuck =
+                                || Case(Box::new(Const(c.clone(), es.clone())), branches.clone());
+                            let Some(Branch(_, xs, e)) =
+                                branches.iter().find(|Branch(b, ..)| b == c)
+                            else {
+                                return Err(EvalError::MissingBranch {
+                                    constructor: c.clone(),
+                                    available: branches
+                                        .iter()
+                                        .map(|Branch(c, ..)| c.clone())
+                                        .collect(),
+                                    expr: stuck(),
+                                    span: None,
+                                });
+                            };
+                            if xs.len() != es.len() {
+                                return Err(EvalError::ArityMismatch {
+                                    constructor: c.clone(),
+                                    expected: xs.len(),
+                                    actual: es.len(),
+                                    expr: stuck(),
+                                    span: None,
+                                });
+                            }
+
+                            let substitution: Vec<_> = xs.iter().cloned().zip(es).collect();
+                            break self.budget.reduce(substitute(&substitution, e.clone()))?;
+                        }
+                        value @ Lambda(..) => {
+                            return Err(EvalError::NotAConstructor {
+                                expr: Case(Box::new(value.clone()), branches),
+                                value,
+                                span: None,
+                            })
+                        }
+                        e => value = Case(Box::new(e), branches),
+                    },
+                }
+            };
+        }
+    }
+}
+
+/// The free variables of an expression
+pub(crate) fn free_variables(expr: &Expr) -> HashSet<Variable> {
+    /// The expressions are visited with an explicit stack, since they can be very deeply nested
+    enum Task<'a> {
+        Visit(&'a Expr),
+        Bind(&'a [Variable]),
+        Unbind(usize),
+    }
+
+    let mut free = HashSet::new();
+    let mut bound = Vec::new();
+    let mut todo = vec![Task::Visit(expr)];
+    while let Some(task) = todo.pop() {
+        let expr = match task {
+            Task::Visit(expr) => expr,
+            Task::Bind(xs) => {
+                bound.extend(xs);
+                continue;
+            }
+            Task::Unbind(n) => {
+                bound.truncate(bound.len() - n);
+                continue;
+            }
+        };
+
+        match expr {
+            Apply(e1, e2) => todo.extend([Task::Visit(e2), Task::Visit(e1)]),
+            Lambda(x, e) | Rec(x, e) => todo.extend([
+                Task::Unbind(1),
+                Task::Visit(e),
+                Task::Bind(std::slice::from_ref(x)),
+            ]),
+            Case(e, branches) => {
+                for Branch(_, xs, e) in branches.iter().rev() {
+                    todo.extend([Task::Unbind(xs.len()), Task::Visit(e), Task::Bind(xs)]);
+                }
+                todo.push(Task::Visit(e));
+            }
+            Var(x) if !bound.contains(&x) => {
+                free.insert(x.clone());
+            }
+            Var(_) | Nat(_) | Global(..) => {}
+            Const(_, es) => todo.extend(es.iter().rev().map(Task::Visit)),
+            Located(_, e) => todo.push(Task::Visit(e)),
+        }
+    }
+    free
+}
+
+/// A variable based on `x` that is not in `avoid`, made by replacing the trailing digits of `x` with a number
+pub(crate) fn fresh(x: &Variable, avoid: &HashSet<Variable>) -> Variable {
+    let base = x.0.trim_end_matches(|c: char| c.is_ascii_digit());
+    (1..)
+        .map(|n| Variable(format!("{base}{n}")))
+        .find(|y| !avoid.contains(y))
+        .unwrap()
+}
+
+/// Simultaneously replace the free occurrences of the variables in `substitution` by their expressions.
+/// If a variable occurs more than once, the last one is used.
+/// Unlike `eval::substitute`, the replacements may be open terms, bound variables are renamed to avoid capturing them.
+pub(crate) fn substitute(substitution: &[(Variable, Expr)], mut expr: Expr) -> Expr {
+    match &mut expr {
+        Apply(e1, e2) => Apply(
+            Box::new(substitute(substitution, e1.take())),
+            Box::new(substitute(substitution, e2.take())),
+        ),
+        Lambda(x, e) => {
+            let (xs, e) = substitute_under(substitution, vec![x.clone()], e.take());
+            Lambda(xs.into_iter().next().unwrap(), Box::new(e))
+        }
+        Rec(x, e) => {
+            let (xs, e) = substitute_under(substitution, vec![x.clone()], e.take());
+            Rec(xs.into_iter().next().unwrap(), Box::new(e))
+        }
+        Case(e, branches) => Case(
+            Box::new(substitute(substitution, e.take())),
+            mem::take(branches)
+                .into_iter()
+                .map(|Branch(c, xs, e)| {
+                    let (xs, e) = substitute_under(substitution, xs, e);
+                    Branch(c, xs, e)
+                })
+                .collect(),
+        ),
+        Var(x) => match substitution.iter().rev().find(|(y, _)| y == x) {
+            Some((_, replacement)) => replacement.clone(),
+            None => expr,
+        },
+        Const(c, es) => Const(
+            c.clone(),
+            mem::take(es)
+                .into_iter()
+                .map(|e| substitute(substitution, e))
+                .collect(),
+        ),
+        Located(span, e) => Located(span.clone(), Box::new(substitute(substitution, e.take()))),
+        Nat(_) | Global(..) => expr,
+    }
+}
+
+/// Substitute into `expr` which is under the binders `xs`, renaming the binders that would capture a free variable
+fn substitute_under(
+    substitution: &[(Variable, Expr)],
+    xs: Vec<Variable>,
+    expr: Expr,
+) -> (Vec<Variable>, Expr) {
+    // The binders shadow the substituted variables
+    let mut inner: Vec<(Variable, Expr)> = substitution
+        .iter()
+        .filter(|(y, _)| !xs.contains(y))
+        .cloned()
+        .collect();
+    if inner.is_empty() {
+        return (xs, expr);
+    }
+
+    let captured: HashSet<Variable> = inner.iter().flat_map(|(_, e)| free_variables(e)).collect();
+    if xs.iter().all(|x| !captured.contains(x)) {
+        return (xs, substitute(&inner, expr));
+    }
+
+    let mut avoid = free_variables(&expr);
+    avoid.extend(captured.iter().cloned());
+    avoid.extend(xs.iter().cloned());
+
+    let xs = xs
+        .into_iter()
+        .map(|x| {
+            if !captured.contains(&x) {
+                return x;
+            }
+            let y = fresh(&x, &avoid);
+            avoid.insert(y.clone());
+            inner.push((x, Var(y.clone())));
+            y
+        })
+        .collect();
+
+    (xs, substitute(&inner, expr))
+}
@@ -0,0 +1,122 @@
+use crate::{
+    alpha_eq, normalize,
+    test_utils::{expr, program},
+    EvalError, EvalLimits, Expr, Limit, Program,
+};
+
+fn normal_form(source: &str) -> Program<Expr> {
+    Program::Expr(normalize(program(source), &EvalLimits::default()).unwrap())
+}
+
+#[test]
+fn reduces_under_lambda() {
+    assert_eq!(normal_form(r"\x. (\y. y) x"), program(r"\x. x"));
+    assert_eq!(
+        normal_form(r"\x. case C(x) of { C(y) -> D(y, (\z. z) A()) }"),
+        program(r"\x. D(x, A())")
+    );
+}
+
+#[test]
+fn stuck_on_free_variables() {
+    assert_eq!(
+        normal_form(r"\f. \x. case f x of { A() -> (\y. y) B() }"),
+        program(r"\f. \x. case f x of { A() -> B() }")
+    );
+    assert_eq!(normal_form("x"), program("x"));
+}
+
+#[test]
+fn behaviourally_equal_functions() {
+    // Both are the composition of the identity function with itself
+    let compose = r"let compose = \f. \g. \x. f (g x);";
+    let Program::Expr(composition) = normal_form(&format!(r"{compose} compose (\x. x) (\y. y)"))
+    else {
+        unreachable!()
+    };
+    assert!(alpha_eq(&composition, &expr(r"\z. z")));
+}
+
+#[test]
+fn capture_avoiding() {
+    // Substituting y for x under \y must rename the bound y
+    assert_eq!(normal_form(r"\y. (\x. \y. x) y"), program(r"\y. \y1. y"));
+    assert_eq!(
+        normal_form(r"\y. \y1. (\x. \y. Pair(x, y, y1)) y"),
+        program(r"\y. \y1. \y2. Pair(y, y2, y1)")
+    );
+    // The same in case branches
+    assert_eq!(
+        normal_form(r"\a. (\x. case a of { C(a, b) -> Pair(x, a) }) a"),
+        program(r"\a. case a of { C(a1, b) -> Pair(a, a1) }")
+    );
+}
+
+#[test]
+fn normal_order() {
+    // The argument is never used, so it is never evaluated
+    assert_eq!(normal_form(r"(\x. C()) (rec x = x)"), program("C()"));
+}
+
+#[test]
+fn fuel() {
+    let limits = EvalLimits {
+        max_steps: Some(100),
+        ..EvalLimits::default()
+    };
+    // Recursive functions applied to free variables keep unfolding
+    let add = r"rec add = \x. \y. case x of { Zero() -> y; Suc(n) -> Suc(add n y) }";
+    assert_eq!(
+        normalize(program(&format!(r"\n. {add} n Zero()")), &limits),
+        Err(EvalError::LimitExceeded(Limit::Steps(100)))
+    );
+    // But closed terms are fine
+    assert_eq!(
+        Program::Expr(
+            normalize(program(&format!(r"({add}) Suc(Zero()) Zero()")), &limits).unwrap()
+        ),
+        program("Suc(Zero())")
+    );
+}
+
+#[test]
+fn infinite_normal_forms() {
+    // The normal form is infinite, it is cut off by the depth limit instead of the native stack
+    for source in ["rec x = S(x)", r"\y. rec x = \z. S(x z)", "rec x = x A()"] {
+        assert_eq!(
+            normalize(program(source), &EvalLimits::default()),
+            Err(EvalError::LimitExceeded(Limit::Depth(100_000))),
+            "{source}"
+        );
+    }
+}
+
+#[test]
+fn errors() {
+    assert!(matches!(
+        normalize(program(r"\x. C() x"), &EvalLimits::default()),
+        Err(EvalError::NotALambda { .. })
+    ));
+    assert!(matches!(
+        normalize(
+            program(r"\x. case C() of { D() -> x }"),
+            &EvalLimits::default()
+        ),
+        Err(EvalError::MissingBranch { .. })
+    ));
+}
+
+#[test]
+fn layered_definitions() {
+    // Substituting the definitions would create 2^40 copies of `twice0`
+    let mut src = r"let twice0 = \f. \x. f (f x);".to_string();
+    for i in 1..40 {
+        src += &format!(
+            r"let twice{i} = \f. \x. twice{} f (twice{} f x);",
+            i - 1,
+            i - 1
+        );
+    }
+    src += r"twice1 (\x. S(x))";
+    assert_eq!(normal_form(&src), program(r"\x. S(S(S(S(x))))"));
+}
@@ -0,0 +1,97 @@
+/// Evaluate every top-level binding of a program separately, like the cells of a notebook.
+/// A file with many definitions and test expressions then gives one result per binding, instead of only the value
+/// of the final expression.
+use crate::{
+    normalize::free_variables,
+    parser::{Span, Variable},
+    EvalError, EvalLimits, EvalStats, Evaluator, Expr, Program, Status,
+};
+
+/// The result of one `let` binding, or of the final expression of the program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+    /// The bound variable, `None` for the final expression
+    pub name: Option<Variable>,
+    /// The location of the right-hand side
+    pub span: Option<Span>,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// The right-hand side was evaluated, `stats` are collected even if the evaluation failed
+    Evaluated(Result<Expr, EvalError>, EvalStats),
+    /// The right-hand side was not evaluated, since these variables are not bound by earlier closed bindings
+    Open(Vec<Variable>),
+}
+
+/// Evaluate the right-hand side of every binding and the final expression, each with its own `limits`.
+/// A right-hand side is evaluated in a program with the earlier closed bindings, which are looked up like `eval` does,
+/// so the result of a cell is the same as the value of the variable in the program.
+/// A binding that is open (or that uses an open binding) is not evaluated.
+pub fn eval_notebook(mut program: Program<Expr>, limits: &EvalLimits) -> Vec<Cell> {
+    let mut cells = Vec::new();
+    // The bindings so far, the right-hand side is `None` if it is open
+    let mut bindings: Vec<(Variable, Option<Expr>)> = Vec::new();
+
+    loop {
+        let (name, expr, rest) = match program {
+            Program::Let(_, x, rhs, rest) => (Some(x), rhs, Some(*rest)),
+            Program::Expr(expr) => (None, expr, None),
+        };
+        let span = expr.span().cloned();
+
+        // The closest binding shadows the earlier ones
+        let mut open: Vec<Variable> = free_variables(&expr)
+            .into_iter()
+            .filter(|x| {
+                !matches!(
+                    bindings.iter().rev().find(|(y, _)| y == x),
+                    Some((_, Some(_)))
+                )
+            })
+            .collect();
+
+        let outcome = if open.is_empty() {
+            // The open bindings are left out, since the closed ones do not refer to them
+            let program = bindings
+                .iter()
+                .rev()
+                .filter_map(|(x, rhs)| Some((x, rhs.as_ref()?)))
+                .fold(Program::Expr(expr.clone()), |rest, (x, rhs)| {
+                    Program::Let(None, x.clone(), rhs.clone(), Box::new(rest))
+                });
+            evaluate(program, limits)
+        } else {
+            open.sort_by(|x, y| x.0.cmp(&y.0));
+            Outcome::Open(open)
+        };
+
+        let closed = matches!(outcome, Outcome::Evaluated(..));
+        cells.push(Cell {
+            name: name.clone(),
+            span,
+            outcome,
+        });
+
+        match (name, rest) {
+            (Some(x), Some(rest)) => {
+                bindings.push((x, closed.then_some(expr)));
+                program = rest;
+            }
+            _ => return cells,
+        }
+    }
+}
+
+fn evaluate(program: Program<Expr>, limits: &EvalLimits) -> Outcome {
+    let mut evaluator = Evaluator::with_stats(program, limits);
+    let result = match evaluator.step(u64::MAX) {
+        Status::Finished(value) => Ok(value.clone()),
+        Status::Failed(error) => Err(error.clone()),
+        Status::Running => {
+            unreachable!("the evaluation is not limited by the number of transitions")
+        }
+    };
+    Outcome::Evaluated(result, evaluator.stats().cloned().unwrap_or_default())
+}
@@ -0,0 +1,133 @@
+use crate::{
+    eval_notebook, parser::Variable, pretty, test_utils::program, EvalError, EvalLimits, Expr,
+    Outcome, Program,
+};
+
+fn expr(source: &str) -> Expr {
+    let Program::Expr(expr) = program(source) else {
+        panic!("expected an expression");
+    };
+    expr.without_spans()
+}
+
+fn names(source: &str) -> Vec<Option<String>> {
+    eval_notebook(program(source), &EvalLimits::default())
+        .into_iter()
+        .map(|cell| cell.name.map(|x| x.to_string()))
+        .collect()
+}
+
+#[test]
+fn every_binding() {
+    assert_eq!(
+        names(r"let id = \x. x; let test = id C(); id"),
+        [Some("id".into()), Some("test".into()), None]
+    );
+}
+
+#[test]
+fn values_and_statistics() {
+    let source = r"
+        let not = \b. case b of { True() -> False(); False() -> True() };
+        let test = not True();
+        not (not False())
+    ";
+    let cells = eval_notebook(program(source), &EvalLimits::default());
+
+    let Outcome::Evaluated(Ok(value), stats) = &cells[1].outcome else {
+        panic!("expected a value");
+    };
+    assert_eq!(*value, expr("False()"));
+    assert_eq!(stats.beta_reductions, 1);
+    assert_eq!(stats.case_reductions, 1);
+
+    let Outcome::Evaluated(Ok(value), stats) = &cells[2].outcome else {
+        panic!("expected a value");
+    };
+    assert_eq!(*value, expr("False()"));
+    assert_eq!(stats.beta_reductions, 2);
+}
+
+#[test]
+fn errors_do_not_stop_later_bindings() {
+    let cells = eval_notebook(
+        program(r"let bad = C() C(); let good = D(); good"),
+        &EvalLimits::default(),
+    );
+    assert!(matches!(
+        &cells[0].outcome,
+        Outcome::Evaluated(Err(EvalError::NotALambda { .. }), _)
+    ));
+    assert!(matches!(&cells[1].outcome, Outcome::Evaluated(Ok(_), _)));
+    assert!(matches!(&cells[2].outcome, Outcome::Evaluated(Ok(_), _)));
+}
+
+#[test]
+fn open_bindings_are_not_evaluated() {
+    let cells = eval_notebook(
+        program(r"let f = \x. y; let g = f; let x = C(); f x"),
+        &EvalLimits::default(),
+    );
+    let open: Vec<_> = cells
+        .iter()
+        .map(|cell| match &cell.outcome {
+            Outcome::Open(free) => free.clone(),
+            Outcome::Evaluated(..) => Vec::new(),
+        })
+        .collect();
+    assert_eq!(
+        open,
+        [
+            vec![Variable::from("y")],
+            vec![Variable::from("f")],
+            vec![],
+            vec![Variable::from("f")],
+        ]
+    );
+}
+
+#[test]
+fn shadowing() {
+    let cells = eval_notebook(
+        program(r"let x = A(); let y = x; let x = B(); C(x, y)"),
+        &EvalLimits::default(),
+    );
+    let Outcome::Evaluated(Ok(value), _) = &cells[3].outcome else {
+        panic!("expected a value");
+    };
+    assert_eq!(*value, expr("C(B(), A())"));
+}
+
+#[test]
+fn spans() {
+    let source = r"let x = A(); x";
+    let cells = eval_notebook(program(source), &EvalLimits::default());
+    assert_eq!(cells[0].span.clone().map(|span| &source[span]), Some("A()"));
+    assert_eq!(cells[1].span.clone().map(|span| &source[span]), Some("x"));
+}
+
+#[test]
+fn layered_definitions() {
+    // Substituting the definitions would create 2^40 copies of `twice0`
+    let mut src = r"let twice0 = \f. \x. f (f x);".to_string();
+    for i in 1..40 {
+        src += &format!(
+            r"let twice{i} = \f. \x. twice{} f (twice{} f x);",
+            i - 1,
+            i - 1
+        );
+    }
+    src += r"twice1 (\x. S(x)) Z()";
+    let cells = eval_notebook(program(&src), &EvalLimits::default());
+    let Outcome::Evaluated(Ok(value), _) = &cells[39].outcome else {
+        panic!("expected a value");
+    };
+    assert_eq!(
+        pretty::concrete(value).unwrap(),
+        r"\f. \x. twice38 f (twice38 f x)"
+    );
+    let Outcome::Evaluated(Ok(value), _) = &cells[40].outcome else {
+        panic!("expected a value");
+    };
+    assert_eq!(*value, expr("S(S(S(S(Z()))))"));
+}
@@ -1,16 +1,23 @@
-use std::fmt;
+use std::{fmt, mem, ops::Range};
 
-use crate::lexer::Token;
+use crate::{
+    data::{self, Constructors, DataType, Use},
+    lexer::Token,
+    module::{imported_path, Diagnostic, Loader, Module, Modules, SourceResolver},
+    pattern::{self, Arm, Pattern, Warning},
+};
 use chumsky::{
-    input::{Stream, ValueInput},
+    input::{Checkpoint, Cursor, Stream, ValueInput},
+    inspector::Inspector,
     prelude::*,
 };
-use logos::Logos;
+use logos::{Lexer, Logos};
+use num_bigint::BigUint;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Constructor(pub(crate) String);
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Variable(pub(crate) String);
 
 impl From<&str> for Variable {
@@ -32,55 +39,928 @@ pub struct Branch<T>(
     pub(crate) T,
 );
 
+/// A byte range in the source code
+pub type Span = Range<usize>;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum CodedLiteral {
     Expr(MetaExpr),
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, Clone)]
 pub enum MetaExpr {
     Apply(Box<Self>, Box<Self>),
     Lambda(Variable, Box<Self>),
+    /// `\x1 ... xn. e`, sugar for nested lambdas that is removed by the `coder` module
+    Lambdas(Vec<Variable>, Box<Self>),
     Case(Box<Self>, Vec<Branch<MetaExpr>>),
     Rec(Variable, Box<Self>),
     Var(Variable),
     Const(Constructor, Vec<Self>),
+    /// A number literal, the constructor tree `Suc(...Suc(Zero())...)`
+    Nat(BigUint),
+    /// `let x = e1 in e2`, a local definition that is removed by the `coder` module.
+    /// Unlike a `let` in a `Program`, it binds `x` in `e2` just like a lambda does
+    Let(Variable, Box<Self>, Box<Self>),
     /// Not an actual part of the Chi language, it must be converted to a constructor tree
     /// see the `repr` module
     Coded(Box<CodedLiteral>),
+    /// The expression was parsed from `span` in the source.
+    /// Locations are ignored when comparing expressions
+    Located(Span, Box<Self>),
+    /// A part of the source that could not be parsed, only produced by `parse_recovering`
+    Error,
+}
+
+impl MetaExpr {
+    /// The expression without its outermost locations
+    pub fn unlocated(&self) -> &Self {
+        let mut expr = self;
+        while let MetaExpr::Located(_, e) = expr {
+            expr = e;
+        }
+        expr
+    }
+
+    /// The innermost source location of the outermost node
+    pub fn span(&self) -> Option<&Span> {
+        let mut span = None;
+        let mut expr = self;
+        while let MetaExpr::Located(s, e) = expr {
+            span = Some(s);
+            expr = e;
+        }
+        span
+    }
+
+    /// A lambda for each of the variables (none if there are no variables) around `body`
+    fn lambdas(mut xs: Vec<Variable>, body: Self) -> Self {
+        match xs.len() {
+            0 => body,
+            1 => MetaExpr::Lambda(xs.remove(0), Box::new(body)),
+            _ => MetaExpr::Lambdas(xs, Box::new(body)),
+        }
+    }
+}
+
+impl PartialEq for MetaExpr {
+    fn eq(&self, other: &Self) -> bool {
+        use MetaExpr::*;
+
+        match (self.unlocated(), other.unlocated()) {
+            (Apply(a1, b1), Apply(a2, b2)) => a1 == a2 && b1 == b2,
+            (Lambda(x1, e1), Lambda(x2, e2)) | (Rec(x1, e1), Rec(x2, e2)) => x1 == x2 && e1 == e2,
+            (Lambdas(xs1, e1), Lambdas(xs2, e2)) => xs1 == xs2 && e1 == e2,
+            (Let(x1, a1, b1), Let(x2, a2, b2)) => x1 == x2 && a1 == a2 && b1 == b2,
+            (Case(e1, branches1), Case(e2, branches2)) => e1 == e2 && branches1 == branches2,
+            (Var(x1), Var(x2)) => x1 == x2,
+            (Const(c1, es1), Const(c2, es2)) => c1 == c2 && es1 == es2,
+            (Nat(n1), Nat(n2)) => n1 == n2,
+            (Coded(l1), Coded(l2)) => l1 == l2,
+            (Error, Error) => true,
+            _ => false,
+        }
+    }
 }
 
 /// A layer on top of the Chi language that
 /// allows Chi expressions to be assigned to meta variables
 #[derive(Debug, PartialEq, Clone)]
 pub enum Program<T> {
-    Let(Variable, T, Box<Self>),
+    /// `let x = e; rest`, with the text of the doc comments (`--|`) right above the binding
+    Let(Option<String>, Variable, T, Box<Self>),
     Expr(T),
 }
 
-pub fn parse(
+impl<T> Program<T> {
+    /// The documented bindings, in order
+    pub fn docs(&self) -> Vec<(&Variable, &str)> {
+        let mut docs = Vec::new();
+        let mut program = self;
+        while let Program::Let(doc, x, _, rest) = program {
+            if let Some(doc) = doc {
+                docs.push((x, doc.as_str()));
+            }
+            program = rest;
+        }
+        docs
+    }
+}
+
+/// What is entered in a `Session`: any number of `let` bindings, optionally followed by an expression
+#[derive(Debug, PartialEq, Clone)]
+pub struct Entry {
+    pub definitions: Vec<(Variable, MetaExpr)>,
+    pub expr: Option<MetaExpr>,
+}
+
+type Extra<'a> = extra::Full<Rich<'a, Token<'a>>, ParserState, ()>;
+
+/// The data types that are known to the pattern compiler, its warnings so far,
+/// the uses of constructors, which are checked against the declared data types once the whole program is parsed,
+/// the files that can be imported and the path of the imported file that is parsed (`None` for the program)
+#[derive(Default)]
+struct ParserState {
+    constructors: Constructors,
+    warnings: Vec<Warning>,
+    uses: Vec<Use>,
+    modules: Modules,
+    file: Option<String>,
+}
+
+impl ParserState {
+    /// Add the errors of the uses of constructors to the result of the parser
+    fn check<'a, T>(
+        &self,
+        result: Result<T, Vec<Rich<'a, Token<'a>>>>,
+    ) -> Result<T, Vec<Rich<'a, Token<'a>>>> {
+        let errors = data::check(&self.constructors, &self.uses);
+        match result {
+            Ok(_) if errors.is_empty() => result,
+            Ok(_) => Err(errors),
+            Err(mut syntax_errors) => {
+                syntax_errors.extend(errors);
+                Err(syntax_errors)
+            }
+        }
+    }
+}
+
+// The warnings, uses and declarations of alternatives that are backtracked are removed
+impl<'a, I: Input<'a>> Inspector<'a, I> for ParserState {
+    type Checkpoint = (usize, usize, usize);
+
+    fn on_token(&mut self, _: &I::Token) {}
+
+    fn on_save<'parse>(&self, _: &Cursor<'a, 'parse, I>) -> Self::Checkpoint {
+        (
+            self.warnings.len(),
+            self.uses.len(),
+            self.constructors.declared(),
+        )
+    }
+
+    fn on_rewind<'parse>(&mut self, marker: &Checkpoint<'a, 'parse, I, Self::Checkpoint>) {
+        let (warnings, uses, declared) = *marker.inspector();
+        self.warnings.truncate(warnings);
+        self.uses.truncate(uses);
+        self.constructors.truncate(declared);
+    }
+}
+
+/// A parsed program, or the syntax errors
+pub type ParseResult<'a> = Result<Program<MetaExpr>, Vec<Rich<'a, Token<'a>>>>;
+
+pub fn parse(source: &str) -> ParseResult<'_> {
+    parse_with_warnings(source).0
+}
+
+/// Parse a program, with the warnings about the branches of case expressions with nested patterns
+pub fn parse_with_warnings(source: &str) -> (ParseResult<'_>, Vec<Warning>) {
+    let end_of_input: SimpleSpan = (source.len()..source.len()).into();
+    let token_stream = Stream::from_iter(tokens(source)).map(end_of_input, |(t, s)| (t, s));
+
+    let mut state = ParserState::default();
+    let result = program_parser()
+        .parse_with_state(token_stream, &mut state)
+        .into_result();
+    (state.check(result), state.warnings)
+}
+
+/// Parse a program that imports other files, which are read by `resolver`.
+/// The errors name the imported file they are in, the warnings are only about the program itself
+pub fn parse_with_imports(
     source: &str,
-) -> Result<Program<MetaExpr>, Vec<Rich<'_, Token<'_>, SimpleSpan, &str>>> {
-    let token_iter = Token::lexer(source)
-        .spanned()
-        // Convert lexer errors into a Token::Error
-        .map(|(token, span)| (token.unwrap_or(Token::Error), span.into()));
+    resolver: &dyn SourceResolver,
+) -> (Result<Program<MetaExpr>, Vec<Diagnostic>>, Vec<Warning>) {
+    match parse_with_imports_recovering(source, resolver) {
+        (Some(program), diagnostics, warnings) if diagnostics.is_empty() => (Ok(program), warnings),
+        (_, diagnostics, warnings) => (Err(diagnostics), warnings),
+    }
+}
+
+/// Like `parse_with_imports`, but parse as much of the program as possible like `parse_recovering` does.
+/// The program is `None` only if the errors could not be recovered from
+pub(crate) fn parse_with_imports_recovering(
+    source: &str,
+    resolver: &dyn SourceResolver,
+) -> (Option<Program<MetaExpr>>, Vec<Diagnostic>, Vec<Warning>) {
+    let mut loader = Loader::new(resolver);
+    loader.load_imports(None, source);
+    let (modules, mut diagnostics) = loader.finish();
 
     let end_of_input: SimpleSpan = (source.len()..source.len()).into();
-    let token_stream = Stream::from_iter(token_iter).spanned(end_of_input);
+    let token_stream = Stream::from_iter(tokens(source)).map(end_of_input, |(t, s)| (t, s));
+
+    let mut state = ParserState {
+        modules,
+        ..ParserState::default()
+    };
+    let (program, mut errors) = program_parser()
+        .parse_with_state(token_stream, &mut state)
+        .into_output_errors();
+    errors.extend(data::check(&state.constructors, &state.uses));
+    diagnostics.extend(errors.iter().map(|error| Diagnostic::new(None, error)));
+    (program, diagnostics, state.warnings)
+}
+
+/// Parse the imported file at `path`, the files it imports must be among the `modules`
+pub(crate) fn parse_module<'a>(
+    path: &str,
+    source: &'a str,
+    modules: &mut Modules,
+) -> Result<Module, Vec<Rich<'a, Token<'a>>>> {
+    let end_of_input: SimpleSpan = (source.len()..source.len()).into();
+    let token_stream = Stream::from_iter(tokens(source)).map(end_of_input, |(t, s)| (t, s));
+
+    let mut state = ParserState {
+        modules: mem::take(modules),
+        file: Some(path.to_string()),
+        ..ParserState::default()
+    };
+    let result = module_parser()
+        .parse_with_state(token_stream, &mut state)
+        .into_result();
+    *modules = mem::take(&mut state.modules);
+
+    let definitions = state.check(result)?;
+    Ok(Module {
+        definitions,
+        data_types: state.constructors.declarations().to_vec(),
+    })
+}
+
+/// Parse as much of the program as possible, even if there are syntax errors.
+/// The parts that could not be parsed are replaced by `MetaExpr::Error`, for instance the right-hand side of a `let`
+/// binding up to the next `;`, or the body of a case branch up to the next branch.
+/// The program is `None` only if the errors could not be recovered from.
+///
+/// Note: when a program that contains errors is evaluated, it gets stuck on the parts that could not be parsed
+/// (see `coder::SYNTAX_ERROR`).
+pub fn parse_recovering(source: &str) -> (Option<Program<MetaExpr>>, Vec<Rich<'_, Token<'_>>>) {
+    let end_of_input: SimpleSpan = (source.len()..source.len()).into();
+    let token_stream = Stream::from_iter(tokens(source)).map(end_of_input, |(t, s)| (t, s));
+
+    let mut state = ParserState::default();
+    let (program, mut errors) = program_parser()
+        .parse_with_state(token_stream, &mut state)
+        .into_output_errors();
+    errors.extend(data::check(&state.constructors, &state.uses));
+    (program, errors)
+}
+
+/// Parse the input of a `Session`, unlike a program it does not need to end with an expression
+pub fn parse_entry(source: &str) -> Result<Entry, Vec<Rich<'_, Token<'_>>>> {
+    let end_of_input: SimpleSpan = (source.len()..source.len()).into();
+    let token_stream = Stream::from_iter(tokens(source)).map(end_of_input, |(t, s)| (t, s));
+
+    entry_parser()
+        .parse_with_state(token_stream, &mut ParserState::default())
+        .into_result()
+}
+
+pub(crate) fn tokens(source: &str) -> Vec<(Token<'_>, SimpleSpan)> {
+    let mut lexer = Token::lexer(source);
+    let mut tokens: Vec<(Token, SimpleSpan)> = Vec::new();
+
+    // `import "path"` on one line is a single token, if it starts a statement of the program.
+    // The statements are separated by the `;` that are not inside brackets
+    let mut depth: usize = 0;
+    let mut statement = true;
+    while let Some(token) = lexer.next() {
+        // Convert lexer errors into a Token::Error
+        let mut token = token.unwrap_or(Token::Error);
+        if statement && token == Token::VarName("import") {
+            if let Some((path, length)) = import_path(&lexer) {
+                lexer.bump(length);
+                token = Token::Import(path);
+            }
+        }
+
+        statement = match token {
+            Token::Semicolon => depth == 0,
+            Token::DocComment(_) => statement,
+            _ => false,
+        };
+        match token {
+            Token::LParen | Token::LCurly | Token::LBracket => depth += 1,
+            Token::RParen | Token::RCurly | Token::RBracket => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        tokens.push((token, lexer.span().into()));
+    }
+
+    // `in` ends the right-hand side of a `let`, and `and` separates the definitions of a `let rec` group,
+    // which ends at the `;` or `in` of its `let` (at the same nesting of brackets). Both can only follow a complete
+    // expression, so they are still variable names as parameters, lambda binders and where an expression starts.
+    // `where` starts a where clause if it is followed by a `{`. Everywhere else they are variable names
+    struct OpenLet {
+        depth: usize,
+        rec: bool,
+        /// The `=` of the current definition has been seen, so the names before it are bound
+        defining: bool,
+    }
+    let mut lets: Vec<OpenLet> = Vec::new();
+    let mut depth: usize = 0;
+    // Between a `\` and its `.`
+    let mut binder = false;
+    // Inside a coded literal
+    let mut quoted = false;
+    for i in 0..tokens.len() {
+        let after_expression = !binder
+            && i > 0
+            && match tokens[i - 1].0 {
+                Token::VarName(_)
+                | Token::ConstName(_)
+                | Token::Number(_)
+                | Token::RParen
+                | Token::RCurly
+                | Token::RBracket => true,
+                Token::Quote => !quoted,
+                _ => false,
+            };
+        let current = lets.last_mut().filter(|open| open.depth == depth);
+        match (&tokens[i].0, current) {
+            (Token::Backslash, _) => binder = true,
+            (Token::Period, _) => binder = false,
+            (Token::Quote, _) => quoted = !quoted,
+            (Token::LParen | Token::LCurly | Token::LBracket, _) => depth += 1,
+            (Token::RParen | Token::RCurly | Token::RBracket, _) => {
+                depth = depth.saturating_sub(1);
+                while lets.last().is_some_and(|open| open.depth > depth) {
+                    lets.pop();
+                }
+            }
+            (Token::Let, _) => lets.push(OpenLet {
+                depth,
+                rec: matches!(tokens.get(i + 1), Some((Token::Rec, _))),
+                defining: false,
+            }),
+            (Token::Equals, Some(open)) => open.defining = true,
+            (Token::Semicolon, Some(_)) => {
+                lets.pop();
+            }
+            (Token::VarName("in"), Some(open)) if open.defining && after_expression => {
+                tokens[i].0 = Token::In;
+                lets.pop();
+            }
+            (Token::VarName("and"), Some(open))
+                if open.rec && open.defining && after_expression =>
+            {
+                tokens[i].0 = Token::And;
+                open.defining = false;
+            }
+            (Token::VarName("where"), _)
+                if matches!(tokens.get(i + 1), Some((Token::LCurly, _))) =>
+            {
+                tokens[i].0 = Token::Where;
+            }
+            _ => {}
+        }
+    }
+
+    // Doc comments that do not document a `let` binding are just comments
+    let mut documents_let = false;
+    for i in (0..tokens.len()).rev() {
+        match tokens[i].0 {
+            Token::DocComment(_) if !documents_let => {
+                tokens.remove(i);
+            }
+            Token::DocComment(_) => {}
+            Token::Let => documents_let = true,
+            _ => documents_let = false,
+        }
+    }
+
+    tokens
+}
+
+/// The path of the import statement that starts with the `import` that was just lexed, and the length of the source
+/// up to the end of the path. The path is quoted on the same line, and the statement ends with a `;` (outside of brackets).
+/// Otherwise `import` is a variable in the final expression of the program, followed by a coded literal
+fn import_path<'a>(lexer: &Lexer<'a, Token<'a>>) -> Option<(&'a str, usize)> {
+    let rest = lexer.remainder();
+    let quoted = rest.trim_start_matches([' ', '\t']).strip_prefix('"')?;
+    let length = quoted
+        .find(['"', '\n'])
+        .filter(|end| quoted[*end..].starts_with('"'))?;
+    let end = rest.len() - quoted.len() + length + 1;
+
+    let mut after = lexer.clone();
+    after.bump(end);
+    let mut depth: usize = 0;
+    for token in after {
+        match token {
+            Ok(Token::LParen | Token::LCurly | Token::LBracket) => depth += 1,
+            Ok(Token::RParen | Token::RCurly | Token::RBracket) => depth = depth.saturating_sub(1),
+            Ok(Token::Semicolon) if depth == 0 => return Some((&quoted[..length], end)),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// A `let` binding, or a group of mutually recursive bindings, with their doc comments
+fn let_parser<'a, I>(
+) -> impl Parser<'a, I, Vec<(Option<String>, Variable, MetaExpr)>, Extra<'a>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = SimpleSpan>,
+{
+    let var_name = select! { Token::VarName(name) => Variable(name.to_string())};
+
+    let doc = doc_comment()
+        .repeated()
+        .collect::<Vec<_>>()
+        .map(|lines| (!lines.is_empty()).then(|| lines.join("\n")));
+
+    // Skip to the next `;`, or to the next binding if the `;` is missing.
+    // There is nothing to recover if the skipping stops at an `in`, since it is a local definition
+    let rhs = expr_parser()
+        .then_ignore(just(Token::Semicolon))
+        .recover_with(via_parser(
+            skippable()
+                .repeated()
+                .then_ignore(just(Token::Semicolon))
+                .or(skippable()
+                    .repeated()
+                    .at_least(1)
+                    .then_ignore(just(Token::In).not()))
+                .to(MetaExpr::Error),
+        ));
+
+    let function = var_name
+        .then(var_name.repeated().collect::<Vec<_>>())
+        .then_ignore(just(Token::Equals))
+        .then(expr_parser())
+        .map_with(|((name, params), e), extra| (name, MetaExpr::lambdas(params, e), extra.span()));
+
+    // `let rec f x = e1 and g y = e2;`, the documentation is shared by the whole group
+    let group = just(Token::Rec)
+        .ignore_then(
+            function
+                .separated_by(just(Token::And))
+                .at_least(2)
+                .collect::<Vec<_>>(),
+        )
+        .then_ignore(just(Token::Semicolon))
+        .validate(|functions, _, emitter| {
+            let names: Vec<Variable> = functions.iter().map(|(name, ..)| name.clone()).collect();
+            recursive_group(functions).unwrap_or_else(|error| {
+                emitter.emit(error);
+                names.into_iter().map(|x| (x, MetaExpr::Error)).collect()
+            })
+        });
+
+    doc.then_ignore(just(Token::Let))
+        .then(group.or(binding_parser(rhs).map(|binding| vec![binding])))
+        .map(|(doc, bindings)| {
+            bindings
+                .into_iter()
+                .map(|(name, e)| (doc.clone(), name, e))
+                .collect()
+        })
+}
+
+/// The definitions of a group of mutually recursive functions `f1 = e1 and ... and fn = en`:
+/// `fi = case (rec g = Group(e1', ..., en')) of { Group(f1, ..., fn) -> fi }`,
+/// where `ei'` is `\x. (case g of { Group(f1, ..., fn) -> ei }) x` for the parameter `x` of the outermost lambda of `ei`.
+/// Every definition must be a lambda, so `g` is only unfolded when one of the functions is applied,
+/// and the parameters of `ei` are bound inside the `case` so they shadow the functions of the group
+fn recursive_group<'a>(
+    functions: Vec<(Variable, MetaExpr, SimpleSpan)>,
+) -> Result<Vec<(Variable, MetaExpr)>, Rich<'a, Token<'a>>> {
+    let names: Vec<Variable> = functions.iter().map(|(name, ..)| name.clone()).collect();
+    // Not a valid variable name in the source, so it can not be captured
+    let group = Variable(
+        names
+            .iter()
+            .map(|x| x.0.as_str())
+            .collect::<Vec<_>>()
+            .join("&"),
+    );
+    let constructor = Constructor::from("Group");
+
+    let mut es = Vec::new();
+    for (i, (name, e, span)) in functions.iter().enumerate() {
+        if names[..i].contains(name) {
+            return Err(Rich::custom(*span, format!("{name} is defined twice")));
+        }
+        let Some(x) = parameter(e) else {
+            return Err(Rich::custom(
+                *span,
+                format!(
+                    "{name} must be a function to be defined in a group of recursive definitions"
+                ),
+            ));
+        };
+        let case = MetaExpr::Case(
+            Box::new(MetaExpr::Var(group.clone())),
+            vec![Branch(constructor.clone(), names.clone(), e.clone())],
+        );
+        es.push(MetaExpr::Lambda(
+            x.clone(),
+            Box::new(MetaExpr::Apply(
+                Box::new(case),
+                Box::new(MetaExpr::Var(x.clone())),
+            )),
+        ));
+    }
+
+    let rec = MetaExpr::Rec(
+        group.clone(),
+        Box::new(MetaExpr::Const(constructor.clone(), es)),
+    );
+    Ok(functions
+        .into_iter()
+        .map(|(name, _, span)| {
+            let projection = MetaExpr::Case(
+                Box::new(rec.clone()),
+                vec![Branch(
+                    constructor.clone(),
+                    names.clone(),
+                    MetaExpr::Var(name.clone()),
+                )],
+            );
+            (
+                name,
+                MetaExpr::Located(span.into_range(), Box::new(projection)),
+            )
+        })
+        .collect())
+}
+
+/// The parameter of the outermost lambda, `None` if `e` is not a lambda
+fn parameter(e: &MetaExpr) -> Option<&Variable> {
+    match e {
+        MetaExpr::Located(_, e) => parameter(e),
+        MetaExpr::Lambda(x, _) => Some(x),
+        MetaExpr::Lambdas(xs, _) => xs.first(),
+        _ => None,
+    }
+}
 
-    program_parser().parse(token_stream).into_result()
+fn doc_comment<'a, I>() -> impl Parser<'a, I, &'a str, Extra<'a>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = SimpleSpan>,
+{
+    select! { Token::DocComment(text) => text }
 }
 
-fn program_parser<'a, I>() -> impl Parser<'a, I, Program<MetaExpr>, extra::Err<Rich<'a, Token<'a>>>>
+/// `import "nat.chi";`, with `as Nat` to name the definitions `Nat.add` and so on,
+/// and `(add, mul)` to only import some of the definitions. The definitions become `let` bindings
+fn import_parser<'a, I>(
+) -> impl Parser<'a, I, Vec<(Option<String>, Variable, MetaExpr)>, Extra<'a>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = SimpleSpan>,
+{
+    let var_name = select! { Token::VarName(name) => Variable(name.to_string())};
+    let alias = just(Token::VarName("as")).ignore_then(select! { Token::ConstName(name) => name });
+    let names = var_name
+        .map_with(|x, extra| (x, extra.span()))
+        .separated_by(just(Token::Comma))
+        .allow_trailing()
+        .collect::<Vec<_>>()
+        .delimited_by(just(Token::LParen), just(Token::RParen));
+
+    select! { Token::Import(path) => path }
+        .then(alias.or_not())
+        .then(names.or_not())
+        .then_ignore(just(Token::Semicolon))
+        .validate(|((path, alias), names), extra, emitter| {
+            let span = extra.span();
+            let state: &mut ParserState = extra.state();
+            let path = imported_path(state.file.as_deref(), path);
+            let path = path.as_str();
+            let module = match state.modules.get(path) {
+                Some(Some(module)) => module,
+                // The errors of the file are already reported
+                Some(None) => return Vec::new(),
+                None => {
+                    emitter.emit(Rich::custom(
+                        span,
+                        format!("{path} is not loaded, imports are only supported by parse_with_imports"),
+                    ));
+                    return Vec::new();
+                }
+            };
+
+            let names = names.map(|names| {
+                names
+                    .into_iter()
+                    .filter(|(x, span)| {
+                        let defined = module.defines(x);
+                        if !defined {
+                            emitter.emit(Rich::custom(*span, format!("{path} does not define {x}")));
+                        }
+                        defined
+                    })
+                    .map(|(x, _)| x)
+                    .collect::<Vec<_>>()
+            });
+            let bindings = module.import(path, alias, names.as_deref());
+
+            for data_type in module.data_types.clone() {
+                for error in state.constructors.import(&data_type, span) {
+                    emitter.emit(error);
+                }
+            }
+            bindings
+        })
+}
+
+/// `data Nat = Zero() | Suc(Nat);`, which declares a data type for the checks of the `data` module.
+/// Only the number of fields of a constructor matters, so a field type is any data type or variable name.
+/// `data` is not a keyword, so it can still be used as a variable name
+fn data_parser<'a, I>() -> impl Parser<'a, I, (), Extra<'a>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = SimpleSpan>,
+{
+    let type_name = select! { Token::ConstName(name) => name.to_string() };
+    let field = select! { Token::ConstName(_) => (), Token::VarName(_) => () };
+
+    let constructor = select! { Token::ConstName(name) => Constructor(name.to_string()) }.then(
+        field
+            .separated_by(just(Token::Comma))
+            .allow_trailing()
+            .count()
+            .delimited_by(just(Token::LParen), just(Token::RParen)),
+    );
+
+    // Skip to the next `;` if the constructors can not be parsed
+    let constructors = constructor
+        .separated_by(just(Token::Bar))
+        .at_least(1)
+        .collect::<Vec<_>>()
+        .then_ignore(just(Token::Semicolon))
+        .map(Some)
+        .recover_with(via_parser(
+            skippable()
+                .repeated()
+                .then_ignore(just(Token::Semicolon))
+                .to(None),
+        ));
+
+    just(Token::VarName("data"))
+        .ignore_then(type_name)
+        .then_ignore(just(Token::Equals))
+        .then(constructors)
+        .validate(|(name, constructors), extra, emitter| {
+            let Some(constructors) = constructors else {
+                return;
+            };
+            let span = extra.span();
+            let data_type = DataType { name, constructors };
+            for error in extra.state().constructors.declare(data_type, span) {
+                emitter.emit(error);
+            }
+        })
+}
+
+/// The part of a `let` after the keyword, `x = e` or a function definition like `rec f x y = e`,
+/// which is `f = rec f = \x y. e`. The right-hand side `e` is parsed by `rhs`
+fn binding_parser<'a, I, P>(rhs: P) -> impl Parser<'a, I, (Variable, MetaExpr), Extra<'a>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = SimpleSpan>,
+    P: Parser<'a, I, MetaExpr, Extra<'a>> + Clone,
+{
+    let var_name = select! { Token::VarName(name) => Variable(name.to_string())};
+
+    just(Token::Rec)
+        .or_not()
+        .map(|rec| rec.is_some())
+        .then(var_name)
+        .then(var_name.repeated().collect::<Vec<_>>())
+        .then_ignore(just(Token::Equals))
+        .then(rhs)
+        .map(|(((rec, name), params), e)| {
+            if !rec && params.is_empty() {
+                return (name, e);
+            }
+            let span = e.span().cloned();
+            let mut e = MetaExpr::lambdas(params, e);
+            if rec {
+                e = MetaExpr::Rec(name.clone(), Box::new(e));
+            }
+            if let Some(span) = span {
+                e = MetaExpr::Located(span, Box::new(e));
+            }
+            (name, e)
+        })
+}
+
+/// The case expression at `span`, the patterns are compiled if any of them is nested
+fn case<'a>(
+    e: MetaExpr,
+    arms: Vec<Arm>,
+    span: SimpleSpan,
+    state: &mut ParserState,
+) -> Result<MetaExpr, Rich<'a, Token<'a>>> {
+    for arm in &arms {
+        arm.pattern.uses(arm.span, &mut state.uses);
+    }
+
+    let simple: Option<Vec<_>> = arms
+        .iter()
+        .map(|arm| match &arm.pattern {
+            Pattern::Const(c, _) => {
+                Some(Branch(c.clone(), arm.pattern.simple()?, arm.body.clone()))
+            }
+            Pattern::Var(_) => None,
+        })
+        .collect();
+
+    match simple {
+        Some(branches) => {
+            state.uses.push(Use::Case {
+                branches: branches
+                    .iter()
+                    .zip(&arms)
+                    .map(|(Branch(c, ..), arm)| (c.clone(), arm.span))
+                    .collect(),
+                span,
+                nested: false,
+            });
+            Ok(MetaExpr::Case(Box::new(e), branches))
+        }
+        None => pattern::compile(
+            e,
+            arms,
+            span,
+            &state.constructors,
+            &mut state.warnings,
+            &mut state.uses,
+        ),
+    }
+}
+
+/// A token, or a group of tokens in matching parentheses or braces, that is skipped when recovering from an error.
+/// Skipping stops at a `;`, a `let`, an `in`, or a closing parenthesis or brace that is not matched by the skipped tokens
+fn skippable<'a, I>() -> impl Parser<'a, I, (), Extra<'a>> + Clone
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = SimpleSpan>,
+{
+    let group = recursive(|group| {
+        let inner = group
+            .or(none_of([Token::RParen, Token::RCurly, Token::RBracket]).ignored())
+            .repeated();
+        choice((
+            inner
+                .clone()
+                .delimited_by(just(Token::LParen), just(Token::RParen)),
+            inner
+                .clone()
+                .delimited_by(just(Token::LCurly), just(Token::RCurly)),
+            inner.delimited_by(just(Token::LBracket), just(Token::RBracket)),
+        ))
+    });
+
+    group.or(none_of([
+        Token::Semicolon,
+        Token::Let,
+        Token::In,
+        Token::RParen,
+        Token::RCurly,
+        Token::RBracket,
+    ])
+    .ignored())
+}
+
+fn program_parser<'a, I>() -> impl Parser<'a, I, Program<MetaExpr>, Extra<'a>>
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = SimpleSpan>,
+{
+    let program = recursive(|program| {
+        let let_ =
+            let_parser()
+                .or(import_parser())
+                .then(program.clone())
+                .map(|(bindings, rest)| {
+                    bindings
+                        .into_iter()
+                        .rev()
+                        .fold(rest, |rest, (doc, name, e)| {
+                            Program::Let(doc, name, e, Box::new(rest))
+                        })
+                });
+
+        let expr = expr_parser()
+            .then_ignore(end())
+            .recover_with(via_parser(any().repeated().at_least(1).to(MetaExpr::Error)));
+
+        let data = data_parser().ignore_then(program);
+
+        let_.or(data).or(expr.map(Program::Expr))
+    });
+
+    program.then_ignore(end()).map(pattern::complete_defaults)
+}
+
+/// An imported file: bindings, data declarations and imports, maybe followed by an expression that is ignored
+fn module_parser<'a, I>() -> impl Parser<'a, I, Vec<(Option<String>, Variable, MetaExpr)>, Extra<'a>>
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = SimpleSpan>,
+{
+    choice((
+        let_parser(),
+        import_parser(),
+        data_parser().map(|_| Vec::new()),
+    ))
+    .repeated()
+    .collect::<Vec<_>>()
+    .then_ignore(expr_parser().or_not())
+    .then_ignore(end())
+    .map(|items| items.into_iter().flatten().collect())
+}
+
+fn entry_parser<'a, I>() -> impl Parser<'a, I, Entry, Extra<'a>>
+where
+    I: ValueInput<'a, Token = Token<'a>, Span = SimpleSpan>,
+{
+    let_parser()
+        .repeated()
+        .collect::<Vec<_>>()
+        .then(expr_parser().or_not())
+        .then_ignore(end())
+        .validate(|(definitions, expr), extra, emitter| {
+            let definitions: Vec<_> = definitions
+                .into_iter()
+                .flatten()
+                .map(|(_, x, e)| (x, e))
+                .collect();
+            for span in pattern::entry_defaults(&definitions, expr.as_ref()) {
+                emitter.emit(Rich::custom(
+                    span.map_or(extra.span(), Into::into),
+                    "the data type of this catch-all pattern is not known, and a later input can use other \
+                    constructors: write a branch for each constructor instead",
+                ));
+            }
+            Entry { definitions, expr }
+        })
+}
+
+/// The largest number in a pattern, since it is expanded to nested constructor patterns
+const MAX_NUMBER_PATTERN: usize = 100;
+
+/// The largest number in a coded literal, since it is coded as a constructor tree
+const MAX_CODED_NUMBER: usize = 10_000;
+
+/// The locations of the numbers in `e` that are larger than `max`
+fn large_numbers(e: &MetaExpr, max: usize) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut todo = vec![(e, None)];
+    while let Some((e, span)) = todo.pop() {
+        match e {
+            MetaExpr::Located(span, e) => todo.push((e, Some(span))),
+            MetaExpr::Nat(n) if *n > BigUint::from(max) => spans.extend(span.cloned()),
+            MetaExpr::Apply(e1, e2) | MetaExpr::Let(_, e1, e2) => {
+                todo.extend([(&**e1, span), (&**e2, span)]);
+            }
+            MetaExpr::Lambda(_, e) | MetaExpr::Lambdas(_, e) | MetaExpr::Rec(_, e) => {
+                todo.push((e, span));
+            }
+            MetaExpr::Case(e, branches) => {
+                todo.push((e, span));
+                todo.extend(branches.iter().map(|Branch(_, _, e)| (e, span)));
+            }
+            MetaExpr::Const(_, es) => todo.extend(es.iter().map(|e| (e, span))),
+            MetaExpr::Coded(literal) => {
+                let CodedLiteral::Expr(e) = &**literal;
+                todo.push((e, span));
+            }
+            MetaExpr::Var(_) | MetaExpr::Nat(_) | MetaExpr::Error => {}
+        }
+    }
+    spans.sort_by_key(|span| span.start);
+    spans
+}
+
+fn expr_parser<'a, I>() -> impl Parser<'a, I, MetaExpr, Extra<'a>> + Clone
 where
     I: ValueInput<'a, Token = Token<'a>, Span = SimpleSpan>,
 {
     let constructor_name = select! { Token::ConstName(name) => Constructor(name.to_string()) };
     let var_name = select! { Token::VarName(name) => Variable(name.to_string())};
+    let number = select! { Token::Number(n) => n.parse::<BigUint>().unwrap() };
+
+    let located = |e, span: SimpleSpan| MetaExpr::Located(span.into_range(), Box::new(e));
 
-    let expr = recursive(|expr| {
-        let var = var_name.map(|var| MetaExpr::Var(var));
+    recursive(|expr| {
+        // `Nat.add` is a definition of a file that is imported with `as Nat`, written without spaces
+        let qualified = select! { Token::ConstName(name) => name }
+            .then_ignore(just(Token::Period))
+            .then(var_name)
+            .validate(|(alias, x), extra, emitter| {
+                let name = format!("{alias}.{x}");
+                let span: SimpleSpan = extra.span();
+                if span.end - span.start != name.len() {
+                    emitter.emit(Rich::custom(
+                        span,
+                        format!("the qualified name {name} can not contain spaces"),
+                    ));
+                }
+                Variable(name)
+            });
+        let var = qualified.or(var_name).map(MetaExpr::Var);
 
         let args = expr
             .clone()
@@ -90,20 +970,89 @@ where
 
         let constructor = constructor_name
             .then(args.delimited_by(just(Token::LParen), just(Token::RParen)))
-            .map(|(name, args)| MetaExpr::Const(name, args));
+            .map_with(|(name, args), extra| {
+                let span = extra.span();
+                let use_ = Use::Const(name.clone(), args.len(), span);
+                let state: &mut ParserState = extra.state();
+                state.uses.push(use_);
+                MetaExpr::Const(name, args)
+            });
 
-        let vars = var_name
+        // `[e1, ..., en]` is `Cons(e1, ... Cons(en, Nil()) ...)`
+        let list = expr
             .clone()
             .separated_by(just(Token::Comma))
             .allow_trailing()
-            .collect::<Vec<_>>();
+            .collect::<Vec<_>>()
+            .delimited_by(just(Token::LBracket), just(Token::RBracket))
+            .map(|es: Vec<MetaExpr>| {
+                es.into_iter()
+                    .rev()
+                    .fold(MetaExpr::Const("Nil".into(), vec![]), |tail, e| {
+                        MetaExpr::Const("Cons".into(), vec![e, tail])
+                    })
+            });
+
+        // Patterns can be nested, and number and list literals can be used in patterns
+        let pattern = recursive(|pattern| {
+            let patterns = pattern
+                .separated_by(just(Token::Comma))
+                .allow_trailing()
+                .collect::<Vec<_>>();
+
+            let number = number.try_map(|n, span| {
+                usize::try_from(n)
+                    .ok()
+                    .filter(|n| *n <= MAX_NUMBER_PATTERN)
+                    .map(Pattern::nat)
+                    .ok_or_else(|| {
+                        Rich::custom(
+                            span,
+                            format!("the number is too large for a pattern, the largest is {MAX_NUMBER_PATTERN}"),
+                        )
+                    })
+            });
+
+            let list = patterns
+                .clone()
+                .delimited_by(just(Token::LBracket), just(Token::RBracket))
+                .map(|ps: Vec<Pattern>| {
+                    ps.into_iter()
+                        .rev()
+                        .fold(Pattern::Const("Nil".into(), vec![]), |tail, p| {
+                            Pattern::Const("Cons".into(), vec![p, tail])
+                        })
+                });
 
-        let branch = constructor_name
-            .then(vars.delimited_by(just(Token::LParen), just(Token::RParen)))
+            choice((
+                var_name.map(Pattern::Var),
+                constructor_name
+                    .then(patterns.delimited_by(just(Token::LParen), just(Token::RParen)))
+                    .map(|(c, ps)| Pattern::Const(c, ps)),
+                number,
+                list,
+            ))
+        });
+
+        // Skip to the next branch if the body is not followed by one
+        let body = expr
+            .clone()
+            .then_ignore(just(Token::Semicolon).or(just(Token::RCurly)).rewind())
+            .recover_with(via_parser(
+                skippable().repeated().at_least(1).to(MetaExpr::Error),
+            ));
+
+        let branch = pattern
+            .map_with(|pattern, extra| (pattern, extra.span()))
             .then_ignore(just(Token::Arrow))
-            .then(expr.clone())
-            .map(|((constructor, vars), e)| Branch(constructor, vars, e));
+            .then(body)
+            .map(|((pattern, span), body)| Arm {
+                pattern,
+                span,
+                body,
+            });
 
+        // The whole case expression is an error if the branches can not be recovered
         let case = just(Token::Case)
             .ignore_then(expr.clone())
             .then_ignore(just(Token::Of))
@@ -111,16 +1060,32 @@ where
                 branch
                     .separated_by(just(Token::Semicolon))
                     .allow_trailing()
-                    .collect::<Vec<Branch<MetaExpr>>>()
-                    .delimited_by(just(Token::LCurly), just(Token::RCurly)),
+                    .collect::<Vec<Arm>>()
+                    .delimited_by(just(Token::LCurly), just(Token::RCurly))
+                    .map(Some)
+                    .recover_with(via_parser(nested_delimiters(
+                        Token::LCurly,
+                        Token::RCurly,
+                        [(Token::LParen, Token::RParen)],
+                        |_| None,
+                    ))),
             )
-            .map(|(e, branches)| MetaExpr::Case(Box::new(e), branches));
+            .validate(|(e, arms), extra, emitter| {
+                let Some(arms) = arms else {
+                    return MetaExpr::Error;
+                };
+                let span = extra.span();
+                case(e, arms, span, extra.state()).unwrap_or_else(|error| {
+                    emitter.emit(error);
+                    MetaExpr::Error
+                })
+            });
 
         let lambda = just(Token::Backslash)
-            .ignore_then(var_name)
+            .ignore_then(var_name.repeated().at_least(1).collect::<Vec<_>>())
             .then_ignore(just(Token::Period))
             .then(expr.clone())
-            .map(|(var, e)| MetaExpr::Lambda(var, Box::new(e)));
+            .map(|(vars, e)| MetaExpr::lambdas(vars, e));
 
         let rec = just(Token::Rec)
             .ignore_then(var_name)
@@ -128,43 +1093,79 @@ where
             .then(expr.clone())
             .map(|(var, e)| MetaExpr::Rec(var, Box::new(e)));
 
+        // Doc comments are allowed, but not kept, for local definitions
+        let let_in = doc_comment()
+            .repeated()
+            .ignore_then(just(Token::Let))
+            .ignore_then(binding_parser(expr.clone()))
+            .then_ignore(just(Token::In))
+            .then(expr.clone())
+            .map(|((x, e1), e2)| MetaExpr::Let(x, Box::new(e1), Box::new(e2)));
+
         // Note: the grammar makes sure that there is no way to have nested repr(...)
         // for instance, ""bar"" is not repr(repr(bar)), it is just a syntax error
         let coded_literal = expr
             .clone()
             .delimited_by(just(Token::Quote), just(Token::Quote))
-            .map(|e| MetaExpr::Coded(Box::new(CodedLiteral::Expr(e))));
+            .validate(|e, _, emitter| {
+                for span in large_numbers(&e, MAX_CODED_NUMBER) {
+                    emitter.emit(Rich::custom(
+                        span.into(),
+                        format!("the number is too large to be coded, the largest is {MAX_CODED_NUMBER}"),
+                    ));
+                }
+                MetaExpr::Coded(Box::new(CodedLiteral::Expr(e)))
+            });
 
         let atom = var
             .or(constructor)
+            .or(number.map(MetaExpr::Nat))
+            .or(list)
             .or(case)
             .or(lambda)
             .or(rec)
+            .or(let_in)
             .or(coded_literal)
+            .map_with(move |e, extra| located(e, extra.span()))
             .or(expr
                 .clone()
-                .delimited_by(just(Token::LParen), just(Token::RParen)));
-
-        let apply = atom.clone().foldl(atom.clone().repeated(), |a, b| {
-            MetaExpr::Apply(Box::new(a), Box::new(b))
-        });
-
-        apply
-    });
+                .delimited_by(just(Token::LParen), just(Token::RParen))
+                .recover_with(via_parser(nested_delimiters(
+                    Token::LParen,
+                    Token::RParen,
+                    [(Token::LCurly, Token::RCurly)],
+                    |_| MetaExpr::Error,
+                ))));
 
-    let program = recursive(|program| {
-        let let_ = just(Token::Let)
-            .ignore_then(var_name)
-            .then_ignore(just(Token::Equals))
-            .then(expr.clone())
-            .then_ignore(just(Token::Semicolon))
-            .then(program)
-            .map(|((name, e), rest)| Program::Let(name, e, Box::new(rest)));
+        let application = atom
+            .clone()
+            .foldl_with(atom.clone().repeated(), move |a, b, extra| {
+                located(MetaExpr::Apply(Box::new(a), Box::new(b)), extra.span())
+            });
 
-        let_.or(expr.map(|e| Program::Expr(e)))
-    });
+        // `e where { x = e1; y = e2 }` is `let x = e1 in let y = e2 in e`,
+        // so a definition can use the ones before it
+        let where_clause = just(Token::Where).ignore_then(
+            binding_parser(expr.clone())
+                .separated_by(just(Token::Semicolon))
+                .allow_trailing()
+                .at_least(1)
+                .collect::<Vec<_>>()
+                .delimited_by(just(Token::LCurly), just(Token::RCurly)),
+        );
 
-    program.then_ignore(end())
+        application
+            .then(where_clause.or_not())
+            .map_with(move |(e, bindings), extra| match bindings {
+                None => e,
+                Some(bindings) => {
+                    let e = bindings.into_iter().rev().fold(e, |e, (x, rhs)| {
+                        MetaExpr::Let(x, Box::new(rhs), Box::new(e))
+                    });
+                    located(e, extra.span())
+                }
+            })
+    })
 }
 
 impl fmt::Display for Variable {
@@ -1,4 +1,8 @@
-use crate::{parse, parser::Variable, MetaExpr, Program};
+use crate::{
+    eval, parse, parse_entry, parse_recovering,
+    parser::{Branch, Variable},
+    replace_coded_literals, run, EvalLimits, MetaExpr, Printer, Program, StandardCoder,
+};
 
 #[test]
 fn variable() {
@@ -74,3 +78,438 @@ fn case_trailing() {
 fn application() {
     parse(r"(\x. x) Foo()").unwrap();
 }
+
+#[test]
+fn spans() {
+    let source = r"let id = \x. x; id (C() y)";
+    let Ok(Program::Let(_, _, id, rest)) = parse(source) else {
+        panic!("expected a let binding");
+    };
+    assert_eq!(&source[id.span().unwrap().clone()], r"\x. x");
+
+    let Program::Expr(MetaExpr::Located(span, e)) = *rest else {
+        panic!("expected a located expression");
+    };
+    assert_eq!(&source[span], "id (C() y)");
+    let MetaExpr::Apply(_, arg) = *e else {
+        panic!("expected an application");
+    };
+    assert_eq!(&source[arg.span().unwrap().clone()], "C() y");
+}
+
+#[test]
+fn entries() {
+    let entry = parse_entry(r"let x = A(); let y = x;").unwrap();
+    assert_eq!(entry.definitions.len(), 2);
+    assert_eq!(entry.expr, None);
+
+    let entry = parse_entry(r"let x = A(); x").unwrap();
+    assert_eq!(entry.definitions.len(), 1);
+    assert_eq!(entry.expr, Some(MetaExpr::Var(Variable::from("x"))));
+
+    assert!(parse_entry("").unwrap().definitions.is_empty());
+    assert!(parse_entry(r"let x = A()").is_err());
+}
+
+#[test]
+fn block_comments() {
+    parse("{- a comment -} x {- another\n comment -}").unwrap();
+    parse("{- outer {- inner -} still a comment -} x").unwrap();
+    parse("let x = {- a comment in an expression -} A(); x").unwrap();
+}
+
+#[test]
+fn unterminated_block_comment() {
+    let source = "x {- outer {- inner -} ";
+    let errors = parse(source).unwrap_err();
+    assert_eq!(errors[0].span().start, 2);
+    assert_eq!(errors[0].span().end, source.len());
+}
+
+#[test]
+fn doc_comments() {
+    let source = r"
+        --| The identity function
+        --| (on anything)
+        let id = \x. x;
+        -- not documented
+        let y = A();
+        --| neither, there is no let after this
+        id y
+    ";
+    let program = parse(source).unwrap();
+    assert_eq!(
+        program.docs(),
+        vec![(
+            &Variable::from("id"),
+            "The identity function\n(on anything)"
+        )]
+    );
+
+    parse("--| a stray doc comment\nx").unwrap();
+}
+
+/// The bindings of a program, and its final expression
+fn bindings(program: Program<MetaExpr>) -> (Vec<(Variable, MetaExpr)>, MetaExpr) {
+    let mut bindings = Vec::new();
+    let mut program = program;
+    loop {
+        match program {
+            Program::Let(_, x, e, rest) => {
+                bindings.push((x, e));
+                program = *rest;
+            }
+            Program::Expr(e) => return (bindings, e),
+        }
+    }
+}
+
+#[test]
+fn recover_let_bindings() {
+    let (program, errors) = parse_recovering(r"let x = A(; let y = B(); let z = \. y; y");
+    assert_eq!(errors.len(), 2);
+
+    let (bindings, expr) = bindings(program.unwrap());
+    assert_eq!(
+        bindings,
+        vec![
+            (Variable::from("x"), MetaExpr::Error),
+            (Variable::from("y"), MetaExpr::Const("B".into(), vec![])),
+            (Variable::from("z"), MetaExpr::Error),
+        ]
+    );
+    assert_eq!(expr, MetaExpr::Var(Variable::from("y")));
+}
+
+#[test]
+fn recover_missing_semicolon() {
+    let (program, errors) = parse_recovering(r"let x = A() let y = x; y");
+    assert_eq!(errors.len(), 1);
+
+    let (bindings, _) = bindings(program.unwrap());
+    assert_eq!(bindings[0], (Variable::from("x"), MetaExpr::Error));
+    assert_eq!(
+        bindings[1],
+        (Variable::from("y"), MetaExpr::Var("x".into()))
+    );
+}
+
+#[test]
+fn recover_case_branches() {
+    let (program, errors) = parse_recovering(
+        r"
+        case x of {
+            A() -> \. x;
+            C() -> D() E(;
+            E() -> F()
+        }
+    ",
+    );
+    assert_eq!(errors.len(), 2);
+
+    let Some(Program::Expr(e)) = program else {
+        panic!("expected an expression");
+    };
+    let MetaExpr::Case(_, branches) = e.unlocated() else {
+        panic!("expected a case expression");
+    };
+    assert_eq!(
+        branches,
+        &vec![
+            Branch("A".into(), vec![], MetaExpr::Error),
+            Branch("C".into(), vec![], MetaExpr::Error),
+            Branch("E".into(), vec![], MetaExpr::Const("F".into(), vec![])),
+        ]
+    );
+}
+
+#[test]
+fn recover_final_expression() {
+    let (program, errors) = parse_recovering(r"let x = A(); x )");
+    assert_eq!(errors.len(), 1);
+
+    let (_, expr) = bindings(program.unwrap());
+    assert_eq!(expr, MetaExpr::Error);
+}
+
+#[test]
+fn recovered_errors_are_reported_by_parse() {
+    assert_eq!(parse(r"let x = A(; let y = B(; y").unwrap_err().len(), 2);
+    assert_eq!(parse_recovering(r"let x = A(); x").1, vec![]);
+}
+
+#[test]
+fn recovered_programs_are_evaluated() {
+    let source = r"let x = A(; let y = {\z. D(}; let w = B(); Pair(w, y)";
+    let (program, errors) = parse_recovering(source);
+    assert_eq!(errors.len(), 2);
+    let program = replace_coded_literals(program.unwrap(), &mut StandardCoder::default());
+    let Err(error) = eval(program, &EvalLimits::default()) else {
+        panic!("expected the evaluation to get stuck");
+    };
+    assert_eq!(
+        error.to_string(),
+        "This part of the program could not be parsed, so it can not be evaluated"
+    );
+
+    let output = run(
+        r"let x = A(; let y = B(); y",
+        Printer::Concrete,
+        &EvalLimits::default(),
+    )
+    .err()
+    .unwrap();
+    assert!(output.contains("Without the parts that could not be parsed, the program gives:\nB()"));
+    let output = run(r"let x = A(; x", Printer::Notebook, &EvalLimits::default())
+        .err()
+        .unwrap();
+    assert!(output.contains("x = ? (not evaluated, a part of it could not be parsed)"));
+}
+
+#[test]
+fn literals() {
+    let suc = |e| MetaExpr::Const("Suc".into(), vec![e]);
+    let cons = |e, tail| MetaExpr::Const("Cons".into(), vec![e, tail]);
+    let nil = MetaExpr::Const("Nil".into(), vec![]);
+
+    let Ok(Program::Expr(e)) = parse("Suc(2)") else {
+        panic!("expected an expression");
+    };
+    assert_eq!(e, suc(MetaExpr::Nat(2u32.into())));
+
+    let Ok(Program::Expr(e)) = parse("[x, [], 0,]") else {
+        panic!("expected an expression");
+    };
+    assert_eq!(
+        e,
+        cons(
+            MetaExpr::Var("x".into()),
+            cons(nil.clone(), cons(MetaExpr::Nat(0u32.into()), nil))
+        )
+    );
+}
+
+#[test]
+fn literal_patterns() {
+    let Ok(Program::Expr(e)) = parse("case x of { 0 -> A(); [] -> B() }") else {
+        panic!("expected an expression");
+    };
+    let MetaExpr::Case(_, branches) = e.unlocated() else {
+        panic!("expected a case expression");
+    };
+    assert_eq!(branches[0].0, "Zero".into());
+    assert_eq!(branches[1].0, "Nil".into());
+
+    // Other numbers are nested patterns
+    let Ok(Program::Expr(e)) = parse("case x of { 1 -> A() }") else {
+        panic!("expected an expression");
+    };
+    let MetaExpr::Case(_, branches) = e.unlocated() else {
+        panic!("expected a case expression");
+    };
+    assert_eq!(branches[0].0, "Suc".into());
+
+    // Number patterns are expanded, so they can not be too large
+    assert!(parse("case x of { 100 -> A() }").is_ok());
+    for source in [
+        "case x of { 101 -> A() }",
+        "case x of { 99999999999999999999999 -> A() }",
+    ] {
+        let errors = parse(source).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].span().start, 12);
+    }
+}
+
+#[test]
+fn large_coded_numbers() {
+    assert!(parse(r#""10000""#).is_ok());
+    let source = r#"[1, "Suc(10001)", "x 99999999999999999999999"]"#;
+    let errors = parse(source).unwrap_err();
+    let spans: Vec<_> = errors
+        .iter()
+        .map(|error| &source[error.span().into_range()])
+        .collect();
+    assert_eq!(spans, ["10001", "99999999999999999999999"]);
+}
+
+#[test]
+fn lambda_multiple_arguments() {
+    let x = Variable::from("x");
+    let y = Variable::from("y");
+    assert_eq!(
+        parse(r"\x y. y").unwrap(),
+        Program::Expr(MetaExpr::Lambdas(
+            vec![x.clone(), y.clone()],
+            Box::new(MetaExpr::Var(y.clone()))
+        ))
+    );
+    assert_eq!(
+        parse(r"\x. y").unwrap(),
+        Program::Expr(MetaExpr::Lambda(x, Box::new(MetaExpr::Var(y))))
+    );
+    assert!(parse(r"\. x").is_err());
+}
+
+#[test]
+fn function_definitions() {
+    let f = Variable::from("f");
+    let x = Variable::from("x");
+    let y = Variable::from("y");
+    let body = MetaExpr::Apply(
+        Box::new(MetaExpr::Var(f.clone())),
+        Box::new(MetaExpr::Var(y.clone())),
+    );
+
+    let (bindings, _) =
+        bindings(parse(r"let rec f x y = f y; let g x = x; let rec h = h; f").unwrap());
+    assert_eq!(
+        bindings,
+        vec![
+            (
+                f.clone(),
+                MetaExpr::Rec(
+                    f.clone(),
+                    Box::new(MetaExpr::Lambdas(vec![x.clone(), y], Box::new(body)))
+                )
+            ),
+            (
+                "g".into(),
+                MetaExpr::Lambda(x.clone(), Box::new(MetaExpr::Var(x)))
+            ),
+            (
+                "h".into(),
+                MetaExpr::Rec("h".into(), Box::new(MetaExpr::Var("h".into())))
+            ),
+        ]
+    );
+}
+
+#[test]
+fn local_definitions() {
+    let x = Variable::from("x");
+    let a = MetaExpr::Const("A".into(), vec![]);
+    let local = MetaExpr::Let(
+        x.clone(),
+        Box::new(a.clone()),
+        Box::new(MetaExpr::Var(x.clone())),
+    );
+
+    assert_eq!(
+        parse("let x = A() in x").unwrap(),
+        Program::Expr(local.clone())
+    );
+    assert_eq!(
+        parse("x where { x = A() }").unwrap(),
+        Program::Expr(local.clone())
+    );
+    assert_eq!(
+        parse("let x = A() in let x = A() in x").unwrap(),
+        Program::Expr(MetaExpr::Let(
+            x.clone(),
+            Box::new(a),
+            Box::new(local.clone())
+        ))
+    );
+
+    // A top-level definition that uses a local definition
+    let (bindings, _) = bindings(parse("let y = let x = A() in x; y").unwrap());
+    assert_eq!(bindings, vec![("y".into(), local.clone())]);
+
+    let entry = parse_entry("let x = A() in x").unwrap();
+    assert!(entry.definitions.is_empty());
+    assert_eq!(entry.expr, Some(local));
+}
+
+#[test]
+fn where_clauses() {
+    let (bindings, _) =
+        bindings(parse(r"let f = \y. g x where { x = y; rec g a = g a; }; f").unwrap());
+    let MetaExpr::Lambda(_, body) = bindings[0].1.unlocated() else {
+        panic!("expected a lambda");
+    };
+    let MetaExpr::Let(x, _, rest) = body.unlocated() else {
+        panic!("expected a local definition");
+    };
+    assert_eq!(x, &Variable::from("x"));
+    let MetaExpr::Let(g, rhs, _) = rest.unlocated() else {
+        panic!("expected a local definition");
+    };
+    assert_eq!(g, &Variable::from("g"));
+    assert!(matches!(rhs.unlocated(), MetaExpr::Rec(..)));
+
+    assert!(parse("x where {}").is_err());
+
+    // `in` and `where` are only keywords in local definitions
+    let (keywords, expr) =
+        self::bindings(parse("let in = A(); let where = B(); Pair(in, where)").unwrap());
+    assert_eq!(keywords[0].0, Variable::from("in"));
+    assert_eq!(keywords[1].0, Variable::from("where"));
+    assert_eq!(
+        expr,
+        MetaExpr::Const(
+            "Pair".into(),
+            vec![MetaExpr::Var("in".into()), MetaExpr::Var("where".into())]
+        )
+    );
+    assert!(parse(r"let f in = \where. where; f").is_ok());
+    // Nor where a local definition can not end, as a lambda binder or where an expression starts
+    assert!(parse(r"let id = \in. in; id C()").is_ok());
+    assert!(parse(r"let f = \x in. in; f").is_ok());
+    let Program::Expr(expr) = parse(r"let x = in in \in. x in").unwrap() else {
+        panic!("expected an expression");
+    };
+    let MetaExpr::Let(x, rhs, body) = expr.unlocated() else {
+        panic!("expected a local definition");
+    };
+    assert_eq!(x, &Variable::from("x"));
+    assert_eq!(rhs.unlocated(), &MetaExpr::Var("in".into()));
+    assert!(matches!(body.unlocated(), MetaExpr::Lambda(..)));
+    assert!(parse("let x = A() in").is_err());
+}
+
+#[test]
+fn recursive_groups() {
+    let program = parse(
+        r"--| Parity
+        let rec even n = odd n and odd = \n. even n and f x y = f x y;
+        even",
+    )
+    .unwrap();
+    assert_eq!(
+        program.docs(),
+        vec![
+            (&Variable::from("even"), "Parity"),
+            (&Variable::from("odd"), "Parity"),
+            (&Variable::from("f"), "Parity"),
+        ]
+    );
+
+    let errors = parse("let rec f x = g x and g = A(); f").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].span().start, 22);
+    assert!(parse("let rec f x = x and f y = y; f").is_err());
+    // `and` only separates the definitions after a complete body
+    assert!(parse("let rec f and = and; f C()").is_ok());
+    assert!(parse(r"let rec f = \and. and and g and = and; f g").is_ok());
+    assert!(parse("let rec f x = x and; f").is_err());
+
+    // `and` is a variable name outside of the groups, and inside them between brackets
+    let program = parse(
+        r"let and = \x y. x;
+        let rec f x = (and) x (g x) and g x = let rec h y = y in h x;
+        let h = \x. let rec x = x in and x;
+        and A() B()",
+    )
+    .unwrap();
+    let (bindings, e) = bindings(program);
+    let names: Vec<_> = bindings.iter().map(|(x, _)| x.0.as_str()).collect();
+    assert_eq!(names, ["and", "f", "g", "h"]);
+    let MetaExpr::Apply(f, _) = e.unlocated() else {
+        panic!("expected an application");
+    };
+    let MetaExpr::Apply(f, _) = f.unlocated() else {
+        panic!("expected an application");
+    };
+    assert_eq!(f.unlocated(), &MetaExpr::Var(Variable::from("and")));
+}
@@ -0,0 +1,533 @@
+/// Nested patterns in case branches, and the compiler that turns them into the plain `case` expressions of Chi.
+/// Branches are tried from top to bottom, so the first branch that matches a value is used, like in Haskell.
+use std::fmt;
+
+use chumsky::{error::Rich, span::SimpleSpan};
+
+use crate::{
+    coder::CODED_CONSTRUCTORS,
+    data::{Constructors, Use},
+    lexer::Token,
+    parser::{Branch, CodedLiteral, Constructor, Span, Variable},
+    MetaExpr, Program,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// Matches any value, and binds it to the variable unless it is `_`
+    Var(Variable),
+    Const(Constructor, Vec<Pattern>),
+}
+
+impl Pattern {
+    /// The pattern of the natural number `n`
+    pub(crate) fn nat(n: usize) -> Self {
+        (0..n).fold(Pattern::Const("Zero".into(), vec![]), |p, _| {
+            Pattern::Const("Suc".into(), vec![p])
+        })
+    }
+
+    /// The variables of a pattern of the core language, `c(x1, ..., xn)`
+    pub(crate) fn simple(&self) -> Option<Vec<Variable>> {
+        let Pattern::Const(_, ps) = self else {
+            return None;
+        };
+        ps.iter()
+            .map(|p| match p {
+                Pattern::Var(x) => Some(x.clone()),
+                Pattern::Const(..) => None,
+            })
+            .collect()
+    }
+
+    /// Record the constructors of the pattern at `span`, with their number of fields
+    pub(crate) fn uses(&self, span: SimpleSpan, uses: &mut Vec<Use>) {
+        if let Pattern::Const(c, ps) = self {
+            uses.push(Use::Const(c.clone(), ps.len(), span));
+            ps.iter().for_each(|p| p.uses(span, uses));
+        }
+    }
+
+    fn variables<'a>(&'a self, xs: &mut Vec<&'a Variable>) {
+        match self {
+            Pattern::Var(x) if !is_wildcard(x) => xs.push(x),
+            Pattern::Var(_) => {}
+            Pattern::Const(_, ps) => ps.iter().for_each(|p| p.variables(xs)),
+        }
+    }
+
+    /// Is there a value that both patterns match
+    fn overlaps(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Pattern::Var(_), _) | (_, Pattern::Var(_)) => true,
+            (Pattern::Const(c1, ps1), Pattern::Const(c2, ps2)) => {
+                c1 == c2
+                    && ps1.len() == ps2.len()
+                    && ps1.iter().zip(ps2).all(|(p1, p2)| p1.overlaps(p2))
+            }
+        }
+    }
+
+    /// Does this pattern match every value that `other` matches
+    fn generalizes(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Pattern::Var(_), _) => true,
+            (Pattern::Const(..), Pattern::Var(_)) => false,
+            (Pattern::Const(c1, ps1), Pattern::Const(c2, ps2)) => {
+                c1 == c2
+                    && ps1.len() == ps2.len()
+                    && ps1.iter().zip(ps2).all(|(p1, p2)| p1.generalizes(p2))
+            }
+        }
+    }
+}
+
+fn is_wildcard(x: &Variable) -> bool {
+    x.0 == "_"
+}
+
+/// A branch that can not be reached, or that is only reached for some of the values it matches
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    /// The location of the pattern
+    pub span: Span,
+    pub kind: WarningKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WarningKind {
+    /// The branches above match every value that this branch matches
+    Redundant,
+    /// Some values are matched both by this branch and by the earlier branch at the span, which is used for them
+    Overlapping(Span),
+}
+
+/// A branch with a nested pattern
+pub(crate) struct Arm {
+    pub pattern: Pattern,
+    pub span: SimpleSpan,
+    pub body: MetaExpr,
+}
+
+/// Compile the case expression at `span` with nested patterns to nested `case` expressions of the core language,
+/// the warnings about the branches are added to `warnings`, and the constructors that each `case` matches to `uses`.
+/// The variables of a pattern are bound with local definitions, `let x = v in e`
+pub(crate) fn compile<'a>(
+    scrutinee: MetaExpr,
+    arms: Vec<Arm>,
+    span: SimpleSpan,
+    constructors: &Constructors,
+    warnings: &mut Vec<Warning>,
+    uses: &mut Vec<Use>,
+) -> Result<MetaExpr, Rich<'a, Token<'a>>> {
+    for arm in &arms {
+        let mut xs = Vec::new();
+        arm.pattern.variables(&mut xs);
+        let duplicate = (0..xs.len()).find(|i| xs[..*i].contains(&xs[*i]));
+        if let Some(x) = duplicate.map(|i| xs[i]) {
+            return Err(Rich::custom(
+                arm.span,
+                format!("{x} is bound more than once in this pattern"),
+            ));
+        }
+    }
+
+    let rows = arms
+        .iter()
+        .enumerate()
+        .map(|(arm, Arm { pattern, .. })| Row {
+            patterns: vec![pattern.clone()],
+            bindings: Vec::new(),
+            arm,
+        })
+        .collect();
+
+    let mut compiler = Compiler {
+        arms: &arms,
+        span,
+        constructors,
+        uses,
+        used: vec![false; arms.len()],
+        fresh: 0,
+    };
+    let expr = compiler.compile(vec![scrutinee], rows)?;
+
+    for (j, arm) in arms.iter().enumerate() {
+        let kind = if !compiler.used[j] {
+            WarningKind::Redundant
+        } else if let Some(earlier) = arms[..j].iter().find(|earlier| {
+            earlier.pattern.overlaps(&arm.pattern) && !arm.pattern.generalizes(&earlier.pattern)
+        }) {
+            WarningKind::Overlapping(earlier.span.into_range())
+        } else {
+            continue;
+        };
+        warnings.push(Warning {
+            span: arm.span.into_range(),
+            kind,
+        });
+    }
+
+    Ok(expr)
+}
+
+/// The constructor of the default branch of a case expression that is compiled from nested patterns, when the
+/// constructors of the data type are not known. It can not be written in the source, and it is replaced by
+/// `complete_defaults` once the whole program is parsed
+const DEFAULT: &str = "_";
+
+/// Replace the default branches with a branch for each constructor of the program that has no branch,
+/// since the case expressions of Chi have no default branch.
+/// The values of the program can only be built from its constructors, and those of numbers and coded literals
+pub(crate) fn complete_defaults(mut program: Program<MetaExpr>) -> Program<MetaExpr> {
+    let mut exprs = Vec::new();
+    let mut rest = &mut program;
+    loop {
+        match rest {
+            Program::Let(_, _, e, next) => {
+                exprs.push(e);
+                rest = next;
+            }
+            Program::Expr(e) => {
+                exprs.push(e);
+                break;
+            }
+        }
+    }
+    complete(exprs);
+    program
+}
+
+/// The locations of the case expressions with a default branch in the definitions and the expression of a session
+/// input. Unlike in a program they can not be completed, since a later input can use other constructors
+pub(crate) fn entry_defaults(
+    definitions: &[(Variable, MetaExpr)],
+    expr: Option<&MetaExpr>,
+) -> Vec<Option<Span>> {
+    let mut spans = Vec::new();
+    let mut todo: Vec<(&MetaExpr, Option<&Span>)> = definitions
+        .iter()
+        .map(|(_, e)| e)
+        .chain(expr)
+        .map(|e| (e, None))
+        .collect();
+    while let Some((e, span)) = todo.pop() {
+        match e {
+            MetaExpr::Located(span, e) => todo.push((e, Some(span))),
+            MetaExpr::Apply(e1, e2) | MetaExpr::Let(_, e1, e2) => {
+                todo.extend([(&**e1, span), (&**e2, span)]);
+            }
+            MetaExpr::Lambda(_, e) | MetaExpr::Lambdas(_, e) | MetaExpr::Rec(_, e) => {
+                todo.push((e, span))
+            }
+            MetaExpr::Case(e, branches) => {
+                if branches.iter().any(|Branch(c, ..)| c.0 == DEFAULT) {
+                    spans.push(span.cloned());
+                }
+                todo.push((e, span));
+                todo.extend(branches.iter().map(|Branch(_, _, e)| (e, span)));
+            }
+            MetaExpr::Const(_, es) => todo.extend(es.iter().map(|e| (e, span))),
+            MetaExpr::Coded(literal) => {
+                let CodedLiteral::Expr(e) = &**literal;
+                todo.push((e, span));
+            }
+            MetaExpr::Var(_) | MetaExpr::Nat(_) | MetaExpr::Error => {}
+        }
+    }
+    spans
+}
+
+fn complete(mut exprs: Vec<&mut MetaExpr>) {
+    let mut constructors: Vec<(Constructor, usize)> = Vec::new();
+    let mut add = |c: &Constructor, arity: usize| {
+        if !constructors.iter().any(|(d, _)| d == c) {
+            constructors.push((c.clone(), arity));
+        }
+    };
+    let mut todo: Vec<&MetaExpr> = exprs.iter().map(|e| &**e).collect();
+    let mut defaults = false;
+    while let Some(e) = todo.pop() {
+        match e {
+            MetaExpr::Apply(e1, e2) | MetaExpr::Let(_, e1, e2) => todo.extend([&**e1, &**e2]),
+            MetaExpr::Lambda(_, e)
+            | MetaExpr::Lambdas(_, e)
+            | MetaExpr::Rec(_, e)
+            | MetaExpr::Located(_, e) => todo.push(e),
+            MetaExpr::Case(e, branches) => {
+                todo.push(e);
+                for Branch(c, xs, e) in branches {
+                    if c.0 == DEFAULT {
+                        defaults = true;
+                    } else {
+                        add(c, xs.len());
+                    }
+                    todo.push(e);
+                }
+            }
+            MetaExpr::Const(c, es) => {
+                add(c, es.len());
+                todo.extend(es);
+            }
+            MetaExpr::Nat(_) => {
+                add(&"Zero".into(), 0);
+                add(&"Suc".into(), 1);
+            }
+            MetaExpr::Coded(literal) => {
+                for (c, arity) in CODED_CONSTRUCTORS {
+                    add(&c.into(), arity);
+                }
+                let CodedLiteral::Expr(e) = &**literal;
+                todo.push(e);
+            }
+            MetaExpr::Var(_) | MetaExpr::Error => {}
+        }
+    }
+    if !defaults {
+        return;
+    }
+
+    while let Some(e) = exprs.pop() {
+        match e {
+            MetaExpr::Apply(e1, e2) | MetaExpr::Let(_, e1, e2) => {
+                exprs.extend([&mut **e1, &mut **e2])
+            }
+            MetaExpr::Lambda(_, e)
+            | MetaExpr::Lambdas(_, e)
+            | MetaExpr::Rec(_, e)
+            | MetaExpr::Located(_, e) => exprs.push(e),
+            MetaExpr::Case(e, branches) => {
+                if let Some(i) = branches.iter().position(|Branch(c, ..)| c.0 == DEFAULT) {
+                    let Branch(_, _, default) = branches.remove(i);
+                    let missing: Vec<Branch<MetaExpr>> = constructors
+                        .iter()
+                        .filter(|(c, _)| !branches.iter().any(|Branch(d, ..)| d == c))
+                        .map(|(c, arity)| {
+                            // The fields are not used, the names can not be written in the source
+                            let fields = (0..*arity).map(|i| Variable(format!("#_{i}"))).collect();
+                            Branch(c.clone(), fields, default.clone())
+                        })
+                        .collect();
+                    branches.extend(missing);
+                }
+                exprs.push(e);
+                exprs.extend(branches.iter_mut().map(|Branch(_, _, e)| e));
+            }
+            MetaExpr::Const(_, es) => exprs.extend(es),
+            MetaExpr::Coded(literal) => {
+                let CodedLiteral::Expr(e) = &mut **literal;
+                exprs.push(e);
+            }
+            MetaExpr::Var(_) | MetaExpr::Nat(_) | MetaExpr::Error => {}
+        }
+    }
+}
+
+/// A row of the pattern matrix, the patterns are matched against the values of the occurrences
+struct Row {
+    patterns: Vec<Pattern>,
+    /// The variables of the patterns that are already matched
+    bindings: Vec<(Variable, MetaExpr)>,
+    arm: usize,
+}
+
+/// The constructors that a column of the pattern matrix is split on
+struct Split {
+    constructors: Vec<(Constructor, usize)>,
+    /// The other constructors are matched by a default branch
+    open: bool,
+}
+
+struct Compiler<'c> {
+    arms: &'c [Arm],
+    /// The location of the case expression
+    span: SimpleSpan,
+    constructors: &'c Constructors,
+    uses: &'c mut Vec<Use>,
+    /// The arms that are reached for some value
+    used: Vec<bool>,
+    fresh: usize,
+}
+
+impl Compiler<'_> {
+    /// Match the values of `occurrences` against the rows, the first row that matches is used.
+    /// Each occurrence is used once, so the scrutinee is only evaluated once
+    fn compile<'a>(
+        &mut self,
+        mut occurrences: Vec<MetaExpr>,
+        mut rows: Vec<Row>,
+    ) -> Result<MetaExpr, Rich<'a, Token<'a>>> {
+        let first = rows
+            .first()
+            .expect("every constructor is matched by some row");
+
+        // Split on the first constructor pattern of the first row, there is none if the first row matches anything
+        let Some(column) = first
+            .patterns
+            .iter()
+            .position(|p| matches!(p, Pattern::Const(..)))
+        else {
+            let row = rows.swap_remove(0);
+            self.used[row.arm] = true;
+            let mut bindings = row.bindings;
+            for (p, occurrence) in row.patterns.into_iter().zip(occurrences) {
+                match p {
+                    Pattern::Var(x) if !is_wildcard(&x) => bindings.push((x, occurrence)),
+                    _ => {}
+                }
+            }
+            let body = self.arms[row.arm].body.clone();
+            return Ok(bindings.into_iter().rev().fold(body, |e, (x, value)| {
+                MetaExpr::Let(x, Box::new(value), Box::new(e))
+            }));
+        };
+
+        let occurrence = occurrences.remove(column);
+        // Only the scrutinee is not a field of a matched value
+        let nested = matches!(&occurrence, MetaExpr::Var(x) if x.0.starts_with('#'));
+        let Split { constructors, open } = self.column_constructors(&rows, column, nested)?;
+
+        let mut branches = Vec::new();
+        for (c, arity) in constructors {
+            let fields: Vec<Variable> = (0..arity).map(|_| self.fresh()).collect();
+            let value = MetaExpr::Const(
+                c.clone(),
+                fields.iter().cloned().map(MetaExpr::Var).collect(),
+            );
+
+            let specialized = rows
+                .iter()
+                .filter_map(|row| {
+                    let mut patterns = row.patterns.clone();
+                    let mut bindings = row.bindings.clone();
+                    let head = match patterns.remove(column) {
+                        Pattern::Const(d, ps) if d == c => ps,
+                        Pattern::Const(..) => return None,
+                        Pattern::Var(x) => {
+                            if !is_wildcard(&x) {
+                                bindings.push((x, value.clone()));
+                            }
+                            vec![Pattern::Var("_".into()); arity]
+                        }
+                    };
+                    Some(Row {
+                        patterns: head.into_iter().chain(patterns).collect(),
+                        bindings,
+                        arm: row.arm,
+                    })
+                })
+                .collect();
+
+            let occurrences = fields
+                .iter()
+                .cloned()
+                .map(MetaExpr::Var)
+                .chain(occurrences.iter().cloned())
+                .collect();
+            let body = self.compile(occurrences, specialized)?;
+            branches.push(Branch(c, fields, body));
+        }
+
+        // The other constructors are matched by the rows with a catch-all in the column
+        if open {
+            let rows = rows
+                .into_iter()
+                .filter_map(|mut row| match row.patterns.remove(column) {
+                    Pattern::Var(x) => {
+                        if !is_wildcard(&x) {
+                            row.bindings.push((x, occurrence.clone()));
+                        }
+                        Some(row)
+                    }
+                    Pattern::Const(..) => None,
+                })
+                .collect();
+            let body = self.compile(occurrences, rows)?;
+            branches.push(Branch(DEFAULT.into(), Vec::new(), body));
+        }
+
+        Ok(MetaExpr::Case(Box::new(occurrence), branches))
+    }
+
+    /// The constructors to split on: the ones used in the column,
+    /// or all constructors of their data type if a row matches anything in the column.
+    /// If the data type is not known, the other constructors are left open for a default branch
+    fn column_constructors<'a>(
+        &mut self,
+        rows: &[Row],
+        column: usize,
+        nested: bool,
+    ) -> Result<Split, Rich<'a, Token<'a>>> {
+        let mut constructors: Vec<(Constructor, usize)> = Vec::new();
+        let mut branches = Vec::new();
+        for row in rows {
+            let Pattern::Const(c, ps) = &row.patterns[column] else {
+                continue;
+            };
+            match constructors.iter().find(|(d, _)| d == c) {
+                Some((_, arity)) if *arity != ps.len() => {
+                    return Err(Rich::custom(
+                        self.arms[row.arm].span,
+                        format!("{c} is used with both {arity} and {} arguments", ps.len()),
+                    ))
+                }
+                Some(_) => {}
+                None => {
+                    constructors.push((c.clone(), ps.len()));
+                    branches.push((c.clone(), self.arms[row.arm].span));
+                }
+            }
+        }
+
+        if rows
+            .iter()
+            .all(|row| matches!(row.patterns[column], Pattern::Const(..)))
+        {
+            self.uses.push(Use::Case {
+                branches,
+                span: self.span,
+                nested,
+            });
+            return Ok(Split {
+                constructors,
+                open: false,
+            });
+        }
+
+        let (c, _) = &constructors[0];
+        match self.constructors.data_type(c) {
+            Some(data_type)
+                if constructors
+                    .iter()
+                    .all(|constructor| data_type.constructors.contains(constructor)) =>
+            {
+                Ok(Split {
+                    constructors: data_type.constructors.clone(),
+                    open: false,
+                })
+            }
+            _ => Ok(Split {
+                constructors,
+                open: true,
+            }),
+        }
+    }
+
+    /// A variable that can not be written in the source, so it does not capture any variable of the branches
+    fn fresh(&mut self) -> Variable {
+        self.fresh += 1;
+        Variable(format!("#{}", self.fresh))
+    }
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WarningKind::Redundant => write!(f, "This branch is never used"),
+            WarningKind::Overlapping(_) => write!(
+                f,
+                "This branch overlaps with an earlier branch, which is used for the values that both match"
+            ),
+        }
+    }
+}
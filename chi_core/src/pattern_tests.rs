@@ -0,0 +1,153 @@
+use crate::{
+    eval, parse, parse_with_warnings, replace_coded_literals, EvalError, EvalLimits, Expr,
+    StandardCoder, WarningKind,
+};
+
+fn run(source: &str) -> Result<Expr, EvalError> {
+    let program = replace_coded_literals(parse(source).unwrap(), &mut StandardCoder::default());
+    eval(program, &EvalLimits::default())
+}
+
+fn warnings(source: &str) -> Vec<(&str, WarningKind)> {
+    let (program, warnings) = parse_with_warnings(source);
+    assert!(program.is_ok(), "{program:?}");
+    warnings
+        .into_iter()
+        .map(|warning| (&source[warning.span], warning.kind))
+        .collect()
+}
+
+const PRED2: &str = r"
+    let pred2 = \n. case n of {
+        Suc(Suc(m)) -> m;
+        Suc(Zero()) -> One();
+        Zero() -> None()
+    };
+";
+
+#[test]
+fn nested_patterns() {
+    assert_eq!(run(&format!("{PRED2} pred2 5")), run("3"));
+    assert_eq!(run(&format!("{PRED2} pred2 1")), run("One()"));
+    assert_eq!(run(&format!("{PRED2} pred2 0")), run("None()"));
+
+    let last = r"
+        let rec last xs = case xs of {
+            [x] -> x;
+            Cons(x, Cons(y, ys)) -> last Cons(y, ys)
+        };
+    ";
+    assert_eq!(run(&format!("{last} last [A(), B(), C()]")), run("C()"));
+    assert!(matches!(
+        run(&format!("{last} last []")),
+        Err(EvalError::MissingBranch { .. })
+    ));
+}
+
+#[test]
+fn catch_all_patterns() {
+    let source = r"
+        let is_two = \n. case n of { 2 -> True(); _ -> False() };
+        let both_zero = \p. case p of { Pair(0, 0) -> True(); Pair(x, y) -> False() };
+        let first = \xs. case xs of { Cons(x, _) -> x; other -> other };
+    ";
+    for (expr, value) in [
+        ("is_two 2", "True()"),
+        ("is_two 3", "False()"),
+        ("is_two 0", "False()"),
+        ("both_zero Pair(0, 0)", "True()"),
+        ("both_zero Pair(0, 1)", "False()"),
+        ("both_zero Pair(1, 0)", "False()"),
+        ("first [A()]", "A()"),
+        ("first []", "Nil()"),
+    ] {
+        assert_eq!(run(&format!("{source} {expr}")), run(value), "{expr}");
+    }
+
+    // A variable pattern binds the whole value
+    assert_eq!(run(r"case Suc(A()) of { x -> x }"), run("Suc(A())"));
+}
+
+#[test]
+fn catch_all_patterns_of_unknown_data_types() {
+    // The constructors of `Pair`, `A` and `B` are not known, so a catch-all matches the other constructors of the program
+    let swap = r"
+        let f = \p. case p of { Pair(A(), x) -> x; Pair(y, B()) -> y; other -> other };
+    ";
+    for (expr, value) in [
+        ("f Pair(A(), B())", "B()"),
+        ("f Pair(C(), B())", "C()"),
+        ("f Pair(C(), D())", "Pair(C(), D())"),
+        ("f 2", "2"),
+        (r#"f "x""#, r#""x""#),
+    ] {
+        assert_eq!(run(&format!("{swap} {expr}")), run(value), "{expr}");
+    }
+    assert_eq!(
+        run(r"case Pair(A(),B()) of { Pair(A(), x) -> x; Pair(y, B()) -> y }"),
+        run("B()")
+    );
+    assert_eq!(
+        run(r"case Pair(C(),B()) of { Pair(A(), x) -> x; Pair(y, B()) -> y }"),
+        run("C()")
+    );
+    assert!(matches!(
+        run(r"case Pair(C(),D()) of { Pair(A(), x) -> x; Pair(y, B()) -> y }"),
+        Err(EvalError::MissingBranch { .. })
+    ));
+}
+
+#[test]
+fn pattern_variables_are_scoped() {
+    // The variables bound by the pattern shadow the variables outside
+    assert_eq!(
+        run(r"(\x. case [B()] of { [x] -> x; _ -> x }) A()"),
+        run("B()")
+    );
+    assert_eq!(
+        run(r"(\x. case [] of { [x] -> x; _ -> x }) A()"),
+        run("A()")
+    );
+}
+
+#[test]
+fn pattern_errors() {
+    assert!(parse("case x of { Pair(0, y) -> y; z -> z }").is_ok());
+    assert!(parse("case x of { Pair(0, x) -> x; Pair(x) -> x }").is_err());
+    assert!(parse("case x of { Pair(0, x, x) -> x }").is_err());
+    assert!(parse("case x of { Pair(_, 0, _) -> x }").is_ok());
+}
+
+#[test]
+fn redundant_and_overlapping_patterns() {
+    let source = r"case x of {
+        Pair(0, y) -> A();
+        Pair(x, 0) -> B();
+        Pair(Suc(x), Suc(y)) -> C();
+        Pair(1, 1) -> D();
+        Pair(x, y) -> E();
+        Pair(0, 0) -> F()
+    }";
+    let first = source.find("Pair(0, y)").unwrap();
+    assert_eq!(
+        warnings(source),
+        vec![
+            ("Pair(x, 0)", WarningKind::Overlapping(first..first + 10)),
+            ("Pair(1, 1)", WarningKind::Redundant),
+            ("Pair(x, y)", WarningKind::Redundant),
+            ("Pair(0, 0)", WarningKind::Redundant),
+        ]
+    );
+
+    // A later branch that is more general is not overlapping
+    assert_eq!(warnings(&format!("{PRED2} pred2")), vec![]);
+    assert_eq!(warnings("case x of { 0 -> A(); n -> B() }"), vec![]);
+}
+
+#[test]
+fn coded_nested_patterns() {
+    // Coded literals follow the coding of the compiled case expressions
+    let coded = run(r#""case x of { Suc(Zero()) -> y }""#);
+    assert!(coded.is_ok());
+    assert_eq!(coded, run(r#""case x of { 1 -> y }""#));
+}
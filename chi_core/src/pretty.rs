@@ -1,83 +1,220 @@
 use crate::{
-    parser::Branch,
+    parser::{Branch, Constructor},
     Expr::{self, *},
 };
-use std::fmt::Write;
+use num_bigint::BigUint;
+use std::fmt::{self, Write};
 
-// Pretty printers for the concrete and abstract syntax used in the Computability course
-// TODO: these printers are very wasteful with memory, would be a lot nicer if they just wrote to a single mutable buffer
-// instead of creating new strings each recursive call
+// Pretty printers for the concrete and abstract syntax used in the Computability course.
+// Values can be very deeply nested (a long list is a chain of `Cons`), so instead of recursing the printers keep the
+// parts that remain to be printed in an explicit stack, and write to a single buffer
 
-const INDENT: &'static str = "  ";
+const INDENT: &str = "  ";
 
-pub fn concrete(expr: &Expr) -> String {
-    concrete_expr(expr, 0, 0)
-}
+/// The largest output of the concrete and abstract printers (in bytes).
+/// They have no number literals, so a large natural number can not be printed
+pub const MAX_OUTPUT: usize = 1 << 26;
 
-fn concrete_expr(expr: &Expr, indent: usize, precedence_lvl: u8) -> String {
-    let mut s = String::new();
+/// The output of the concrete or abstract printer would be larger than `MAX_OUTPUT`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TooLarge;
 
-    let current_precedence = precedence(expr);
-    if current_precedence < precedence_lvl {
-        s.push('(');
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The value is too large to print in this syntax (more than {MAX_OUTPUT} bytes), \
+            the sugared syntax prints natural numbers as number literals"
+        )
     }
+}
 
-    match expr {
-        Apply(e1, e2) => write!(
-            &mut s,
-            "{} {}",
-            concrete_expr(e1, indent, 1),
-            concrete_expr(e2, indent, 2)
-        )
-        .unwrap(),
-        Lambda(x, e) => write!(&mut s, r"\{x}. {}", concrete_expr(e, indent, 0)).unwrap(),
-        Case(e, branches) => write!(
-            &mut s,
-            "case {} of {{\n{}\n{indent}}}",
-            concrete_expr(e, indent, 0),
-            concrete_branches(branches, indent + 1),
-            indent = INDENT.repeat(indent)
-        )
-        .unwrap(),
-        Rec(x, e) => write!(&mut s, "rec {x} = {}", concrete_expr(e, indent, 0)).unwrap(),
-        Var(x) => write!(&mut s, "{x}").unwrap(),
-        Const(c, es) => {
-            let es: Vec<String> = es.iter().map(|e| concrete_expr(e, indent, 0)).collect();
-            write!(&mut s, "{c}({es})", es = es.join(",")).unwrap()
+/// The unary representation `prefix^n zero suffix^n` of `n`, if the output stays within `MAX_OUTPUT`
+fn unary(
+    output: &str,
+    n: &BigUint,
+    zero: &str,
+    prefix: &str,
+    suffix: &str,
+) -> Result<String, TooLarge> {
+    let room = MAX_OUTPUT.saturating_sub(output.len() + zero.len());
+    match usize::try_from(n) {
+        Ok(n) if n <= room / (prefix.len() + suffix.len()) => {
+            Ok(format!("{}{zero}{}", prefix.repeat(n), suffix.repeat(n)))
         }
+        _ => Err(TooLarge),
     }
+}
+
+/// A part of the output that remains to be printed
+enum Item<'a> {
+    Str(&'static str),
+    String(String),
+    /// An expression with its indentation and the precedence of its position
+    Expr(&'a Expr, usize, u8),
+}
+
+use Item::Str;
+
+pub fn concrete(expr: &Expr) -> Result<String, TooLarge> {
+    concrete_expr(expr, false)
+}
+
+/// The concrete syntax, but natural numbers are printed as number literals and lists as list literals
+pub fn sugared(expr: &Expr) -> String {
+    concrete_expr(expr, true).expect("only the unary natural numbers are too large")
+}
+
+/// The concrete syntax if it is not too large and the sugared syntax otherwise, for the messages about a value
+pub(crate) fn message(expr: &Expr) -> String {
+    concrete(expr).unwrap_or_else(|TooLarge| sugared(expr))
+}
+
+fn concrete_expr(expr: &Expr, sugar: bool) -> Result<String, TooLarge> {
+    let mut s = String::new();
+    let mut todo = vec![Item::Expr(expr, 0, 0)];
+
+    while let Some(item) = todo.pop() {
+        let (expr, indent, precedence_lvl) = match item {
+            Str(text) => {
+                s.push_str(text);
+                continue;
+            }
+            Item::String(text) => {
+                s.push_str(&text);
+                continue;
+            }
+            Item::Expr(expr, indent, precedence_lvl) => (expr.unlocated(), indent, precedence_lvl),
+        };
+
+        // The parts of `expr` in order, they are pushed to `todo` in reverse order
+        let mut items = Vec::new();
+        if sugar {
+            if let Some(n) = natural(expr) {
+                write!(&mut s, "{n}").unwrap();
+                continue;
+            }
+            if let Some(es) = list(expr) {
+                items.push(Str("["));
+                for (i, e) in es.into_iter().enumerate() {
+                    if i > 0 {
+                        items.push(Str(", "));
+                    }
+                    items.push(Item::Expr(e, indent, 0));
+                }
+                items.push(Str("]"));
+                todo.extend(items.into_iter().rev());
+                continue;
+            }
+        }
+
+        let parenthesized = precedence(expr) < precedence_lvl;
+        if parenthesized {
+            items.push(Str("("));
+        }
+
+        match expr {
+            Apply(e1, e2) => items.extend([
+                Item::Expr(e1, indent, 1),
+                Str(" "),
+                Item::Expr(e2, indent, 2),
+            ]),
+            Lambda(x, e) => {
+                items.extend([Item::String(format!(r"\{x}. ")), Item::Expr(e, indent, 0)])
+            }
+            Case(e, branches) => {
+                items.extend([Str("case "), Item::Expr(e, indent, 0), Str(" of {\n")]);
+                concrete_branches(branches, indent + 1, sugar, &mut items);
+                items.push(Item::String(format!("\n{}}}", INDENT.repeat(indent))));
+            }
+            Rec(x, e) => items.extend([
+                Item::String(format!("rec {x} = ")),
+                Item::Expr(e, indent, 0),
+            ]),
+            Var(x) | Global(x, _) => items.push(Item::String(x.to_string())),
+            Const(c, es) => {
+                items.push(Item::String(format!("{c}(")));
+                for (i, e) in es.iter().enumerate() {
+                    if i > 0 {
+                        items.push(Str(","));
+                    }
+                    items.push(Item::Expr(e, indent, 0));
+                }
+                items.push(Str(")"));
+            }
+            Nat(n) => items.push(Item::String(unary(&s, n, "Zero()", "Suc(", ")")?)),
+            Located(..) => unreachable!(),
+        }
 
-    if current_precedence < precedence_lvl {
-        s.push(')');
+        if parenthesized {
+            items.push(Str(")"));
+        }
+        todo.extend(items.into_iter().rev());
     }
 
-    s
+    Ok(s)
 }
 
-fn concrete_branches(branches: &Vec<Branch<Expr>>, indent: usize) -> String {
+fn concrete_branches<'a>(
+    branches: &'a [Branch<Expr>],
+    indent: usize,
+    sugar: bool,
+    items: &mut Vec<Item<'a>>,
+) {
     /*
     Foo() -> Bar();
     Baz() -> Bam()
     */
-    let mut s = String::new();
-
     for (i, Branch(c, vars, expr)) in branches.iter().enumerate() {
         let vars: Vec<String> = vars.iter().map(|x| format!("{x}")).collect();
-        write!(
-            &mut s,
-            "{indent}{c}({vars}) -> {expr}",
-            indent = INDENT.repeat(indent),
-            vars = vars.join(","),
-            expr = concrete_expr(expr, indent, 0)
-        )
-        .unwrap();
+        let pattern = match (c.0.as_str(), vars.as_slice()) {
+            ("Zero", []) if sugar => "0".to_string(),
+            ("Nil", []) if sugar => "[]".to_string(),
+            _ => format!("{c}({vars})", vars = vars.join(",")),
+        };
+        items.push(Item::String(format!(
+            "{indent}{pattern} -> ",
+            indent = INDENT.repeat(indent)
+        )));
+        items.push(Item::Expr(expr, indent, 0));
 
         if i + 1 != branches.len() {
-            s.push(';');
-            s.push('\n');
+            items.push(Str(";\n"));
+        }
+    }
+}
+
+/// The number that `expr` represents, if it is a natural number
+fn natural(expr: &Expr) -> Option<BigUint> {
+    let mut n = BigUint::default();
+    let mut expr = expr.unlocated();
+    loop {
+        match expr {
+            Nat(m) => return Some(n + m),
+            Const(Constructor(c), es) if c == "Zero" && es.is_empty() => return Some(n),
+            Const(Constructor(c), es) if c == "Suc" && es.len() == 1 => {
+                n += 1u32;
+                expr = es[0].unlocated();
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// The elements of `expr`, if it is a list
+fn list(expr: &Expr) -> Option<Vec<&Expr>> {
+    let mut es = Vec::new();
+    let mut expr = expr.unlocated();
+    loop {
+        match expr {
+            Const(Constructor(c), tail) if c == "Nil" && tail.is_empty() => return Some(es),
+            Const(Constructor(c), args) if c == "Cons" && args.len() == 2 => {
+                es.push(&args[0]);
+                expr = args[1].unlocated();
+            }
+            _ => return None,
         }
     }
-    s
 }
 
 fn precedence(expr: &Expr) -> u8 {
@@ -86,45 +223,107 @@ fn precedence(expr: &Expr) -> u8 {
         Lambda(..) => 0,
         Case(..) => 1,
         Rec(..) => 0,
-        Var(..) => 2,
-        Const(..) => 2,
+        Var(..) | Global(..) => 2,
+        Const(..) | Nat(_) => 2,
+        Located(_, e) => precedence(e),
     }
 }
 
-pub fn abstr(expr: &Expr) -> String {
-    abstr_expr(expr)
-}
+pub fn abstr(expr: &Expr) -> Result<String, TooLarge> {
+    let mut s = String::new();
+    let mut todo = vec![Item::Expr(expr, 0, 0)];
 
-fn abstr_expr(expr: &Expr) -> String {
-    match expr {
-        Apply(e1, e2) => format!("apply ({}) ({})", abstr_expr(e1), abstr_expr(e2)),
-        Lambda(x, e) => format!(r"lambda <u>{x}</u> ({})", abstr_expr(e)),
-        Case(e, branches) => {
-            let branches: Vec<String> = branches.iter().map(abstr_branch).collect();
-            format!("case ({}) ({})", abstr_expr(e), abstr_list(&branches))
-        }
-        Rec(x, e) => format!("rec <u>{x}</u> ({})", abstr_expr(e)),
-        Var(x) => format!("var <u>{x}</u>"),
-        Const(c, es) => {
-            let es: Vec<String> = es.iter().map(|e| abstr_expr(e)).collect();
-            format!("const <u>{c}</u> {es}", es = abstr_list(&es))
+    while let Some(item) = todo.pop() {
+        let expr = match item {
+            Str(text) => {
+                s.push_str(text);
+                continue;
+            }
+            Item::String(text) => {
+                s.push_str(&text);
+                continue;
+            }
+            Item::Expr(expr, ..) => expr,
+        };
+
+        // The parts of `expr` in order, they are pushed to `todo` in reverse order
+        let mut items = Vec::new();
+        match expr {
+            Apply(e1, e2) => items.extend([
+                Str("apply ("),
+                Item::Expr(e1, 0, 0),
+                Str(") ("),
+                Item::Expr(e2, 0, 0),
+                Str(")"),
+            ]),
+            Lambda(x, e) => items.extend([
+                Item::String(format!("lambda <u>{x}</u> (")),
+                Item::Expr(e, 0, 0),
+                Str(")"),
+            ]),
+            Case(e, branches) => {
+                items.extend([Str("case ("), Item::Expr(e, 0, 0), Str(") (")]);
+                let branches = branches.iter().map(|Branch(c, vars, e)| {
+                    let vars: Vec<String> = vars.iter().map(|x| format!("<u>{x}</u>")).collect();
+                    [
+                        Item::String(format!(
+                            "branch <u>{c}</u> {vars} (",
+                            vars = abstr_list(&vars)
+                        )),
+                        Item::Expr(e, 0, 0),
+                        Str(")"),
+                    ]
+                });
+                abstr_items(branches, &mut items);
+                items.push(Str(")"));
+            }
+            Rec(x, e) => items.extend([
+                Item::String(format!("rec <u>{x}</u> (")),
+                Item::Expr(e, 0, 0),
+                Str(")"),
+            ]),
+            Var(x) | Global(x, _) => items.push(Item::String(format!("var <u>{x}</u>"))),
+            Const(c, es) => {
+                items.push(Item::String(format!("const <u>{c}</u> ")));
+                abstr_items(es.iter().map(|e| [Item::Expr(e, 0, 0)]), &mut items);
+            }
+            Located(_, e) => items.push(Item::Expr(e, 0, 0)),
+            Nat(n) => items.push(Item::String(unary(
+                &s,
+                n,
+                "const <u>Zero</u> nil",
+                "const <u>Suc</u> (cons (",
+                ") nil)",
+            )?)),
         }
+        todo.extend(items.into_iter().rev());
     }
+
+    Ok(s)
 }
 
-fn abstr_list(xs: &[String]) -> String {
-    if let Some(x) = xs.get(0) {
-        format!("(cons ({x}) {xs})", xs = abstr_list(&xs[1..]))
-    } else {
-        "nil".to_string()
+/// The list `(cons (x1) (cons (x2) ... nil))` of the parts of the elements
+fn abstr_items<'a, const N: usize>(
+    xs: impl Iterator<Item = [Item<'a>; N]>,
+    items: &mut Vec<Item<'a>>,
+) {
+    let mut n = 0;
+    for x in xs {
+        items.push(Str("(cons ("));
+        items.extend(x);
+        items.push(Str(") "));
+        n += 1;
     }
+    items.push(Str("nil"));
+    items.extend((0..n).map(|_| Str(")")));
 }
 
-fn abstr_branch(Branch(c, vars, e): &Branch<Expr>) -> String {
-    let vars: Vec<String> = vars.iter().map(|x| format!("<u>{x}</u>")).collect();
-    format!(
-        "branch <u>{c}</u> {vars} ({e})",
-        vars = abstr_list(&vars),
-        e = abstr_expr(e)
-    )
+fn abstr_list(xs: &[String]) -> String {
+    let mut s = String::new();
+    for x in xs {
+        write!(&mut s, "(cons ({x}) ").unwrap();
+    }
+    s.push_str("nil");
+    s.push_str(&")".repeat(xs.len()));
+    s
 }
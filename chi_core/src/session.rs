@@ -0,0 +1,219 @@
+/// A session that keeps definitions between inputs, for REPLs and other incremental uses of the interpreter.
+///
+/// Unlike the `let` bindings of a program, the definitions of a session refer to each other by name:
+/// redefining a name changes the meaning of every definition that uses it, and a definition may use a name
+/// that is only defined later. Definitions that depend on each other are rejected, since `let` is not recursive.
+/// In particular `let n = Suc(n);` is rejected, while in a program it defines a new `n` in terms of the earlier `n`
+/// (or gets stuck on the unbound `n` when it is evaluated), so extending a definition needs a new name.
+use std::collections::HashSet;
+
+use crate::{
+    coder::to_expr,
+    eval,
+    normalize::free_variables,
+    parse_error_report,
+    parser::{parse_entry, Entry, Variable},
+    EvalError, EvalLimits, Expr, Program, StandardCoder,
+};
+
+pub struct Session {
+    limits: EvalLimits,
+    /// Shared by all inputs, so the same symbol gets the same code every time
+    coder: StandardCoder,
+    /// In the order they were first defined
+    definitions: Vec<Definition>,
+}
+
+#[derive(Clone)]
+struct Definition {
+    name: Variable,
+    expr: Expr,
+    free: HashSet<Variable>,
+}
+
+/// The result of an input to a session
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    /// The names that were defined or redefined, in order
+    pub defined: Vec<Variable>,
+    /// The other definitions that use a (re)defined name, directly or through other definitions
+    pub affected: Vec<Variable>,
+    /// The value of the expression at the end of the input, if there was one
+    pub value: Option<Result<Expr, EvalError>>,
+}
+
+impl Session {
+    pub fn new(limits: EvalLimits) -> Self {
+        Self {
+            limits,
+            coder: StandardCoder::default(),
+            definitions: Vec::new(),
+        }
+    }
+
+    /// Add the `let` bindings of `source` to the session, replacing the earlier definitions with the same names,
+    /// and then evaluate the expression at the end of `source`, if any.
+    /// Returns an error report, without changing the session, if the input can not be parsed or if a definition would
+    /// depend on itself.
+    ///
+    /// Note: the locations are removed from the definitions, since they refer to the source of an earlier input,
+    /// so only errors in the expression itself are located. A catch-all pattern is rejected if the constructors of its
+    /// data type are not known, since it could only cover the constructors that are used in the same input.
+    pub fn input(&mut self, source: &str) -> Result<Response, String> {
+        let Entry { definitions, expr } =
+            parse_entry(source).map_err(|errors| parse_error_report(source, errors))?;
+
+        let previous = (self.definitions.clone(), self.coder.clone());
+        let mut defined = Vec::new();
+        for (name, expr) in definitions {
+            let expr = to_expr(expr, &mut self.coder).without_spans();
+            if let Err(error) = self.define(name.clone(), expr) {
+                (self.definitions, self.coder) = previous;
+                return Err(error);
+            }
+            defined.push(name);
+        }
+
+        let affected = self
+            .dependents(&defined)
+            .into_iter()
+            .filter(|x| !defined.contains(x))
+            .collect();
+
+        let value = expr.map(|expr| {
+            let expr = to_expr(expr, &mut self.coder);
+            eval(self.program(expr), &self.limits)
+        });
+
+        Ok(Response {
+            defined,
+            affected,
+            value,
+        })
+    }
+
+    /// Remove the definition of `name`, returns the definitions that used it, or `None` if it was not defined
+    pub fn remove(&mut self, name: &str) -> Option<Vec<Variable>> {
+        let name = Variable::from(name);
+        let index = self.definitions.iter().position(|d| d.name == name)?;
+        let affected = self.dependents(&[name]);
+        self.definitions.remove(index);
+        Some(affected)
+    }
+
+    /// The names of the definitions, in the order they were first defined
+    pub fn definitions(&self) -> impl Iterator<Item = &Variable> {
+        self.definitions.iter().map(|d| &d.name)
+    }
+
+    /// The coder used for the coded literals of all inputs
+    pub fn coder(&self) -> &StandardCoder {
+        &self.coder
+    }
+
+    fn define(&mut self, name: Variable, expr: Expr) -> Result<(), String> {
+        let free = free_variables(&expr);
+        // The definitions that the new definition uses, directly or indirectly, must not include itself
+        let mut todo: Vec<&Variable> = free.iter().collect();
+        let mut seen = HashSet::new();
+        while let Some(x) = todo.pop() {
+            if *x == name {
+                // In a program, `let n = Suc(n);` would extend the earlier `n`
+                let hint = if free.contains(&name) && self.get(&name).is_some() {
+                    format!(" or a new name to extend the earlier {name}")
+                } else {
+                    String::new()
+                };
+                return Err(format!(
+                    r#"<span class="error">The definition of {name} depends on itself, use rec for recursive definitions{hint}</span>"#
+                ));
+            }
+            if seen.insert(x) {
+                if let Some(definition) = self.get(x) {
+                    todo.extend(&definition.free);
+                }
+            }
+        }
+
+        let definition = Definition { name, expr, free };
+        match self
+            .definitions
+            .iter_mut()
+            .find(|d| d.name == definition.name)
+        {
+            Some(old) => *old = definition,
+            None => self.definitions.push(definition),
+        }
+        Ok(())
+    }
+
+    fn get(&self, name: &Variable) -> Option<&Definition> {
+        self.definitions.iter().find(|d| d.name == *name)
+    }
+
+    /// The definitions that use any of the `names`, directly or indirectly, in definition order
+    fn dependents(&self, names: &[Variable]) -> Vec<Variable> {
+        let mut used: HashSet<&Variable> = names.iter().collect();
+        let mut dependents = HashSet::new();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for definition in &self.definitions {
+                if !dependents.contains(&definition.name)
+                    && definition.free.iter().any(|x| used.contains(x))
+                {
+                    dependents.insert(definition.name.clone());
+                    used.insert(&definition.name);
+                    changed = true;
+                }
+            }
+        }
+
+        self.definitions()
+            .filter(|x| dependents.contains(*x))
+            .cloned()
+            .collect()
+    }
+
+    /// A program that binds the definitions used by `expr`, each one after the definitions it uses
+    fn program(&self, expr: Expr) -> Program<Expr> {
+        fn visit<'a>(
+            session: &'a Session,
+            free: &'a HashSet<Variable>,
+            visited: &mut HashSet<&'a Variable>,
+            order: &mut Vec<&'a Definition>,
+        ) {
+            for x in free {
+                if let Some(definition) = session.get(x) {
+                    if visited.insert(x) {
+                        visit(session, &definition.free, visited, order);
+                        order.push(definition);
+                    }
+                }
+            }
+        }
+
+        let free = free_variables(&expr);
+        let mut order = Vec::new();
+        visit(self, &free, &mut HashSet::new(), &mut order);
+
+        order
+            .into_iter()
+            .rev()
+            .fold(Program::Expr(expr), |rest, definition| {
+                Program::Let(
+                    None,
+                    definition.name.clone(),
+                    definition.expr.clone(),
+                    Box::new(rest),
+                )
+            })
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new(EvalLimits::default())
+    }
+}
@@ -0,0 +1,162 @@
+use crate::{parser::Variable, test_utils::expr, Coder, EvalError, Expr, Response, Session};
+
+fn value(session: &mut Session, source: &str) -> Result<Expr, EvalError> {
+    session.input(source).unwrap().value.unwrap()
+}
+
+fn names(names: &[&str]) -> Vec<Variable> {
+    names.iter().map(|x| Variable::from(*x)).collect()
+}
+
+const ADD: &str = r"let add = rec add = \x. \y. case x of
+    { Zero() -> y
+    ; Suc(n) -> Suc(add n y)
+    };";
+
+#[test]
+fn definitions_are_kept() {
+    let mut session = Session::default();
+    let response = session.input(ADD).unwrap();
+    assert_eq!(
+        response,
+        Response {
+            defined: names(&["add"]),
+            affected: Vec::new(),
+            value: None,
+        }
+    );
+
+    session.input(r"let one = Suc(Zero());").unwrap();
+    assert_eq!(
+        value(&mut session, "add one one"),
+        Ok(expr("Suc(Suc(Zero()))"))
+    );
+    assert_eq!(
+        session.definitions().cloned().collect::<Vec<_>>(),
+        names(&["add", "one"])
+    );
+}
+
+#[test]
+fn redefinition_affects_users() {
+    let mut session = Session::default();
+    session
+        .input(r"let x = A(); let pair = P(x, x); let wrapped = W(pair); let other = B();")
+        .unwrap();
+
+    let response = session.input(r"let x = C(); wrapped").unwrap();
+    assert_eq!(response.defined, names(&["x"]));
+    assert_eq!(response.affected, names(&["pair", "wrapped"]));
+    assert_eq!(response.value, Some(Ok(expr("W(P(C(), C()))"))));
+}
+
+#[test]
+fn later_definitions() {
+    let mut session = Session::default();
+    session.input(r"let f = \x. g x;").unwrap();
+    assert!(matches!(
+        value(&mut session, "f A()"),
+        Err(EvalError::UnboundVariable { .. })
+    ));
+
+    let response = session.input(r"let g = \x. P(x);").unwrap();
+    assert_eq!(response.affected, names(&["f"]));
+    assert_eq!(value(&mut session, "f A()"), Ok(expr("P(A())")));
+}
+
+#[test]
+fn remove() {
+    let mut session = Session::default();
+    session.input(r"let x = A(); let y = P(x);").unwrap();
+    assert_eq!(session.remove("x"), Some(names(&["y"])));
+    assert_eq!(session.remove("x"), None);
+    assert!(matches!(
+        value(&mut session, "y"),
+        Err(EvalError::UnboundVariable { .. })
+    ));
+}
+
+#[test]
+fn cyclic_definitions_are_rejected() {
+    let mut session = Session::default();
+    session.input(r"let f = \x. g x;").unwrap();
+    assert!(session.input(r"let h = A(); let g = \x. f x;").is_err());
+    // The input is rejected as a whole
+    assert_eq!(
+        session.definitions().cloned().collect::<Vec<_>>(),
+        names(&["f"])
+    );
+    // Unlike in a program, the `n` on the right is not an earlier `n`
+    session.input(r"let n = 1;").unwrap();
+    assert!(session.input(r"let n = Suc(n);").is_err());
+    assert_eq!(value(&mut session, "n"), Ok(expr("1")));
+}
+
+#[test]
+fn rejected_inputs_do_not_change_the_coder() {
+    let mut session = Session::default();
+    assert!(session
+        .input(r#"let foo = "Foo()"; let f = \x. f x;"#)
+        .is_err());
+    assert!(session.coder().defined_symbols().is_empty());
+    assert_eq!(value(&mut session, r#""Bar()""#), Ok(expr(r#""Bar()""#)));
+}
+
+#[test]
+fn shared_coder() {
+    let mut session = Session::default();
+    let first = value(&mut session, r#""Foo()""#).unwrap();
+    session.input(r#"let bar = "Bar()";"#).unwrap();
+    let second = value(&mut session, r#"P("Foo()", bar)"#).unwrap();
+    assert_eq!(
+        second,
+        Expr::Const(
+            "P".into(),
+            vec![first.clone(), value(&mut session, "bar").unwrap()]
+        )
+    );
+    assert_ne!(first, value(&mut session, "bar").unwrap());
+}
+
+#[test]
+fn parse_errors() {
+    let mut session = Session::default();
+    assert!(session.input(r"let x = ;").is_err());
+    assert_eq!(session.definitions().count(), 0);
+}
+
+#[test]
+fn recursive_groups() {
+    let mut session = Session::default();
+    let response = session
+        .input(
+            r"let rec even n = case n of { 0 -> True(); Suc(n) -> odd n }
+              and odd n = case n of { 0 -> False(); Suc(n) -> even n };",
+        )
+        .unwrap();
+    assert_eq!(response.defined, names(&["even", "odd"]));
+
+    assert_eq!(value(&mut session, "odd 7"), Ok(expr("True()")));
+}
+
+#[test]
+fn catch_all_patterns() {
+    let mut session = Session::default();
+    let is_a = r"let isA = \x. case x of { A() -> True(); _ -> False() };";
+    // A later input can use other constructors
+    let error = session.input(is_a).unwrap_err();
+    assert!(error.contains("catch-all"), "{error}");
+    assert_eq!(session.definitions().count(), 0);
+
+    session
+        .input(r"let isA = \x. case x of { A() -> True(); B() -> False(); C() -> False() };")
+        .unwrap();
+    assert_eq!(value(&mut session, "isA A()"), Ok(expr("True()")));
+    assert_eq!(value(&mut session, "isA C()"), Ok(expr("False()")));
+
+    // The data types of numbers, lists and booleans are always known
+    session
+        .input(r"let isZero = \n. case n of { 0 -> True(); _ -> False() };")
+        .unwrap();
+    assert_eq!(value(&mut session, "isZero 3"), Ok(expr("False()")));
+}
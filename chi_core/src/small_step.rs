@@ -0,0 +1,275 @@
+/// A small-step semantics for Chi, that mirrors the call-by-value big-step semantics in `eval`.
+/// Every step contracts exactly one redex, chosen in the same order as the big-step rules evaluate subterms,
+/// so the final term of a complete trace is the same value as the one computed by `eval`.
+use std::{fmt, mem};
+
+use crate::{
+    eval::{select_branch, substitute, Budget, Definitions},
+    parser::{Branch, Constructor, Span},
+    EvalError, EvalLimits,
+    Expr::{self, *},
+    Program,
+};
+
+/// The rule that was used to contract a redex
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    /// `(\x. e) v ⟶ e[x := v]`
+    Apply,
+    /// `case c(vs) of { ...; c(xs) -> e; ... } ⟶ e[xs := vs]`
+    Case,
+    /// `rec x = e ⟶ e[x := rec x = e]`
+    Rec,
+    /// `x ⟶ e`, for a top-level definition `let x = e`
+    Lookup,
+}
+
+/// A single reduction step
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub rule: Rule,
+    /// The path from the root of the term down to the redex, as a list of child indices.
+    /// The children of `Apply` are numbered function then argument, the scrutinee of a `Case` is child 0
+    /// and the arguments of a `Const` are numbered from left to right.
+    pub position: Vec<usize>,
+    /// The whole term after the step
+    pub expr: Expr,
+}
+
+/// An iterator over the reduction sequence of a term.
+/// The iterator ends when the term is a value, if the term gets stuck or a limit is exceeded the last item is the error.
+/// Note: without a step limit the sequence is infinite for non-terminating terms.
+pub struct Trace {
+    expr: Option<Expr>,
+    budget: Budget,
+    definitions: Definitions,
+}
+
+/// Trace the reduction of a program. The references to the let bindings are kept in the terms, a definition is
+/// looked up with a `Rule::Lookup` step when the reference is the next subterm to evaluate.
+/// Like in `eval`, looking up a definition does not count as a reduction for the step limit
+pub fn trace(program: Program<Expr>, limits: &EvalLimits) -> Trace {
+    let (definitions, expr) = Definitions::new(program);
+    Trace {
+        expr: Some(expr),
+        budget: Budget::new(limits),
+        definitions,
+    }
+}
+
+impl Trace {
+    pub fn new(expr: Expr, limits: &EvalLimits) -> Self {
+        Self {
+            expr: Some(expr),
+            budget: Budget::new(limits),
+            definitions: Definitions::default(),
+        }
+    }
+
+    /// The current term, `None` once the trace has ended
+    pub fn term(&self) -> Option<&Expr> {
+        self.expr.as_ref()
+    }
+}
+
+impl Iterator for Trace {
+    type Item = Result<Step, EvalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let expr = self.expr.as_mut()?;
+        let mut position = Vec::new();
+
+        match step(expr, &mut position, &mut self.budget, &self.definitions) {
+            Ok(Some(rule)) => Some(Ok(Step {
+                rule,
+                position,
+                expr: expr.clone(),
+            })),
+            // Values can not be reduced further
+            Ok(None) => {
+                self.expr = None;
+                None
+            }
+            Err(error) => {
+                self.expr = None;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// The subterms around the one that is searched for the next redex, `□` marks its position.
+/// The contexts that can get stuck also hold the location of their expression
+enum Context {
+    /// The location of the subterm
+    Located(Span),
+    /// `□ e2`
+    ApplyFun(Expr, Option<Span>),
+    /// `(\x. e) □`
+    ApplyArg(Expr),
+    /// `case □ of { branches }`
+    Case(Vec<Branch<Expr>>, Option<Span>),
+    /// `c(v1, ..., □, e1, ...)`, the remaining arguments are stored in reverse order
+    Const(Constructor, Vec<Expr>, Vec<Expr>),
+}
+
+impl Context {
+    /// Put `expr` into the hole
+    fn plug(self, expr: Expr) -> Expr {
+        match self {
+            Context::Located(span) => Located(span, Box::new(expr)),
+            Context::ApplyFun(e2, _) => Apply(Box::new(expr), Box::new(e2)),
+            Context::ApplyArg(e1) => Apply(Box::new(e1), Box::new(expr)),
+            Context::Case(branches, _) => Case(Box::new(expr), branches),
+            Context::Const(c, mut values, rest) => {
+                values.push(expr);
+                values.extend(rest.into_iter().rev());
+                Const(c, values)
+            }
+        }
+    }
+}
+
+/// Contract the next redex of `expr` in place, returns `None` if the expression already is a value.
+/// The path to the redex is pushed to `position`, locations are not part of the position and they are removed from values. The contexts of the redex are kept in an explicit stack, whose size is bounded by `max_depth`.
+/// If the term is stuck or a limit is exceeded, `expr` is left in an unspecified state
+fn step(
+    expr: &mut Expr,
+    position: &mut Vec<usize>,
+    budget: &mut Budget,
+    definitions: &Definitions,
+) -> Result<Option<Rule>, EvalError> {
+    let mut stack = Vec::new();
+    let push = |stack: &mut Vec<Context>, context| {
+        budget.check_depth(stack.len())?;
+        stack.push(context);
+        Ok::<_, EvalError>(())
+    };
+
+    let mut current = expr.take();
+    let mut location = None;
+    let (rule, contracted) = 'search: loop {
+        // Go down to the leftmost subterm that is not a value
+        let mut value = loop {
+            let span = location.take();
+            match &mut current {
+                Located(s, e) => {
+                    push(&mut stack, Context::Located(s.clone()))?;
+                    location = Some(s.clone());
+                    current = e.take();
+                }
+                Apply(e1, e2) => {
+                    push(&mut stack, Context::ApplyFun(e2.take(), span))?;
+                    position.push(0);
+                    current = e1.take();
+                }
+                Case(e, branches) => {
+                    push(&mut stack, Context::Case(mem::take(branches), span))?;
+                    position.push(0);
+                    current = e.take();
+                }
+                Const(c, es) if !es.is_empty() => {
+                    es.reverse();
+                    let e = es.pop().unwrap();
+                    push(
+                        &mut stack,
+                        Context::Const(c.clone(), Vec::new(), mem::take(es)),
+                    )?;
+                    position.push(0);
+                    current = e;
+                }
+                Rec(x, e) => {
+                    let rec = Rec(x.clone(), e.clone());
+                    break 'search (Rule::Rec, substitute(x, &rec, e.take()));
+                }
+                Var(x) => {
+                    return Err(EvalError::UnboundVariable {
+                        variable: x.clone(),
+                        expr: Var(x.clone()),
+                        span,
+                    })
+                }
+                Global(_, index) => break 'search (Rule::Lookup, definitions.get(*index).clone()),
+                Lambda(..) | Const(..) | Nat(_) => break current,
+            }
+        };
+
+        // Pass the value up until a context has another subterm to search, or the value completes a redex
+        current = loop {
+            let Some(context) = stack.pop() else {
+                *expr = value;
+                return Ok(None);
+            };
+            match context {
+                // Locations are removed from values
+                Context::Located(_) => {}
+                Context::ApplyFun(e2, span) => {
+                    position.pop();
+                    if !matches!(value, Lambda(..)) {
+                        return Err(EvalError::NotALambda {
+                            value: value.clone(),
+                            expr: Apply(Box::new(value), Box::new(e2)),
+                            span,
+                        });
+                    }
+                    push(&mut stack, Context::ApplyArg(value))?;
+                    position.push(1);
+                    break e2;
+                }
+                Context::ApplyArg(mut e1) => {
+                    position.pop();
+                    let Lambda(x, e) = &mut e1 else {
+                        unreachable!()
+                    };
+                    break 'search (Rule::Apply, substitute(x, &value, e.take()));
+                }
+                Context::Case(branches, span) => {
+                    position.pop();
+                    let value = value.unfold();
+                    let Const(constructor_name, es) = &value else {
+                        return Err(EvalError::NotAConstructor {
+                            value: value.clone(),
+                            expr: Case(Box::new(value), branches),
+                            span,
+                        });
+                    };
+                    let e = select_branch(constructor_name, es, &branches, span.as_ref())?;
+                    break 'search (Rule::Case, e);
+                }
+                Context::Const(c, mut values, mut rest) => {
+                    position.pop();
+                    values.push(value);
+                    match rest.pop() {
+                        Some(e) => {
+                            position.push(values.len());
+                            push(&mut stack, Context::Const(c, values, rest))?;
+                            break e;
+                        }
+                        None => value = Const(c, values),
+                    }
+                }
+            }
+        };
+    };
+
+    if rule != Rule::Lookup {
+        budget.step()?;
+    }
+    *expr = stack
+        .into_iter()
+        .rev()
+        .fold(contracted, |expr, context| context.plug(expr));
+    budget.check_size(expr)?;
+    Ok(Some(rule))
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::Apply => write!(f, "apply"),
+            Rule::Case => write!(f, "case"),
+            Rule::Rec => write!(f, "rec"),
+            Rule::Lookup => write!(f, "lookup"),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{
+    eval, test_utils::program, trace, EvalError, EvalLimits, Limit, Printer, Program, Rule, Run,
+    Step,
+};
+
+#[test]
+fn value_has_no_steps() {
+    assert_eq!(
+        trace(program(r"C(\x. x)"), &EvalLimits::default()).count(),
+        0
+    );
+}
+
+#[test]
+fn single_application() {
+    let steps: Vec<_> = trace(program(r"(\x. x) C()"), &EvalLimits::default()).collect();
+    assert_eq!(steps.len(), 1);
+    let Step {
+        rule,
+        position,
+        expr,
+    } = steps[0].as_ref().unwrap();
+    assert_eq!(*rule, Rule::Apply);
+    assert!(position.is_empty());
+    assert_eq!(Program::Expr(expr.clone()), program("C()"));
+}
+
+#[test]
+fn redex_position() {
+    let rules: Vec<_> = trace(
+        program(r"C(D(), case (\x. x) E() of { E() -> F() })"),
+        &EvalLimits::default(),
+    )
+    .map(|step| {
+        let step = step.unwrap();
+        (step.rule, step.position)
+    })
+    .collect();
+    assert_eq!(
+        rules,
+        vec![(Rule::Apply, vec![1, 0]), (Rule::Case, vec![1])]
+    );
+}
+
+#[test]
+fn rec_unfolding() {
+    let steps: Vec<_> = trace(program(r"(rec f = \x. x) C()"), &EvalLimits::default()).collect();
+    let rules: Vec<_> = steps.into_iter().map(|step| step.unwrap().rule).collect();
+    assert_eq!(rules, vec![Rule::Rec, Rule::Apply]);
+}
+
+#[test]
+fn stuck_term() {
+    let steps: Vec<_> = trace(program(r"(\x. x) (C() D())"), &EvalLimits::default()).collect();
+    assert_eq!(steps.len(), 1);
+    assert!(steps[0].is_err());
+}
+
+#[test]
+fn non_terminating_is_infinite() {
+    assert_eq!(
+        trace(program("rec x = x"), &EvalLimits::default())
+            .take(100)
+            .count(),
+        100
+    );
+}
+
+#[test]
+fn limits() {
+    let limits = EvalLimits {
+        max_steps: Some(10),
+        ..EvalLimits::default()
+    };
+    let steps: Vec<_> = trace(program("rec x = x"), &limits).collect();
+    assert_eq!(steps.len(), 11);
+    assert_eq!(steps[10], Err(EvalError::LimitExceeded(Limit::Steps(10))));
+
+    // The redex gets more deeply nested in every step, which is bounded by the depth limit instead of the native stack
+    let limits = EvalLimits {
+        max_depth: Some(1000),
+        ..EvalLimits::default()
+    };
+    let error = trace(program("rec x = S(x)"), &limits).last().unwrap();
+    assert_eq!(error, Err(EvalError::LimitExceeded(Limit::Depth(1000))));
+}
+
+#[test]
+fn printed_trace() {
+    let output = |source| {
+        Run::new(source, Printer::Trace, &EvalLimits::default())
+            .unwrap()
+            .finish()
+            .map(|(output, ..)| output)
+    };
+    assert_eq!(
+        output(r"(\x. x) C()"),
+        Ok("(\\x. x) C()\n⟶ apply at []\nC()".to_string())
+    );
+
+    // Every step prints the whole term, which keeps growing
+    let error = output("rec x = S(x)").unwrap_err();
+    assert!(
+        error.ends_with("The trace is too long to print, it was stopped after 1179 steps</span>")
+    );
+}
+
+#[test]
+fn same_value_as_big_step() {
+    let programs = [
+        r#"
+        let add = rec add = \x. \y. case x of
+        { Zero() -> y
+        ; Suc(n) -> Suc(add n y)
+        };
+        let two = Suc(Suc(Zero()));
+        Pair(add two two, \x. add x)
+        "#,
+        // The references in the final value are looked up too
+        "let x = D(); C(x)",
+        "let x = 200000; x",
+    ];
+    for src in programs {
+        let last = trace(program(src), &EvalLimits::default())
+            .last()
+            .unwrap()
+            .unwrap()
+            .expr;
+        assert_eq!(last, eval(program(src), &EvalLimits::default()).unwrap());
+    }
+}
+
+#[test]
+fn lookup_steps() {
+    let steps: Vec<_> = trace(
+        program(r"let id = \x. x; let c = C(); id c"),
+        &EvalLimits {
+            max_steps: Some(1),
+            ..EvalLimits::default()
+        },
+    )
+    .map(|step| {
+        let step = step.unwrap();
+        (step.rule, step.position)
+    })
+    .collect();
+    // Looking up a definition is not a reduction
+    assert_eq!(
+        steps,
+        vec![
+            (Rule::Lookup, vec![0]),
+            (Rule::Lookup, vec![1]),
+            (Rule::Apply, vec![])
+        ]
+    );
+}
+
+#[test]
+fn stuck_location() {
+    let source = r"(\x. case x of { A() -> x }) B()";
+    let error = trace(program(source), &EvalLimits::default())
+        .last()
+        .unwrap()
+        .expect_err("the case expression gets stuck");
+    assert_eq!(
+        &source[error.span().unwrap().clone()],
+        "case x of { A() -> x }"
+    );
+    assert_eq!(Err(error), eval(program(source), &EvalLimits::default()));
+}
+
+#[test]
+fn layered_definitions() {
+    // Substituting the definitions would create 2^40 copies of `twice0`
+    let mut src = r"let twice0 = \f. \x. f (f x);".to_string();
+    for i in 1..40 {
+        src += &format!(
+            r"let twice{i} = \f. \x. twice{} f (twice{} f x);",
+            i - 1,
+            i - 1
+        );
+    }
+    src += r"twice1 (\x. S(x)) Z()";
+    let last = trace(program(&src), &EvalLimits::default())
+        .last()
+        .unwrap()
+        .unwrap();
+    assert_eq!(Program::Expr(last.expr), program("S(S(S(S(Z()))))"));
+}
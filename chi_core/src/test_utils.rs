@@ -0,0 +1,15 @@
+/// Fixtures shared by the tests of the modules
+use crate::{parse, replace_coded_literals, Expr, Program, StandardCoder};
+
+/// Parse a program and replace its coded literals
+pub(crate) fn program(source: &str) -> Program<Expr> {
+    replace_coded_literals(parse(source).unwrap(), &mut StandardCoder::default())
+}
+
+/// Parse a program that is a single expression
+pub(crate) fn expr(source: &str) -> Expr {
+    let Program::Expr(expr) = program(source) else {
+        panic!("expected an expression");
+    };
+    expr
+}
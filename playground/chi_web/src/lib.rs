@@ -1,31 +1,124 @@
 mod utils;
 
-use chi_core::{pretty, Coder, Printer};
+use chi_core::{pretty, Coder, EvalLimits, EvalStats, Expr, MemoryResolver, Printer};
 use std::convert::TryInto;
 use wasm_bindgen::prelude::*;
 
+/// The files that a program can import, by their paths
 #[wasm_bindgen]
-pub fn run(source: &str, printer: String) -> Result<String, String> {
-    utils::set_panic_hook();
-    let printer = printer.as_str().try_into().unwrap();
-    match chi_core::run(source, printer) {
-        Ok((output, coder)) => {
-            if printer == Printer::Concrete {
-                let defined_symbols: String = coder
-                    .defined_symbols()
-                    .into_iter()
-                    .map(|(symbol, expr)| {
-                        format!("<li>⌜<u>{symbol}</u>⌝ = {}", pretty::concrete(&expr))
-                    })
-                    .collect();
-
-                Ok(format!(
-                    r#"<ul class="symbols">{defined_symbols}</ul>{output}"#
-                ))
-            } else {
-                Ok(output)
-            }
+#[derive(Default)]
+pub struct Files(MemoryResolver);
+
+#[wasm_bindgen]
+impl Files {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Files {
+        Files::default()
+    }
+
+    pub fn add(&mut self, path: String, source: String) {
+        self.0.insert(path, source);
+    }
+}
+
+/// Run a program, any limit that is not given falls back to the default of `EvalLimits`
+#[wasm_bindgen]
+pub fn run(
+    source: &str,
+    printer: String,
+    max_steps: Option<u32>,
+    max_depth: Option<u32>,
+    max_size: Option<u32>,
+    files: &Files,
+) -> Result<String, String> {
+    let mut evaluation = Evaluation::new(source, printer, max_steps, max_depth, max_size, files)?;
+    while !evaluation.step(u32::MAX) {}
+    evaluation.output()
+}
+
+/// A program that is evaluated a few steps at a time, so that the browser does not freeze on slow programs.
+/// To cancel the evaluation, just stop calling `step` and free it.
+#[wasm_bindgen]
+pub struct Evaluation {
+    run: chi_core::Run,
+    printer: Printer,
+}
+
+#[wasm_bindgen]
+impl Evaluation {
+    /// Parse the program and start evaluating it, any limit that is not given falls back to the default of `EvalLimits`
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        source: &str,
+        printer: String,
+        max_steps: Option<u32>,
+        max_depth: Option<u32>,
+        max_size: Option<u32>,
+        files: &Files,
+    ) -> Result<Evaluation, String> {
+        utils::set_panic_hook();
+        let printer = printer.as_str().try_into().unwrap();
+        let default = EvalLimits::default();
+        let limits = EvalLimits {
+            max_steps: max_steps.map(u64::from).or(default.max_steps),
+            max_depth: max_depth.map(|n| n as usize).or(default.max_depth),
+            max_size: max_size.map(|n| n as usize).or(default.max_size),
+            // Instant is not supported in the browser
+            timeout: None,
+        };
+        let run = chi_core::Run::with_resolver(source, printer, &limits, &files.0)?;
+        Ok(Evaluation { run, printer })
+    }
+
+    /// Perform at most `n` steps, returns true once the evaluation is finished
+    pub fn step(&mut self, n: u32) -> bool {
+        self.run.step(u64::from(n))
+    }
+
+    /// The printed result, this is an error if the evaluation failed or is not finished yet.
+    /// The warnings about the program come first
+    pub fn output(&self) -> Result<String, String> {
+        let warnings = self.run.warning_report();
+        match self.run.output() {
+            None => Err("The evaluation is not finished".to_string()),
+            Some(Err(error)) => Err(format!("{warnings}{error}")),
+            Some(Ok(output)) => Ok(format!(
+                "{warnings}{}",
+                self.format(output, self.run.stats())
+            )),
+        }
+    }
+
+    /// The number of reductions so far
+    pub fn reductions(&self) -> f64 {
+        self.run.stats().map_or(0, EvalStats::reductions) as f64
+    }
+}
+
+impl Evaluation {
+    fn format(&self, output: &str, stats: Option<&EvalStats>) -> String {
+        // The cost report goes below the value
+        let output = match stats {
+            Some(stats) => format!(r#"{output}<hr><div class="stats">{stats}</div>"#),
+            None => output.to_string(),
+        };
+
+        if let Printer::Concrete | Printer::Sugared = self.printer {
+            let print: fn(&Expr) -> String = match self.printer {
+                Printer::Sugared => pretty::sugared,
+                _ => |expr| pretty::concrete(expr).unwrap_or_else(|error| error.to_string()),
+            };
+            let defined_symbols: String = self
+                .run
+                .coder()
+                .defined_symbols()
+                .into_iter()
+                .map(|(symbol, expr)| format!("<li>⌜<u>{symbol}</u>⌝ = {}", print(&expr)))
+                .collect();
+
+            format!(r#"<ul class="symbols">{defined_symbols}</ul>{output}"#)
+        } else {
+            output
         }
-        Err(error) => Err(error),
     }
 }
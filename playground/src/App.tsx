@@ -1,7 +1,7 @@
 /* eslint-disable no-useless-escape */
 import { Editor, OnChange, OnMount } from "@monaco-editor/react";
-import {useState, useEffect, useMemo, useRef} from "react";
-import init, {run} from "chi_web";
+import {useState, useEffect, useMemo, useRef, useCallback} from "react";
+import init, {Evaluation, Files} from "chi_web";
 import styled from "styled-components";
 import Convert from "ansi-to-html";
 import readGist from "./gist";
@@ -86,6 +86,10 @@ const Output = styled.div`
   & .symbols {
     opacity: 0.7;
   }
+
+  & .stats {
+    opacity: 0.7;
+  }
 `;
 
 const Options = styled.div`
@@ -102,10 +106,25 @@ const Options = styled.div`
   }
 `
 
+// Evaluation limits passed to the interpreter, undefined means the default limit
+const LIMITS = {
+  maxSteps: 1_000_000,
+  maxDepth: undefined,
+  maxSize: 1_000_000,
+};
+
+// How many steps to evaluate before giving control back to the browser
+const STEPS_PER_CHUNK = 20_000;
+
 enum Printer {
   Concrete = "concrete", 
+  Sugared = "sugared",
   Abstract = "abstract",
-  Debug = "debug"
+  Debug = "debug",
+  Trace = "trace",
+  Derivation = "derivation",
+  Normalize = "normalize",
+  Notebook = "notebook"
 }
 
 type PrinterOptionsProps = {
@@ -126,6 +145,17 @@ const PrinterOptions= ({onChange, value}: PrinterOptionsProps) => {
         />
         <label htmlFor="concrete">Concrete</label>
     </div>
+    <div>
+      <input
+        type="radio"
+        name="Sugared"
+        value={Printer.Sugared as string}
+        id="sugared"
+        checked={value === Printer.Sugared}
+        onChange={onChange}
+      />
+      <label htmlFor="sugared">Sugared</label>
+    </div>
 
     <div>
       <input
@@ -149,6 +179,50 @@ const PrinterOptions= ({onChange, value}: PrinterOptionsProps) => {
       />
       <label htmlFor="debug">Debug</label>
     </div>
+    <div>
+      <input
+        type="radio"
+        name="Trace"
+        value={Printer.Trace as string}
+        id="trace"
+        checked={value === Printer.Trace}
+        onChange={onChange}
+      />
+      <label htmlFor="trace">Trace</label>
+    </div>
+    <div>
+      <input
+        type="radio"
+        name="Derivation"
+        value={Printer.Derivation as string}
+        id="derivation"
+        checked={value === Printer.Derivation}
+        onChange={onChange}
+      />
+      <label htmlFor="derivation">Derivation</label>
+    </div>
+    <div>
+      <input
+        type="radio"
+        name="Normalize"
+        value={Printer.Normalize as string}
+        id="normalize"
+        checked={value === Printer.Normalize}
+        onChange={onChange}
+      />
+      <label htmlFor="normalize">Normalize</label>
+    </div>
+    <div>
+      <input
+        type="radio"
+        name="Notebook"
+        value={Printer.Notebook as string}
+        id="notebook"
+        checked={value === Printer.Notebook}
+        onChange={onChange}
+      />
+      <label htmlFor="notebook">Notebook</label>
+    </div>
   </Options>
 }
 
@@ -161,6 +235,10 @@ function App() {
     const [wasmLoaded, setWasmLoaded] = useState(false);
     const [editorLoaded, setEditorLoaded] = useState(false);
     const [printer, setPrinter] = useState(Printer.Concrete);
+    // The evaluation that is currently running, if any
+    const evaluationRef = useRef<{evaluation: Evaluation, timeout: number} | null>(null);
+    // The other files of a Gist, which the program can import
+    const filesRef = useRef<Record<string, string>>({});
 
   useEffect(() => {
     // Load the wasm module
@@ -182,12 +260,19 @@ function App() {
         "of",
         "rec",
         "let",
+        "in",
+        "where",
+        "and",
+        "data",
+        "import",
+        "as",
       ],
     
       operators: [
         "->",
         "=",
         "\\",
+        "|",
       ],
     
       // we include these common regular expressions
@@ -206,7 +291,8 @@ function App() {
           { include: "@whitespace" },
     
           // delimiters and operators
-          [/[{}()]/, "@brackets"],
+          [/\d+/, "number"],
+          [/[{}()\[\]]/, "@brackets"],
           [/[<>](?!@symbols)/, "@brackets"],
           [/@symbols/, { cases: { "@operators": "operator", "@default": "" } }],
     
@@ -215,8 +301,17 @@ function App() {
     
         whitespace: [
           [/(^--.*$)/, "comment"],
+          [/\{-/, "comment", "@comment"],
           [/[ \t\r\n]+/, "white"],
         ],
+
+        // block comments can be nested
+        comment: [
+          [/[^{-]+/, "comment"],
+          [/\{-/, "comment", "@push"],
+          [/-\}/, "comment", "@pop"],
+          [/[{-]/, "comment"],
+        ],
       },
     });
 
@@ -225,7 +320,10 @@ function App() {
     const params = new URLSearchParams(window.location.search);
     const gistId = params.get("gist");
     if (gistId) {
-      const text = readGist(gistId).then(text => editor.setValue(text));
+      readGist(gistId).then(({main, files}) => {
+        filesRef.current = files;
+        editor.setValue(main);
+      });
     } else {
       editor.setValue(localStorage.getItem("content") ?? WELCOME_TEXT);
     }
@@ -237,28 +335,62 @@ function App() {
     setPrinter(event.target.value as Printer);
   }
 
-  useEffect(() => {
-    if (!wasmLoaded || !editorLoaded || editorRef.current === null) {
-      return;
+  // Start evaluating the text in chunks, cancelling the previous evaluation
+  const evaluate = useCallback((text: string, printer: Printer) => {
+    const previous = evaluationRef.current;
+    if (previous !== null) {
+      window.clearTimeout(previous.timeout);
+      previous.evaluation.free();
+      evaluationRef.current = null;
     }
 
-    const text = editorRef.current.getValue();
-    
-    try {
-      const result = run(text ?? " ", printer as string);
-      setOutput(result);
-    } catch (error) {
-      setOutput(convert.toHtml((error as string) ?? ""));
+    const files = new Files();
+    for (const [path, source] of Object.entries(filesRef.current)) {
+      files.add(path, source);
     }
-  }, [printer, convert, wasmLoaded, editorLoaded]);
 
-  const editorChange: OnChange = (value, event) => {
+    let evaluation: Evaluation;
     try {
-      const result = run(value ?? " ", printer as string);
-      setOutput(result);
+      evaluation = new Evaluation(text, printer as string, LIMITS.maxSteps, LIMITS.maxDepth, LIMITS.maxSize, files);
     } catch (error) {
       setOutput(convert.toHtml((error as string) ?? ""));
+      return;
+    } finally {
+      // The files are only read when the evaluation is created
+      files.free();
+    }
+
+    const advance = () => {
+      if (!evaluation.step(STEPS_PER_CHUNK)) {
+        setOutput(`Evaluating... (${evaluation.reductions()} reductions so far)`);
+        evaluationRef.current = {evaluation, timeout: window.setTimeout(advance, 0)};
+        return;
+      }
+
+      try {
+        // The notebook printer includes the error reports of the bindings that failed
+        setOutput(convert.toHtml(evaluation.output()));
+      } catch (error) {
+        setOutput(convert.toHtml((error as string) ?? ""));
+      }
+      evaluation.free();
+      evaluationRef.current = null;
+    };
+
+    evaluationRef.current = {evaluation, timeout: window.setTimeout(advance, 0)};
+  }, [convert]);
+
+  useEffect(() => {
+    if (!wasmLoaded || !editorLoaded || editorRef.current === null) {
+      return;
     }
+
+    const text = editorRef.current.getValue();
+    evaluate(text ?? " ", printer);
+  }, [printer, evaluate, wasmLoaded, editorLoaded]);
+
+  const editorChange: OnChange = (value, event) => {
+    evaluate(value ?? " ", printer);
     
       localStorage.setItem("content", value ?? "");
   };
@@ -12,32 +12,51 @@ type GistFile = {
   content: string;
 };
 
+// The program to show in the editor, and the other files of the Gist, which it can import
+export type Gist = {
+  main: string;
+  files: Record<string, string>;
+};
+
 // I'm borrowing some of this from the modmark-org/modmark repo
 export default async function readGist(
   id: string,
-): Promise<string> {
+): Promise<Gist> {
+  const message = (main: string) => ({ main, files: {} });
+
   let api_result: GistResp;
   try {
     const res = await fetch("https://api.github.com/gists/" + id);
     if (res.status !== 200) {
-      return `-- Error fetching Gist with id ${id}: status code ${res.status}`;
+      return message(`-- Error fetching Gist with id ${id}: status code ${res.status}`);
     }
     const content = await res.text();
     api_result = JSON.parse(content) as GistResp;
   } catch (e) {
-    return `-- Error loading Gist: ${e}`;
+    return message(`-- Error loading Gist: ${e}`);
   }
 
   const entries = Object.entries(api_result.files);
 
   if (entries.length === 0) {
-    return "-- No files found in Gist";
+    return message("-- No files found in Gist");
   }
 
   if (entries.length === 1) {
     const [_, file] = entries[0];
-    return file.content;
-  } else {
-    return "-- Gist contains multiple files! I can't choose which one to use.";
+    return message(file.content);
+  }
+
+  // With multiple files, main.chi is the program and the others can be imported
+  const main = api_result.files["main.chi"];
+  if (main === undefined) {
+    return message("-- Gist contains multiple files, but none of them is main.chi");
+  }
+  const files: Record<string, string> = {};
+  for (const [name, file] of entries) {
+    if (name !== "main.chi") {
+      files[name] = file.content;
+    }
   }
+  return { main: main.content, files };
 }
@@ -3,17 +3,27 @@ const WELCOME_TEXT = `
 
 -- Small example program
 -- Note: meta variables and assignments
--- (let name = <some expr>;) are supported 
-let add = rec add = \\x. \\y. case x of
+-- (let name = <some expr>;) are supported,
+-- let rec f x y = e; is short for let f = rec f = \\x. \\y. e;
+-- and let rec f x = e1 and g y = e2; defines mutually recursive functions
+
+-- data declarations are optional, once there is one the constructors
+-- and case branches are checked against them
+data Nat = Zero() | Suc(Nat);
+data Bool = False() | True();
+
+let rec add x y = case x of
 { Zero() -> y
 ; Suc(n) -> Suc(add n y)
 };
 
 let zero = Zero();
 
-let three = Suc(Suc(Suc(Zero())));
+-- 3 is short for Suc(Suc(Suc(Zero()))),
+-- and [x, y] for Cons(x, Cons(y, Nil()))
+let three = 3;
 
-let equals = rec equals = \\m. \\n. case m of
+let rec equals m n = case m of
 { Zero() -> case n of
   { Zero() -> True()
   ; Suc(n) -> False()
@@ -24,17 +34,26 @@ let equals = rec equals = \\m. \\n. case m of
   }
 };
 
-equals (add zero three) three
+-- patterns can be nested, and _ (or a variable) matches
+-- every value that the branches above do not match
+let rec half n = case n of
+{ Suc(Suc(n)) -> Suc(half n)
+; _ -> 0
+};
+
+equals (add zero three) (half 6)
 -- the value of the last expression is printed in the right window
 -- (each time the contents of the editor changes).
 
 
 -- if you want to share your program you can create
 -- a Github Gist and open it by adding ?gist=<gist id> to the URL.
+-- A Gist with several files opens main.chi, which can use the definitions
+-- of the other files with import "nat.chi"; (or import "nat.chi" as Nat;
+-- for Nat.add, or import "nat.chi" (add, mul); for only some of them).
 
 
 --- TODOS ---
--- Support some more convenient syntax: multiline comments etc.
 -- Improve runtime error messages (programs that don't evaluate to a value)
 -- Improve the abstract syntax pretty printer
 `;